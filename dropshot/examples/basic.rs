@@ -17,7 +17,6 @@ use dropshot::TypedBody;
 use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
-use std::any::Any;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -81,20 +80,10 @@ impl ExampleContext {
     /**
      * Return a new ExampleContext.
      */
-    pub fn new() -> Arc<ExampleContext> {
-        Arc::new(ExampleContext {
+    pub fn new() -> ExampleContext {
+        ExampleContext {
             counter: AtomicU64::new(0),
-        })
-    }
-
-    /**
-     * Given `rqctx` (which is provided by Dropshot to all HTTP handler
-     * functions), return our application-specific context.
-     */
-    pub fn from_rqctx(rqctx: &Arc<RequestContext>) -> Arc<ExampleContext> {
-        let ctx: Arc<dyn Any + Send + Sync + 'static> =
-            Arc::clone(&rqctx.server.private);
-        ctx.downcast::<ExampleContext>().expect("wrong type for private data")
+        }
     }
 }
 
@@ -120,9 +109,9 @@ struct CounterValue {
     path = "/counter",
 }]
 async fn example_api_get_counter(
-    rqctx: Arc<RequestContext>,
+    rqctx: Arc<RequestContext<ExampleContext>>,
 ) -> Result<HttpResponseOk<CounterValue>, HttpError> {
-    let api_context = ExampleContext::from_rqctx(&rqctx);
+    let api_context = rqctx.context();
 
     Ok(HttpResponseOk(CounterValue {
         counter: api_context.counter.load(Ordering::SeqCst),
@@ -138,10 +127,10 @@ async fn example_api_get_counter(
     path = "/counter",
 }]
 async fn example_api_put_counter(
-    rqctx: Arc<RequestContext>,
+    rqctx: Arc<RequestContext<ExampleContext>>,
     update: TypedBody<CounterValue>,
 ) -> Result<HttpResponseUpdatedNoContent, HttpError> {
-    let api_context = ExampleContext::from_rqctx(&rqctx);
+    let api_context = rqctx.context();
     let updated_value = update.into_inner();
 
     if updated_value.counter == 10 {
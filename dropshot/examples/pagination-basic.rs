@@ -77,12 +77,12 @@ struct ProjectPage {
     path = "/projects"
 }]
 async fn example_list_projects(
-    rqctx: Arc<RequestContext>,
+    rqctx: Arc<RequestContext<BTreeMap<String, Project>>>,
     query: Query<PaginationParams<EmptyScanParams, ProjectPage>>,
 ) -> Result<HttpResponseOk<ResultsPage<Project>>, HttpError> {
     let pag_params = query.into_inner();
     let limit = rqctx.page_limit(&pag_params)?.get();
-    let tree = rqctx_to_tree(rqctx);
+    let tree = rqctx.context();
     let projects = match &pag_params.page {
         WhichPage::First(..) => {
             /* Return a list of the first "limit" projects. */
@@ -111,11 +111,6 @@ async fn example_list_projects(
     )?))
 }
 
-fn rqctx_to_tree(rqctx: Arc<RequestContext>) -> Arc<BTreeMap<String, Project>> {
-    let c = Arc::clone(&rqctx.server.private);
-    c.downcast::<BTreeMap<String, Project>>().unwrap()
-}
-
 #[tokio::main]
 async fn main() -> Result<(), String> {
     let port = std::env::args()
@@ -140,10 +135,10 @@ async fn main() -> Result<(), String> {
     /*
      * Run the Dropshot server.
      */
-    let ctx = Arc::new(tree);
     let config_dropshot = ConfigDropshot {
         bind_address: SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
         request_body_max_bytes: 1024,
+        ..Default::default()
     };
     let config_logging = ConfigLogging::StderrTerminal {
         level: ConfigLoggingLevel::Debug,
@@ -153,7 +148,7 @@ async fn main() -> Result<(), String> {
         .map_err(|error| format!("failed to create logger: {}", error))?;
     let mut api = ApiDescription::new();
     api.register(example_list_projects).unwrap();
-    let mut server = HttpServer::new(&config_dropshot, api, ctx, &log)
+    let mut server = HttpServer::new(&config_dropshot, api, tree, &log)
         .map_err(|error| format!("failed to create server: {}", error))?;
     let server_task = server.run();
     server.wait_for_shutdown(server_task).await
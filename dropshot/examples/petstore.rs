@@ -73,7 +73,7 @@ struct PathParams {
 }]
 /// Get the pet with the specified ID
 async fn get_pet_by_id(
-    rqctx: Arc<RequestContext>,
+    rqctx: Arc<RequestContext<()>>,
     path_params: Path<PathParams>,
 ) -> Result<HttpResponseOk<Pet>, HttpError> {
     let pet = Pet {
@@ -96,7 +96,7 @@ async fn get_pet_by_id(
 }]
 /// Add a new pet to the store
 async fn update_pet_with_form(
-    rqctx: Arc<RequestContext>,
+    rqctx: Arc<RequestContext<()>>,
     body: TypedBody<Pet>,
 ) -> Result<HttpResponseOk<()>, HttpError> {
     unimplemented!()
@@ -127,7 +127,7 @@ struct FindByTagsPageSelector {
 }]
 /// Find pets by tags
 async fn find_pets_by_tags(
-    rqctx: Arc<RequestContext>,
+    rqctx: Arc<RequestContext<()>>,
     query: Query<
         PaginationParams<FindByTagsScanParams, FindByTagsPageSelector>,
     >,
@@ -7,9 +7,12 @@ use crate::handler::HttpHandlerFunc;
 use crate::handler::HttpResponse;
 use crate::handler::HttpRouteHandler;
 use crate::handler::RouteHandler;
+use crate::middleware::Middleware;
+use crate::router::openapi_path;
 use crate::router::path_to_segments;
 use crate::router::HttpRouter;
 use crate::router::PathSegment;
+use crate::server::ServerContext;
 use crate::Extractor;
 use crate::CONTENT_TYPE_JSON;
 use crate::CONTENT_TYPE_OCTET_STREAM;
@@ -17,6 +20,8 @@ use crate::CONTENT_TYPE_OCTET_STREAM;
 use http::Method;
 use http::StatusCode;
 use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
 
 /**
  * ApiEndpoint represents a single API endpoint associated with an
@@ -25,18 +30,43 @@ use std::collections::HashSet;
  * inferred from function parameter types and doc comments (respectively).
  */
 #[derive(Debug)]
-pub struct ApiEndpoint {
+pub struct ApiEndpoint<Context: ServerContext> {
     pub operation_id: String,
-    pub handler: Box<dyn RouteHandler>,
+    pub handler: Box<dyn RouteHandler<Context>>,
     pub method: Method,
     pub path: String,
     pub parameters: Vec<ApiEndpointParameter>,
     pub response: ApiEndpointResponse,
     pub description: Option<String>,
     pub tags: Vec<String>,
+    /**
+     * how long the handler may run before the request fails, overriding the
+     * server's `handler_timeout_ms`
+     */
+    pub timeout: Option<Duration>,
+    /**
+     * maximum number of requests to this endpoint that may be handled at once
+     * (see the server's `max_concurrent_requests`)
+     */
+    pub concurrency_limit: Option<usize>,
+    /**
+     * maximum number of bytes allowed in the request body, overriding the
+     * server's `request_body_max_bytes`
+     */
+    pub request_body_max_bytes: Option<usize>,
+    /**
+     * middleware that runs around the handler, outermost first (see
+     * [`Middleware`])
+     */
+    pub middleware: Vec<Arc<dyn Middleware<Context>>>,
+    /**
+     * whether path variables may contain a "/", which clients must send
+     * percent-encoded as "%2F" (otherwise, such requests fail with a 400)
+     */
+    pub allow_encoded_slashes: bool,
 }
 
-impl<'a> ApiEndpoint {
+impl<'a, Context: ServerContext> ApiEndpoint<Context> {
     pub fn new<HandlerType, FuncParams, ResponseType>(
         operation_id: String,
         handler: HandlerType,
@@ -44,7 +74,7 @@ impl<'a> ApiEndpoint {
         path: &'a str,
     ) -> Self
     where
-        HandlerType: HttpHandlerFunc<FuncParams, ResponseType>,
+        HandlerType: HttpHandlerFunc<Context, FuncParams, ResponseType>,
         FuncParams: Extractor + 'static,
         ResponseType: HttpResponse + Send + Sync + 'static,
     {
@@ -57,6 +87,11 @@ impl<'a> ApiEndpoint {
             response: ResponseType::metadata(),
             description: None,
             tags: vec![],
+            timeout: None,
+            concurrency_limit: None,
+            request_body_max_bytes: None,
+            middleware: vec![],
+            allow_encoded_slashes: false,
         }
     }
 
@@ -69,12 +104,84 @@ impl<'a> ApiEndpoint {
         self.tags.push(tag.to_string());
         self
     }
+
+    /**
+     * Limits how long this endpoint's handler (along with any middleware
+     * around it) may run, overriding the server's `handler_timeout_ms`.  When
+     * the limit expires, the handler's future is dropped, so it stops at
+     * whatever `await` it was waiting on, and the request fails with a 503
+     * "Service Unavailable" error.  This is what the `timeout_ms` endpoint
+     * attribute sets.
+     */
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout.replace(timeout);
+        self
+    }
+
+    /**
+     * Limits how many requests to this endpoint may be handled at once.  This
+     * applies in addition to the server's `max_concurrent_requests`: a request
+     * must first get one of the server's slots and then one of the endpoint's.
+     * A request that can't get a slot waits for up to the server's
+     * `concurrency_queue_timeout_ms` (which applies to both limits) and then
+     * fails with a 503 "Service Unavailable" error whose "Retry-After" header
+     * suggests when the client should try again.  This is what the
+     * `concurrency_limit` endpoint attribute sets.  The limit must be at least
+     * 1, or registering the endpoint fails.
+     */
+    pub fn concurrency_limit(mut self, limit: usize) -> Self {
+        self.concurrency_limit.replace(limit);
+        self
+    }
+
+    /**
+     * Sets the maximum size, in bytes, of request bodies that this endpoint
+     * accepts, overriding the server's `request_body_max_bytes`.  The limit
+     * applies however the handler reads the body: `TypedBody` and
+     * `UntypedBody` fail with a 400 error if the body is larger, and so does
+     * `StreamingBody` (either up front, if the request's "Content-Length" says
+     * the body is too large, or from the stream once the limit is exceeded).
+     * This is what the `request_body_max_bytes` endpoint attribute sets.
+     */
+    pub fn request_body_max_bytes(mut self, limit: usize) -> Self {
+        self.request_body_max_bytes.replace(limit);
+        self
+    }
+
+    /**
+     * Allows this endpoint's path variables to contain a "/".  By default, a
+     * request whose path contains a percent-encoded "/" ("%2F") fails with a
+     * 400 before any endpoint is selected.  With this set, the "%2F" is
+     * decoded and the "/" ends up in the value of the variable, so
+     * "/objects/a%2Fb" matches "/objects/{name}" with `name` equal to "a/b".
+     *
+     * Handlers that use such a value as a filesystem path (or as part of any
+     * other hierarchical name) must not assume it has only one component.
+     * Dropshot rejects with a 400 any segment that contains "." or ".." as a
+     * "/"-separated component once decoded, so "..%2F..%2Fetc" never reaches
+     * the handler, but the value may still be absolute or nested arbitrarily
+     * deep.
+     */
+    pub fn allow_encoded_slashes(mut self) -> Self {
+        self.allow_encoded_slashes = true;
+        self
+    }
+
+    /**
+     * Adds `middleware` to run around this endpoint's handler, after any
+     * middleware registered with the `ApiDescription` and any previously added
+     * to this endpoint.
+     */
+    pub fn middleware<M: Middleware<Context>>(mut self, middleware: M) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
 }
 
 /**
- * ApiEndpointParameter represents the discrete path and query parameters for a
- * given API endpoint. These are typically derived from the members of stucts
- * used as parameters to handler functions.
+ * ApiEndpointParameter represents the discrete path, query, header, and
+ * cookie parameters for a given API endpoint. These are typically derived
+ * from the members of structs used as parameters to handler functions.
  */
 #[derive(Debug)]
 pub struct ApiEndpointParameter {
@@ -102,6 +209,12 @@ impl ApiEndpointParameter {
                 ApiEndpointParameterLocation::Query => {
                     ApiEndpointParameterMetadata::Query(name)
                 }
+                ApiEndpointParameterLocation::Header => {
+                    ApiEndpointParameterMetadata::Header(name)
+                }
+                ApiEndpointParameterLocation::Cookie => {
+                    ApiEndpointParameterMetadata::Cookie(name)
+                }
             },
             description,
             required,
@@ -131,12 +244,16 @@ impl ApiEndpointParameter {
 pub enum ApiEndpointParameterLocation {
     Path,
     Query,
+    Header,
+    Cookie,
 }
 
 #[derive(Debug, Clone)]
 pub enum ApiEndpointParameterMetadata {
     Path(String),
     Query(String),
+    Header(String),
+    Cookie(String),
     Body(ApiEndpointBodyContentType),
 }
 
@@ -197,24 +314,112 @@ impl std::fmt::Debug for ApiSchemaGenerator {
  * Other metadata could also be provided here.  This object can be used to
  * generate an OpenAPI spec or to run an HTTP server implementing the API.
  */
-pub struct ApiDescription {
+pub struct ApiDescription<Context: ServerContext> {
     /** In practice, all the information we need is encoded in the router. */
-    router: HttpRouter,
+    router: HttpRouter<Context>,
+    /** middleware for all endpoints */
+    middleware: Vec<Arc<dyn Middleware<Context>>>,
+    /** middleware for endpoints having a particular tag */
+    tag_middleware: Vec<(String, Arc<dyn Middleware<Context>>)>,
+    /** handler for requests that match no endpoint */
+    fallback: Option<Box<dyn RouteHandler<Context>>>,
 }
 
-impl ApiDescription {
+impl<Context: ServerContext> ApiDescription<Context> {
     pub fn new() -> Self {
         ApiDescription {
             router: HttpRouter::new(),
+            middleware: vec![],
+            tag_middleware: vec![],
+            fallback: None,
         }
     }
 
+    /**
+     * Adds `middleware` to run around the handlers for all endpoints in this
+     * API, regardless of whether they were registered before or after this
+     * call.  See [`Middleware`] for the order in which middleware runs.
+     */
+    pub fn middleware<M: Middleware<Context>>(&mut self, middleware: M) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /**
+     * Adds `middleware` to run around the handlers for endpoints in this API
+     * that have the tag `tag`.  See [`Middleware`] for the order in which
+     * middleware runs.
+     */
+    pub fn tag_middleware<M: Middleware<Context>>(
+        &mut self,
+        tag: &str,
+        middleware: M,
+    ) {
+        self.tag_middleware.push((tag.to_string(), Arc::new(middleware)));
+    }
+
+    /**
+     * Sets whether HEAD and OPTIONS requests are handled automatically for
+     * resources that have no endpoints for them.  By default, they are: a
+     * HEAD request is handled by the resource's GET endpoint (with the body
+     * of its response discarded), and an OPTIONS request gets an empty
+     * response whose "Allow" header lists the methods that the resource
+     * supports.  Either way, requests for methods that a resource doesn't
+     * support fail with a 405 ("Method Not Allowed") error whose "Allow"
+     * header lists the methods that it does.
+     *
+     * This applies to the whole API, including any APIs mounted within it
+     * (whose own setting is ignored).
+     */
+    pub fn automatic_methods(&mut self, enabled: bool) {
+        self.router.set_automatic_methods(enabled);
+    }
+
+    /**
+     * Sets whether a request whose path ends with "/" is redirected (with a
+     * 308 "Permanent Redirect") to the same path without the trailing "/"
+     * when that identifies a resource.  By default, it isn't redirected and
+     * is simply handled as though the "/" weren't there.
+     *
+     * Like `automatic_methods`, this applies to the whole API.
+     */
+    pub fn redirect_trailing_slashes(&mut self, enabled: bool) {
+        self.router.set_redirect_trailing_slash(enabled);
+    }
+
+    /**
+     * Sets the handler for requests whose path matches no endpoint, which
+     * otherwise fail with a 404 ("Not Found") error.  This could serve a
+     * single-page web app, proxy requests for legacy routes, or produce a
+     * custom error.  `handler` is an ordinary handler function (without the
+     * `endpoint` attribute) that's called for requests of any method.  It
+     * gets the full `RequestContext` (with no path variables) and may use any
+     * extractors other than `Path`.  The middleware registered for all
+     * endpoints runs around it, but tag middleware doesn't.
+     *
+     * The fallback handler isn't an endpoint, so it doesn't appear in the
+     * OpenAPI definition.  Like `automatic_methods`, it applies to the whole
+     * API: the fallback handler of an API mounted within this one is ignored.
+     * Requests whose path matches an endpoint but not its method still fail
+     * with a 405 ("Method Not Allowed") error.
+     */
+    pub fn fallback<HandlerType, FuncParams, ResponseType>(
+        &mut self,
+        handler: HandlerType,
+    ) where
+        HandlerType: HttpHandlerFunc<Context, FuncParams, ResponseType>,
+        FuncParams: Extractor + 'static,
+        ResponseType: HttpResponse + Send + Sync + 'static,
+    {
+        self.fallback =
+            Some(HttpRouteHandler::new_with_name(handler, "fallback handler"));
+    }
+
     /**
      * Register a new API endpoint.
      */
     pub fn register<T>(&mut self, endpoint: T) -> Result<(), String>
     where
-        T: Into<ApiEndpoint>,
+        T: Into<ApiEndpoint<Context>>,
     {
         let e = endpoint.into();
 
@@ -222,8 +427,13 @@ impl ApiDescription {
         // make sure they're identical.
         let path = path_to_segments(&e.path)
             .iter()
-            .filter_map(|segment| match PathSegment::from(segment) {
-                PathSegment::Varname(v) => Some(v),
+            .map(|segment| PathSegment::parse(segment))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter_map(|segment| match segment {
+                PathSegment::Varname(v)
+                | PathSegment::VarnameConstrained(v, _)
+                | PathSegment::VarnameWildcard(v) => Some(v),
                 _ => None,
             })
             .collect::<HashSet<_>>();
@@ -283,7 +493,97 @@ impl ApiDescription {
             ));
         }
 
-        self.router.insert(e);
+        self.router.try_insert(e)
+    }
+
+    /**
+     * Adds all of the endpoints in `api` to this API, with `prefix` prepended
+     * to each of their paths.  For example, mounting an API having an endpoint
+     * for `"/{instance}/start"` under the prefix `"/v1/instances"` results in
+     * an endpoint for `"/v1/instances/{instance}/start"`.  A prefix of `"/"`
+     * merges the two APIs without changing any paths.  This allows an API to
+     * be assembled from descriptions built separately (e.g., by different
+     * modules).
+     *
+     * The endpoints keep their tags and other properties, and the middleware
+     * registered with `api` (for all of its endpoints or by tag) continues to
+     * apply to them (only them), inside the middleware registered with this
+     * API.
+     *
+     * `prefix` may contain variables (e.g., `"/projects/{project}"`).  The
+     * values of these are available to handlers in
+     * `RequestContext::path_variables`, and each is described as a string path
+     * parameter in the OpenAPI definition.
+     *
+     * Returns an error if any of the resulting routes would conflict with one
+     * already registered (see [`ApiDescription::register()`]), in which case
+     * none of the endpoints in `api` are added.
+     */
+    pub fn mount(
+        &mut self,
+        prefix: &str,
+        api: ApiDescription<Context>,
+    ) -> Result<(), String> {
+        let prefix_segments = path_to_segments(prefix);
+        let prefix_vars = prefix_segments
+            .iter()
+            .map(|segment| PathSegment::parse(segment))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .filter_map(|segment| match segment {
+                PathSegment::Varname(v)
+                | PathSegment::VarnameConstrained(v, _)
+                | PathSegment::VarnameWildcard(v) => Some(v),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut endpoints = api.into_router().into_endpoints();
+        for e in &mut endpoints {
+            let mut segments = prefix_segments.clone();
+            segments.extend(path_to_segments(&e.path));
+            e.path = format!("/{}", segments.join("/"));
+
+            for varname in &prefix_vars {
+                let has_parameter =
+                    e.parameters.iter().any(|p| match &p.metadata {
+                        ApiEndpointParameterMetadata::Path(name) => {
+                            name == varname
+                        }
+                        _ => false,
+                    });
+                if !has_parameter {
+                    e.parameters.push(ApiEndpointParameter::new_named(
+                        &ApiEndpointParameterLocation::Path,
+                        varname.clone(),
+                        None,
+                        true,
+                        ApiSchemaGenerator::Static(
+                            schemars::schema::SchemaObject {
+                                instance_type: Some(
+                                    schemars::schema::InstanceType::String
+                                        .into(),
+                                ),
+                                ..Default::default()
+                            }
+                            .into(),
+                        ),
+                        vec![],
+                    ));
+                }
+            }
+        }
+
+        /*
+         * The endpoints in `api` can't conflict with each other, so if none of
+         * them conflicts with an existing route, we can add them all.
+         */
+        for e in &endpoints {
+            self.router.check_insert(e)?;
+        }
+        for e in endpoints {
+            self.router.insert(e);
+        }
 
         Ok(())
     }
@@ -297,7 +597,11 @@ impl ApiDescription {
      * `version` properties that the `Info` object in an OpenAPI definition must
      * contain.
      */
-    pub fn openapi<S1, S2>(&self, title: S1, version: S2) -> OpenApiDefinition
+    pub fn openapi<S1, S2>(
+        &self,
+        title: S1,
+        version: S2,
+    ) -> OpenApiDefinition<'_, Context>
     where
         S1: AsRef<str>,
         S2: AsRef<str>,
@@ -367,6 +671,24 @@ impl ApiDescription {
         let mut generator = schemars::gen::SchemaGenerator::new(settings);
 
         for (path, method, endpoint) in &self.router {
+            /*
+             * OpenAPI path templates have no syntax for a variable that matches
+             * the rest of the path, so wildcards appear like any other
+             * variable and their parameters say that they may contain "/".
+             * Likewise, there's no syntax for constraints on a variable's
+             * values, so those are left out.  (The router doesn't allow two
+             * routes for the same method whose paths differ only in these
+             * ways, so no operation here replaces another.)
+             */
+            let wildcards = path_to_segments(&path)
+                .iter()
+                .filter_map(|segment| match PathSegment::from(segment) {
+                    PathSegment::VarnameWildcard(v) => Some(v),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            let path = openapi_path(&path);
+
             let path = openapi.paths.entry(path).or_insert(
                 openapiv3::ReferenceOr::Item(openapiv3::PathItem::default()),
             );
@@ -404,6 +726,12 @@ impl ApiDescription {
                         ApiEndpointParameterMetadata::Query(name) => {
                             (name, ApiEndpointParameterLocation::Query)
                         }
+                        ApiEndpointParameterMetadata::Header(name) => {
+                            (name, ApiEndpointParameterLocation::Header)
+                        }
+                        ApiEndpointParameterMetadata::Cookie(name) => {
+                            (name, ApiEndpointParameterLocation::Cookie)
+                        }
                     };
 
                     let schema = match &param.schema {
@@ -415,9 +743,23 @@ impl ApiDescription {
                         }
                     };
 
+                    let description = match location {
+                        ApiEndpointParameterLocation::Path
+                            if wildcards.contains(name) =>
+                        {
+                            param.description.clone().or_else(|| {
+                                Some(String::from(
+                                    "the rest of the path, which may contain \
+                                     \"/\" characters",
+                                ))
+                            })
+                        }
+                        _ => param.description.clone(),
+                    };
+
                     let parameter_data = openapiv3::ParameterData {
                         name: name.clone(),
-                        description: param.description.clone(),
+                        description: description,
                         required: param.required,
                         deprecated: None,
                         format: openapiv3::ParameterSchemaOrContent::Schema(
@@ -445,6 +787,22 @@ impl ApiDescription {
                                 },
                             ))
                         }
+                        ApiEndpointParameterLocation::Header => {
+                            Some(openapiv3::ReferenceOr::Item(
+                                openapiv3::Parameter::Header {
+                                    parameter_data: parameter_data,
+                                    style: openapiv3::HeaderStyle::Simple,
+                                },
+                            ))
+                        }
+                        ApiEndpointParameterLocation::Cookie => {
+                            Some(openapiv3::ReferenceOr::Item(
+                                openapiv3::Parameter::Cookie {
+                                    parameter_data: parameter_data,
+                                    style: openapiv3::CookieStyle::Form,
+                                },
+                            ))
+                        }
                     }
                 })
                 .collect::<Vec<_>>();
@@ -564,7 +922,28 @@ impl ApiDescription {
      * crate?  Once we do that, we don't need to consume the ApiDescription to
      * do this.
      */
-    pub fn into_router(self) -> HttpRouter {
+    pub fn into_router(mut self) -> HttpRouter<Context> {
+        /*
+         * Now that all of the middleware is known, assemble the chain for each
+         * endpoint: the API-wide middleware, then the middleware for each of
+         * the endpoint's tags, then the endpoint's own.
+         */
+        let middleware = self.middleware;
+        let tag_middleware = self.tag_middleware;
+        self.router.visit_endpoints_mut(&mut |endpoint| {
+            let mut chain = middleware.clone();
+            chain.extend(
+                tag_middleware
+                    .iter()
+                    .filter(|(tag, _)| endpoint.tags.contains(tag))
+                    .map(|(_, m)| Arc::clone(m)),
+            );
+            chain.append(&mut endpoint.middleware);
+            endpoint.middleware = chain;
+        });
+        if let Some(handler) = self.fallback {
+            self.router.set_fallback(handler, middleware);
+        }
         self.router
     }
 }
@@ -956,17 +1335,17 @@ fn j2oas_object(
  * document may be generated via [`write()`](`OpenApiDefinition::write`) or
  * [`json()`](`OpenApiDefinition::json`).
  */
-pub struct OpenApiDefinition<'a> {
-    api: &'a ApiDescription,
+pub struct OpenApiDefinition<'a, Context: ServerContext> {
+    api: &'a ApiDescription<Context>,
     info: openapiv3::Info,
 }
 
-impl<'a> OpenApiDefinition<'a> {
+impl<'a, Context: ServerContext> OpenApiDefinition<'a, Context> {
     fn new(
-        api: &'a ApiDescription,
+        api: &'a ApiDescription<Context>,
         title: &str,
         version: &str,
-    ) -> OpenApiDefinition<'a> {
+    ) -> OpenApiDefinition<'a, Context> {
         let info = openapiv3::Info {
             title: title.to_string(),
             version: version.to_string(),
@@ -1130,7 +1509,7 @@ mod test {
     }
 
     async fn test_badpath_handler(
-        _: Arc<RequestContext>,
+        _: Arc<RequestContext<()>>,
         _: Path<TestPath>,
     ) -> Result<Response<Body>, HttpError> {
         panic!("test handler is not supposed to run");
@@ -1184,6 +1563,21 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_badpath4() {
+        let mut api = ApiDescription::new();
+        let ret = api.register(ApiEndpoint::new(
+            "test_badpath_handler".to_string(),
+            test_badpath_handler,
+            Method::GET,
+            "/{a:[a-z}/{b}",
+        ));
+        assert!(ret.unwrap_err().starts_with(
+            "HTTP URI path segment variable \"a\" has invalid pattern \
+             \"[a-z\": "
+        ));
+    }
+
     #[test]
     fn test_empty_struct() {
         #[derive(JsonSchema)]
@@ -1247,7 +1641,7 @@ mod test {
             path = "/testing/two_bodies"
         }]
         async fn test_twobodies_handler(
-            _: Arc<RequestContext>,
+            _: Arc<RequestContext<()>>,
             _: UntypedBody,
             _: TypedBody<AStruct>,
         ) -> Result<Response<Body>, HttpError> {
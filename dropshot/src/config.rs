@@ -6,6 +6,7 @@
 use serde::Deserialize;
 use serde::Serialize;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 
 /**
  * Configuration for a Dropshot server.
@@ -47,15 +48,267 @@ use std::net::SocketAddr;
 pub struct ConfigDropshot {
     /** IP address and TCP port to which to bind for accepting connections */
     pub bind_address: SocketAddr,
-    /** maximum allowed size of a request body, defaults to 1024 */
+    /**
+     * other IP addresses and TCP ports on which to accept connections for the
+     * same API (e.g., an IPv6 address in addition to an IPv4 one)
+     */
+    pub additional_bind_addresses: Vec<SocketAddr>,
+    /**
+     * maximum allowed size of a request body, defaults to 1024.  Endpoints can
+     * override this with the `request_body_max_bytes` endpoint attribute.
+     */
     pub request_body_max_bytes: usize,
+    /**
+     * maximum time, in milliseconds, that a request handler may run before
+     * the request fails with a 503 "Service Unavailable" error.  Endpoints can
+     * override this with the `timeout_ms` endpoint attribute.  By default,
+     * there is no limit.
+     */
+    pub handler_timeout_ms: Option<u64>,
+    /**
+     * maximum time, in milliseconds, that a client may take to send the
+     * headers of a request (HTTP/1 only).  This also limits how long an idle
+     * connection stays open waiting for the next request.  If the client takes
+     * longer, the connection is closed.  By default, there is no limit.
+     */
+    pub request_header_timeout_ms: Option<u64>,
+    /**
+     * maximum time, in milliseconds, that a client may take to send the body
+     * of a request once the handler starts reading it.  If the client takes
+     * longer, the request fails with a 408 "Request Timeout" error and the
+     * connection is closed.  By default, there is no limit.
+     */
+    pub request_body_timeout_ms: Option<u64>,
+    /**
+     * minimum average rate, in bytes per second, at which a client must send
+     * the body of a request (after a grace period of one second).  Slower
+     * clients are handled as for `request_body_timeout_ms`.  By default, there
+     * is no minimum.
+     */
+    pub request_body_min_bytes_per_sec: Option<u64>,
+    /**
+     * maximum number of requests that the server handles at once.  Endpoints
+     * can impose their own (lower) limit with the `concurrency_limit` endpoint
+     * attribute.  If specified, it must be at least 1.  By default, there is
+     * no limit.
+     */
+    pub max_concurrent_requests: Option<usize>,
+    /**
+     * how long, in milliseconds, a request may wait for a chance to be handled
+     * when the server (or endpoint) is already handling as many requests as it
+     * may.  Requests still waiting after this long fail with a 503 "Service
+     * Unavailable" error.  The default (0) rejects such requests immediately.
+     */
+    pub concurrency_queue_timeout_ms: u64,
+    /**
+     * maximum number of connections that the server keeps open at once.
+     * Connections accepted beyond this limit are closed immediately.  If
+     * specified, it must be at least 1.  By default, there is no limit.
+     */
+    pub max_connections: Option<usize>,
+    /**
+     * maximum time, in milliseconds, that a connection may sit idle (with no
+     * requests in progress and no data sent or received) before the server
+     * closes it.  By default, there is no limit.
+     */
+    pub idle_timeout_ms: Option<u64>,
+    /**
+     * whether clients may send more than one HTTP/1 request on the same
+     * connection, defaults to true
+     */
+    pub keep_alive: bool,
+    /**
+     * maximum total size of the request line and headers of an HTTP/1
+     * request.  Requests with larger headers fail with a 431 "Request Header
+     * Fields Too Large" error.  This must be at least 8192.  Defaults to about
+     * 400 KiB.
+     */
+    pub request_header_max_bytes: Option<usize>,
+    /**
+     * If present, the server terminates TLS itself (i.e., serves HTTPS) using
+     * this configuration.  Otherwise, the server speaks plain HTTP.
+     */
+    pub tls: Option<ConfigTls>,
+    /**
+     * If present, the server accepts HTTP/2 as well as HTTP/1, using this
+     * configuration.  Otherwise, the server speaks only HTTP/1.
+     */
+    pub http2: Option<ConfigHttp2>,
+    /**
+     * If present, the server also listens for connections on this Unix domain
+     * socket.  This is only supported on Unix-like systems.
+     */
+    pub unix_socket: Option<ConfigUnixSocket>,
 }
 
 impl Default for ConfigDropshot {
     fn default() -> Self {
         ConfigDropshot {
             bind_address: "127.0.0.1:0".parse().unwrap(),
+            additional_bind_addresses: Vec::new(),
             request_body_max_bytes: 1024,
+            handler_timeout_ms: None,
+            request_header_timeout_ms: None,
+            request_body_timeout_ms: None,
+            request_body_min_bytes_per_sec: None,
+            max_concurrent_requests: None,
+            concurrency_queue_timeout_ms: 0,
+            max_connections: None,
+            idle_timeout_ms: None,
+            keep_alive: true,
+            request_header_max_bytes: None,
+            tls: None,
+            http2: None,
+            unix_socket: None,
         }
     }
 }
+
+/**
+ * Configuration for terminating TLS in a Dropshot server.
+ *
+ * The certificate chain and private key are loaded from the filesystem when
+ * the server is created.  They can be loaded again later (e.g., after the
+ * certificate has been renewed) without restarting the server using
+ * [`crate::HttpServer::refresh_tls`].
+ *
+ * If `client_ca_file` is specified, clients may authenticate themselves with a
+ * certificate issued by one of the certificate authorities in that file
+ * ("mutual TLS").  The verified certificate is made available to request
+ * handlers via [`crate::RequestContext::connection`].  If
+ * `require_client_cert` is also set, handshakes from clients that do not
+ * present a valid certificate are rejected.  The client CA file is read only
+ * when the server is created.
+ */
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConfigTls {
+    /**
+     * path to a PEM file containing the server's certificate chain, starting
+     * with the server's own certificate
+     */
+    pub cert_file: PathBuf,
+    /** path to a PEM file containing the server's private key */
+    pub key_file: PathBuf,
+    /** oldest TLS protocol version to accept, defaults to TLS 1.2 */
+    #[serde(default)]
+    pub min_version: ConfigTlsVersion,
+    /**
+     * path to a PEM file containing the certificate authorities trusted to
+     * issue client certificates
+     */
+    #[serde(default)]
+    pub client_ca_file: Option<PathBuf>,
+    /**
+     * whether clients must present a valid certificate (requires
+     * `client_ca_file`)
+     */
+    #[serde(default)]
+    pub require_client_cert: bool,
+    /**
+     * maximum time, in milliseconds, that a client may take to complete the
+     * TLS handshake after connecting, defaults to 10 seconds.  If the client
+     * takes longer, the connection is closed.
+     */
+    #[serde(default = "ConfigTls::default_handshake_timeout_ms")]
+    pub handshake_timeout_ms: u64,
+}
+
+impl ConfigTls {
+    fn default_handshake_timeout_ms() -> u64 {
+        10000
+    }
+}
+
+/**
+ * TLS protocol versions that may be used as the minimum version accepted by a
+ * Dropshot server.
+ */
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum ConfigTlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/**
+ * Configuration for serving HTTP/2.
+ *
+ * A server with this configuration accepts HTTP/2 from clients that know in
+ * advance that the server supports it ("prior knowledge", as with `h2c`), and,
+ * if the server terminates TLS, it offers HTTP/2 during the TLS handshake
+ * using ALPN.  Clients that speak only HTTP/1 are served as usual.  Each
+ * connection may carry many requests at once, each on its own stream.
+ *
+ * The HTTP/1-specific settings in [`ConfigDropshot`] (`keep_alive`,
+ * `request_header_timeout_ms`, and `request_header_max_bytes`) do not apply to
+ * HTTP/2 connections.
+ */
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct ConfigHttp2 {
+    /**
+     * maximum number of streams (requests) that a client may have open at
+     * once on each connection.  By default, there is no limit.
+     */
+    pub max_concurrent_streams: Option<u32>,
+    /**
+     * initial flow control window, in bytes, for each stream, defaults to 1
+     * MiB.  This must be at most 2147483647.
+     */
+    pub initial_stream_window_size: Option<u32>,
+    /**
+     * initial flow control window, in bytes, for each connection as a whole,
+     * defaults to 1 MiB.  This must be at most 2147483647.
+     */
+    pub initial_connection_window_size: Option<u32>,
+    /**
+     * whether to adjust flow control windows based on the connection's
+     * measured bandwidth and latency, which overrides both window size
+     * settings.  Defaults to false.
+     */
+    pub adaptive_window: bool,
+    /**
+     * interval, in milliseconds, at which the server sends a PING frame on
+     * each connection to check that the client is still there.  By default,
+     * the server does not send pings.
+     */
+    pub keep_alive_interval_ms: Option<u64>,
+    /**
+     * how long, in milliseconds, the server waits for the client to
+     * acknowledge a ping before closing the connection, defaults to 20000.
+     * This has no effect unless `keep_alive_interval_ms` is also specified.
+     */
+    pub keep_alive_timeout_ms: Option<u64>,
+}
+
+/**
+ * Configuration for accepting connections on a Unix domain socket.
+ *
+ * Requests arriving on the socket are served by the same API as requests
+ * arriving over TCP.  Connections on the socket never use TLS, even if the
+ * server is configured to terminate TLS for TCP connections.  Request handlers
+ * can find the credentials of the process on the other end of the socket via
+ * [`crate::RequestContext::connection`].  The socket is removed when the
+ * server shuts down.
+ */
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConfigUnixSocket {
+    /** filesystem path at which to create the socket */
+    pub path: PathBuf,
+    /**
+     * permissions to apply to the socket after creating it (e.g., `0o660`),
+     * defaults to whatever the process's umask produces
+     */
+    #[serde(default)]
+    pub mode: Option<u32>,
+    /**
+     * If a socket already exists at `path` but no process is listening on it
+     * (e.g., because a previous instance of the server exited without cleaning
+     * up), remove it before binding.  Files at `path` that are not sockets and
+     * sockets that are in use are never removed.
+     */
+    #[serde(default)]
+    pub remove_stale: bool,
+}
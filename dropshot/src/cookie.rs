@@ -0,0 +1,320 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Support for HTTP cookies: parsing the `Cookie` request header and generating
+ * `Set-Cookie` response headers
+ */
+
+use super::error::HttpError;
+use chrono::DateTime;
+use chrono::Utc;
+use http::header::HeaderValue;
+use hyper::Body;
+use hyper::Request;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::time::Duration;
+
+/**
+ * Collects the cookies sent with `request` (in any number of `Cookie` headers)
+ * into a map from cookie name to value.  Pairs that aren't of the form
+ * "name=value" are ignored, as are pairs that aren't valid UTF-8.  If a cookie
+ * appears more than once, the first value wins, since user agents send the
+ * most specific cookie first.
+ */
+pub(crate) fn http_request_cookies(
+    request: &Request<Body>,
+) -> BTreeMap<String, String> {
+    let mut cookies = BTreeMap::new();
+    for header in request.headers().get_all(http::header::COOKIE) {
+        let header = match header.to_str() {
+            Ok(header) => header,
+            Err(_) => continue,
+        };
+        for pair in header.split(';') {
+            if let Some((name, value)) = pair.trim().split_once('=') {
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                cookies
+                    .entry(name.trim().to_string())
+                    .or_insert_with(|| value.to_string());
+            }
+        }
+    }
+    cookies
+}
+
+/**
+ * Value of the `SameSite` attribute of a cookie, which controls whether the
+ * user agent sends the cookie with requests initiated by other sites.
+ */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SameSite {
+    /** send the cookie only with requests initiated by the same site */
+    Strict,
+    /** also send the cookie when the user navigates here from another site */
+    Lax,
+    /** send the cookie with all requests (requires `Secure`) */
+    None,
+}
+
+impl Display for SameSite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        })
+    }
+}
+
+/**
+ * Describes a cookie to be set using a `Set-Cookie` response header.  Cookies
+ * are attached to responses using `HttpResponseSetCookies`.
+ *
+ * ```
+ * use dropshot::SameSite;
+ * use dropshot::SetCookie;
+ * use std::time::Duration;
+ *
+ * let cookie = SetCookie::new("session", "abc123")
+ *     .path("/")
+ *     .max_age(Duration::from_secs(3600))
+ *     .same_site(SameSite::Lax)
+ *     .secure(true)
+ *     .http_only(true);
+ * assert_eq!(
+ *     cookie.to_string(),
+ *     "session=abc123; Path=/; Max-Age=3600; SameSite=Lax; Secure; HttpOnly"
+ * );
+ * ```
+ */
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SetCookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    expires: Option<DateTime<Utc>>,
+    same_site: Option<SameSite>,
+    secure: bool,
+    http_only: bool,
+}
+
+impl SetCookie {
+    /**
+     * Describes a cookie called `name` with value `value` and no attributes.
+     * Without an expiry (see `max_age()` and `expires()`), this is a "session
+     * cookie" that the user agent discards when it shuts down.
+     */
+    pub fn new<S1, S2>(name: S1, value: S2) -> SetCookie
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        SetCookie {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            same_site: None,
+            secure: false,
+            http_only: false,
+        }
+    }
+
+    /**
+     * Describes a `Set-Cookie` header that removes the cookie called `name`
+     * from the user agent.  The path and domain must match those with which
+     * the cookie was set.
+     */
+    pub fn removal<S: Into<String>>(name: S) -> SetCookie {
+        SetCookie::new(name, "").max_age(Duration::from_secs(0))
+    }
+
+    /** Limits the cookie to requests for paths under `path`. */
+    pub fn path<S: Into<String>>(mut self, path: S) -> SetCookie {
+        self.path = Some(path.into());
+        self
+    }
+
+    /** Allows the cookie to be sent to `domain` and its subdomains. */
+    pub fn domain<S: Into<String>>(mut self, domain: S) -> SetCookie {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /** Causes the cookie to expire after `max_age` has elapsed. */
+    pub fn max_age(mut self, max_age: Duration) -> SetCookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /**
+     * Causes the cookie to expire at `expires`.  If `max_age()` is also
+     * specified, user agents ignore this in favor of that.
+     */
+    pub fn expires(mut self, expires: DateTime<Utc>) -> SetCookie {
+        self.expires = Some(expires);
+        self
+    }
+
+    /** Sets the cookie's `SameSite` attribute. */
+    pub fn same_site(mut self, same_site: SameSite) -> SetCookie {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /** Specifies whether the cookie may only be sent over HTTPS. */
+    pub fn secure(mut self, secure: bool) -> SetCookie {
+        self.secure = secure;
+        self
+    }
+
+    /**
+     * Specifies whether the cookie should be hidden from scripts running in
+     * the user agent.
+     */
+    pub fn http_only(mut self, http_only: bool) -> SetCookie {
+        self.http_only = http_only;
+        self
+    }
+
+    /**
+     * Returns the value of the `Set-Cookie` header for this cookie, or an
+     * error if the cookie's name or value contains characters that aren't
+     * allowed (see RFC 6265).
+     */
+    pub(crate) fn to_header_value(&self) -> Result<HeaderValue, HttpError> {
+        let is_token_char = |c: char| {
+            c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c)
+        };
+        let is_value_char =
+            |c: char| c.is_ascii_graphic() && !"\",;\\".contains(c);
+        if self.name.is_empty() || !self.name.chars().all(is_token_char) {
+            return Err(HttpError::for_internal_error(format!(
+                "invalid cookie name: {:?}",
+                self.name
+            )));
+        }
+        if !self.value.chars().all(is_value_char) {
+            return Err(HttpError::for_internal_error(format!(
+                "invalid value for cookie {:?}",
+                self.name
+            )));
+        }
+        HeaderValue::from_str(&self.to_string()).map_err(|error| {
+            HttpError::for_internal_error(format!(
+                "invalid attributes for cookie {:?}: {}",
+                self.name, error
+            ))
+        })
+    }
+}
+
+impl Display for SetCookie {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = &self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = &self.expires {
+            write!(
+                f,
+                "; Expires={}",
+                expires.format("%a, %d %b %Y %H:%M:%S GMT")
+            )?;
+        }
+        if let Some(same_site) = &self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::http_request_cookies;
+    use super::SameSite;
+    use super::SetCookie;
+    use chrono::TimeZone;
+    use chrono::Utc;
+    use hyper::Body;
+    use hyper::Request;
+    use std::time::Duration;
+
+    #[test]
+    fn test_request_cookies() {
+        let request = Request::builder()
+            .header("cookie", "a=1; b=\"two\"; junk; c=")
+            .header("cookie", "a=3;d=4=5")
+            .body(Body::empty())
+            .unwrap();
+        let cookies = http_request_cookies(&request);
+        let cookies = cookies
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(cookies, vec![
+            ("a", "1"),
+            ("b", "two"),
+            ("c", ""),
+            ("d", "4=5")
+        ]);
+    }
+
+    #[test]
+    fn test_set_cookie() {
+        let cookie = SetCookie::new("id", "a3fWa")
+            .domain("example.com")
+            .path("/docs")
+            .expires(Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap())
+            .same_site(SameSite::Strict)
+            .secure(true);
+        assert_eq!(
+            cookie.to_header_value().unwrap(),
+            "id=a3fWa; Path=/docs; Domain=example.com; Expires=Wed, 21 Oct \
+             2015 07:28:00 GMT; SameSite=Strict; Secure"
+        );
+
+        let cookie = SetCookie::removal("id").path("/");
+        assert_eq!(cookie.to_header_value().unwrap(), "id=; Path=/; Max-Age=0");
+
+        let cookie = SetCookie::new("id", "x").max_age(Duration::from_secs(60));
+        assert_eq!(cookie.to_header_value().unwrap(), "id=x; Max-Age=60");
+    }
+
+    #[test]
+    fn test_set_cookie_invalid() {
+        let error =
+            SetCookie::new("bad name", "x").to_header_value().unwrap_err();
+        assert_eq!(error.internal_message, "invalid cookie name: \"bad name\"");
+        let error = SetCookie::new("id", "a;b").to_header_value().unwrap_err();
+        assert_eq!(error.internal_message, "invalid value for cookie \"id\"");
+        let error = SetCookie::new("id", "x")
+            .path("/\n")
+            .to_header_value()
+            .unwrap_err();
+        assert!(error
+            .internal_message
+            .starts_with("invalid attributes for cookie \"id\""));
+    }
+}
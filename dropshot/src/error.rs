@@ -98,6 +98,11 @@ pub struct HttpError {
     pub external_message: String,
     /** Error message recorded in the log for this error */
     pub internal_message: String,
+    /**
+     * Additional headers to include in the response for this error (e.g.,
+     * "Retry-After"), if any
+     */
+    pub headers: Option<Box<http::HeaderMap>>,
 }
 
 /**
@@ -156,6 +161,7 @@ impl HttpError {
             error_code,
             internal_message: message.clone(),
             external_message: message,
+            headers: None,
         }
     }
 
@@ -173,6 +179,7 @@ impl HttpError {
                 .unwrap()
                 .to_string(),
             internal_message,
+            headers: None,
         }
     }
 
@@ -193,6 +200,7 @@ impl HttpError {
                 .unwrap()
                 .to_string(),
             internal_message,
+            headers: None,
         }
     }
 
@@ -244,9 +252,23 @@ impl HttpError {
             error_code,
             internal_message,
             external_message,
+            headers: None,
         }
     }
 
+    /**
+     * Adds the header `name` with value `value` to the response that will be
+     * generated for this error.
+     */
+    pub fn with_header(
+        mut self,
+        name: http::header::HeaderName,
+        value: http::header::HeaderValue,
+    ) -> Self {
+        self.headers.get_or_insert_with(Default::default).append(name, value);
+        self
+    }
+
     /**
      * Generates an HTTP response for the given `HttpError`, using `request_id`
      * for the response's request id.
@@ -265,7 +287,11 @@ impl HttpError {
          * probably have to use unwrap() there and make sure we've tested that
          * code at least once!)
          */
-        hyper::Response::builder()
+        let mut builder = hyper::Response::builder();
+        if let Some(headers) = self.headers {
+            builder.headers_mut().unwrap().extend(*headers);
+        }
+        builder
             .status(self.status_code)
             .header(
                 http::header::CONTENT_TYPE,
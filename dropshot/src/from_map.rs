@@ -30,11 +30,13 @@ where
 /**
  * Deserializer for BTreeMap<String, String> that interprets the values. It has
  * two modes: about to iterate over the map or about to process a single value.
+ * A value may be accompanied by the key under which it appeared, which is used
+ * to report errors parsing it.
  */
 #[derive(Debug)]
 enum MapDeserializer<'de> {
     Map(&'de BTreeMap<String, String>),
-    Value(String),
+    Value(String, Option<String>),
 }
 
 impl<'de> MapDeserializer<'de> {
@@ -44,14 +46,24 @@ impl<'de> MapDeserializer<'de> {
 
     /**
      * Helper function to extract pattern match for Value. Fail if we're
-     * expecting a Map or return the result of the provided function.
+     * expecting a Map or return the result of the provided function.  Errors
+     * from that function name the key for this value, if there is one, since
+     * the errors themselves generally don't.
      */
     fn value<VV, F>(&self, deserialize: F) -> Result<VV, MapError>
     where
         F: FnOnce(&String) -> Result<VV, MapError>,
     {
         match self {
-            MapDeserializer::Value(ref raw_value) => deserialize(raw_value),
+            MapDeserializer::Value(ref raw_value, ref key) => {
+                deserialize(raw_value).map_err(|error| match key {
+                    Some(key) => MapError(format!(
+                        "invalid value for \"{}\": {}",
+                        key, error
+                    )),
+                    None => error,
+                })
+            }
             MapDeserializer::Map(_) => Err(MapError(
                 "must be applied to a flattened struct rather than a raw type"
                     .to_string(),
@@ -189,7 +201,7 @@ impl<'de, 'a> Deserializer<'de> for &'a mut MapDeserializer<'de> {
                     value: None,
                 })
             }
-            MapDeserializer::Value(_) => Err(MapError(
+            MapDeserializer::Value(..) => Err(MapError(
                 "destination struct must be fully flattened".to_string(),
             )),
         }
@@ -328,8 +340,8 @@ impl<'de, 'a> VariantAccess<'de> for &mut MapDeserializer<'de> {
 struct MapMapAccess {
     /** Iterator through the Map */
     iter: Box<dyn Iterator<Item = (String, String)>>,
-    /** Pending value in a key-value pair */
-    value: Option<String>,
+    /** Pending key-value pair */
+    value: Option<(String, String)>,
 }
 
 impl<'de, 'a> MapAccess<'de> for MapMapAccess {
@@ -344,10 +356,10 @@ impl<'de, 'a> MapAccess<'de> for MapMapAccess {
     {
         match self.iter.next() {
             Some((key, value)) => {
-                /* Save the value for later. */
-                self.value.replace(value);
+                /* Save the pair for later. */
+                self.value.replace((key.clone(), value));
                 /* Create a Deserializer for that single value. */
-                let mut deserializer = MapDeserializer::Value(key);
+                let mut deserializer = MapDeserializer::Value(key, None);
                 seed.deserialize(&mut deserializer).map(Some)
             }
             None => Ok(None),
@@ -358,8 +370,8 @@ impl<'de, 'a> MapAccess<'de> for MapMapAccess {
         V: DeserializeSeed<'de>,
     {
         match self.value.take() {
-            Some(value) => {
-                let mut deserializer = MapDeserializer::Value(value);
+            Some((key, value)) => {
+                let mut deserializer = MapDeserializer::Value(value, Some(key));
                 seed.deserialize(&mut deserializer)
             }
             /*
@@ -447,6 +459,24 @@ mod test {
         }
     }
     #[test]
+    fn test_bad_value() {
+        #[derive(Deserialize, Debug)]
+        struct A {
+            aaa: String,
+            bbb: u32,
+        }
+        let mut map = BTreeMap::new();
+        map.insert("aaa".to_string(), "A".to_string());
+        map.insert("bbb".to_string(), "B".to_string());
+        match from_map::<A>(&map) {
+            Err(s) => assert_eq!(
+                s,
+                "invalid value for \"bbb\": unable to parse 'B' as u32"
+            ),
+            Ok(_) => panic!("unexpected success"),
+        }
+    }
+    #[test]
     fn test_types() {
         #[derive(Deserialize, Debug)]
         struct A {
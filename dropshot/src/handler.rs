@@ -33,22 +33,30 @@
  * OpenAPI document generation.
  */
 
+use super::cookie::http_request_cookies;
+use super::cookie::SetCookie;
 use super::error::HttpError;
 use super::http_util::http_extract_path_params;
 use super::http_util::http_read_body;
 use super::http_util::CONTENT_TYPE_JSON;
+use super::server::ConnectionInfo;
 use super::server::DropshotState;
+use super::server::ServerContext;
 use crate::api_description::ApiEndpointBodyContentType;
 use crate::api_description::ApiEndpointParameter;
 use crate::api_description::ApiEndpointParameterLocation;
 use crate::api_description::ApiEndpointResponse;
 use crate::api_description::ApiSchemaGenerator;
+use crate::from_map::from_map;
 use crate::pagination::PaginationParams;
 
 use async_trait::async_trait;
 use bytes::Bytes;
 use futures::lock::Mutex;
+use futures::Stream;
+use http::Extensions;
 use http::StatusCode;
+use hyper::body::HttpBody;
 use hyper::Body;
 use hyper::Request;
 use hyper::Response;
@@ -67,7 +75,9 @@ use std::fmt::Result as FmtResult;
 use std::future::Future;
 use std::marker::PhantomData;
 use std::num::NonZeroUsize;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::Poll;
 
 /**
  * Type alias for the result returned by HTTP handler functions.
@@ -75,7 +85,9 @@ use std::sync::Arc;
 pub type HttpHandlerResult = Result<Response<Body>, HttpError>;
 
 /**
- * Handle for various interfaces useful during request processing.
+ * Handle for various interfaces useful during request processing.  `Context`
+ * is the type of the server-wide state provided by the consumer when the server
+ * was created.  See [`RequestContext::context()`].
  */
 /*
  * TODO-cleanup What's the right way to package up "request"?  The only time we
@@ -86,20 +98,43 @@ pub type HttpHandlerResult = Result<Response<Body>, HttpError>;
  * overkill since it will only really be used by one thread at a time (at all,
  * let alone mutably) and there will never be contention on the Mutex.
  */
-pub struct RequestContext {
+pub struct RequestContext<Context: ServerContext> {
     /** shared server state */
-    pub server: Arc<DropshotState>,
+    pub server: Arc<DropshotState<Context>>,
     /** HTTP request details */
     pub request: Arc<Mutex<Request<Body>>>,
+    /** details about the connection on which the request arrived */
+    pub connection: Arc<ConnectionInfo>,
     /** HTTP request routing variables */
     pub path_variables: BTreeMap<String, String>,
+    /**
+     * maximum number of bytes allowed in the request body: the endpoint's
+     * limit, if it specifies one, or else the server's
+     * `request_body_max_bytes`
+     */
+    pub request_body_max_bytes: usize,
     /** unique id assigned to this request */
     pub request_id: String,
     /** logger for this specific request */
     pub log: Logger,
+    /**
+     * request-scoped data that extractors, middleware, and the handler can use
+     * to pass values to each other (e.g., the authenticated user), keyed by
+     * type.  This starts out with any extensions that were attached to the
+     * underlying hyper request.
+     */
+    pub extensions: Mutex<Extensions>,
 }
 
-impl RequestContext {
+impl<Context: ServerContext> RequestContext<Context> {
+    /**
+     * Returns the server-wide state provided by the consumer when the server
+     * was created (the `private` argument to `HttpServer::new()`).
+     */
+    pub fn context(&self) -> &Context {
+        &self.server.private
+    }
+
     /**
      * Returns the appropriate count of items to return for a paginated request
      *
@@ -154,16 +189,30 @@ impl RequestContext {
     }
 }
 
+/**
+ * Helper trait for extracting the underlying Context type from the first
+ * argument to an endpoint handler.  This is used by the `#[endpoint]` macro to
+ * determine which `ApiDescription` an endpoint may be registered with; it
+ * generally isn't useful to consumers directly.
+ */
+pub trait RequestContextArgument {
+    type Context;
+}
+
+impl<T: ServerContext> RequestContextArgument for Arc<RequestContext<T>> {
+    type Context = T;
+}
+
 /**
  * `Extractor` defines an interface allowing a type to be constructed from a
  * `RequestContext`.  Unlike most traits, `Extractor` essentially defines only a
  * constructor function, not instance functions.
  *
- * The extractors that we provide (`Query`, `Path`, `TypedBody`, and
- * `UntypedBody`) implement `Extractor` in order to construct themselves from
- * the request. For example, `Extractor` is implemented for `Query<Q>` with a
- * function that reads the query string from the request, parses it, and
- * constructs a `Query<Q>` with it.
+ * The extractors that we provide (`Query`, `Path`, `Header`, `Cookies`,
+ * `TypedBody`, and `UntypedBody`) implement `Extractor` in order to construct
+ * themselves from the request. For example, `Extractor` is implemented for
+ * `Query<Q>` with a function that reads the query string from the request,
+ * parses it, and constructs a `Query<Q>` with it.
  *
  * We also define implementations of `Extractor` for tuples of types that
  * themselves implement `Extractor`.  See the implementation of
@@ -174,8 +223,8 @@ pub trait Extractor: Send + Sync + Sized {
     /**
      * Construct an instance of this type from a `RequestContext`.
      */
-    async fn from_request(
-        rqctx: Arc<RequestContext>,
+    async fn from_request<Context: ServerContext>(
+        rqctx: Arc<RequestContext<Context>>,
     ) -> Result<Self, HttpError>;
 
     fn metadata() -> Vec<ApiEndpointParameter>;
@@ -190,8 +239,9 @@ macro_rules! impl_extractor_for_tuple {
     #[async_trait]
     impl< $($T: Extractor + 'static,)* > Extractor for ($($T,)*)
     {
-        async fn from_request(_rqctx: Arc<RequestContext>)
-            -> Result<( $($T,)* ), HttpError>
+        async fn from_request<Context: ServerContext>(
+            _rqctx: Arc<RequestContext<Context>>,
+        ) -> Result<( $($T,)* ), HttpError>
         {
             futures::try_join!($($T::from_request(Arc::clone(&_rqctx)),)*)
         }
@@ -209,6 +259,27 @@ impl_extractor_for_tuple!();
 impl_extractor_for_tuple!(T1);
 impl_extractor_for_tuple!(T1, T2);
 impl_extractor_for_tuple!(T1, T2, T3);
+impl_extractor_for_tuple!(T1, T2, T3, T4);
+impl_extractor_for_tuple!(T1, T2, T3, T4, T5);
+impl_extractor_for_tuple!(T1, T2, T3, T4, T5, T6);
+impl_extractor_for_tuple!(T1, T2, T3, T4, T5, T6, T7);
+impl_extractor_for_tuple!(T1, T2, T3, T4, T5, T6, T7, T8);
+impl_extractor_for_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9);
+impl_extractor_for_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10);
+impl_extractor_for_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11);
+impl_extractor_for_tuple!(T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12);
+impl_extractor_for_tuple!(
+    T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13
+);
+impl_extractor_for_tuple!(
+    T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14
+);
+impl_extractor_for_tuple!(
+    T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15
+);
+impl_extractor_for_tuple!(
+    T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11, T12, T13, T14, T15, T16
+);
 
 /**
  * `HttpHandlerFunc` is a trait providing a single function, `handle_request()`,
@@ -219,7 +290,8 @@ impl_extractor_for_tuple!(T1, T2, T3);
  * signatures.  They all consume a reference to the current request context.
  * They may also consume some number of extractor arguments.  The
  * `HttpHandlerFunc` trait is parametrized by the type `FuncParams`, which is
- * expected to be a tuple describing these extractor arguments.
+ * expected to be a tuple describing these extractor arguments, and by the type
+ * `Context` of the server-wide state in the request context.
  *
  * Below, we define implementations of `HttpHandlerFunc` for various function
  * types.  In this way, we can treat functions with different signatures as
@@ -228,15 +300,16 @@ impl_extractor_for_tuple!(T1, T2, T3);
  * treat different handlers interchangeably.  See `RouteHandler` below.
  */
 #[async_trait]
-pub trait HttpHandlerFunc<FuncParams, ResponseType>:
+pub trait HttpHandlerFunc<Context, FuncParams, ResponseType>:
     Send + Sync + 'static
 where
+    Context: ServerContext,
     FuncParams: Extractor,
     ResponseType: HttpResponse + Send + Sync + 'static,
 {
     async fn handle_request(
         &self,
-        rqctx: Arc<RequestContext>,
+        rqctx: Arc<RequestContext<Context>>,
         p: FuncParams,
     ) -> HttpHandlerResult;
 }
@@ -327,10 +400,11 @@ macro_rules! impl_HttpHandlerFunc_for_func_with_params {
     ($(($i:tt, $T:tt)),*) => {
 
     #[async_trait]
-    impl<FuncType, FutureType, ResponseType, $($T,)*>
-        HttpHandlerFunc<($($T,)*), ResponseType> for FuncType
+    impl<Context, FuncType, FutureType, ResponseType, $($T,)*>
+        HttpHandlerFunc<Context, ($($T,)*), ResponseType> for FuncType
     where
-        FuncType: Fn(Arc<RequestContext>, $($T,)*)
+        Context: ServerContext,
+        FuncType: Fn(Arc<RequestContext<Context>>, $($T,)*)
             -> FutureType + Send + Sync + 'static,
         FutureType: Future<Output = Result<ResponseType, HttpError>>
             + Send + 'static,
@@ -339,7 +413,7 @@ macro_rules! impl_HttpHandlerFunc_for_func_with_params {
     {
         async fn handle_request(
             &self,
-            rqctx: Arc<RequestContext>,
+            rqctx: Arc<RequestContext<Context>>,
             _param_tuple: ($($T,)*)
         ) -> HttpHandlerResult
         {
@@ -354,6 +428,157 @@ impl_HttpHandlerFunc_for_func_with_params!();
 impl_HttpHandlerFunc_for_func_with_params!((0, T0));
 impl_HttpHandlerFunc_for_func_with_params!((0, T1), (1, T2));
 impl_HttpHandlerFunc_for_func_with_params!((0, T1), (1, T2), (2, T3));
+impl_HttpHandlerFunc_for_func_with_params!((0, T1), (1, T2), (2, T3), (3, T4));
+impl_HttpHandlerFunc_for_func_with_params!(
+    (0, T1),
+    (1, T2),
+    (2, T3),
+    (3, T4),
+    (4, T5)
+);
+impl_HttpHandlerFunc_for_func_with_params!(
+    (0, T1),
+    (1, T2),
+    (2, T3),
+    (3, T4),
+    (4, T5),
+    (5, T6)
+);
+impl_HttpHandlerFunc_for_func_with_params!(
+    (0, T1),
+    (1, T2),
+    (2, T3),
+    (3, T4),
+    (4, T5),
+    (5, T6),
+    (6, T7)
+);
+impl_HttpHandlerFunc_for_func_with_params!(
+    (0, T1),
+    (1, T2),
+    (2, T3),
+    (3, T4),
+    (4, T5),
+    (5, T6),
+    (6, T7),
+    (7, T8)
+);
+impl_HttpHandlerFunc_for_func_with_params!(
+    (0, T1),
+    (1, T2),
+    (2, T3),
+    (3, T4),
+    (4, T5),
+    (5, T6),
+    (6, T7),
+    (7, T8),
+    (8, T9)
+);
+impl_HttpHandlerFunc_for_func_with_params!(
+    (0, T1),
+    (1, T2),
+    (2, T3),
+    (3, T4),
+    (4, T5),
+    (5, T6),
+    (6, T7),
+    (7, T8),
+    (8, T9),
+    (9, T10)
+);
+impl_HttpHandlerFunc_for_func_with_params!(
+    (0, T1),
+    (1, T2),
+    (2, T3),
+    (3, T4),
+    (4, T5),
+    (5, T6),
+    (6, T7),
+    (7, T8),
+    (8, T9),
+    (9, T10),
+    (10, T11)
+);
+impl_HttpHandlerFunc_for_func_with_params!(
+    (0, T1),
+    (1, T2),
+    (2, T3),
+    (3, T4),
+    (4, T5),
+    (5, T6),
+    (6, T7),
+    (7, T8),
+    (8, T9),
+    (9, T10),
+    (10, T11),
+    (11, T12)
+);
+impl_HttpHandlerFunc_for_func_with_params!(
+    (0, T1),
+    (1, T2),
+    (2, T3),
+    (3, T4),
+    (4, T5),
+    (5, T6),
+    (6, T7),
+    (7, T8),
+    (8, T9),
+    (9, T10),
+    (10, T11),
+    (11, T12),
+    (12, T13)
+);
+impl_HttpHandlerFunc_for_func_with_params!(
+    (0, T1),
+    (1, T2),
+    (2, T3),
+    (3, T4),
+    (4, T5),
+    (5, T6),
+    (6, T7),
+    (7, T8),
+    (8, T9),
+    (9, T10),
+    (10, T11),
+    (11, T12),
+    (12, T13),
+    (13, T14)
+);
+impl_HttpHandlerFunc_for_func_with_params!(
+    (0, T1),
+    (1, T2),
+    (2, T3),
+    (3, T4),
+    (4, T5),
+    (5, T6),
+    (6, T7),
+    (7, T8),
+    (8, T9),
+    (9, T10),
+    (10, T11),
+    (11, T12),
+    (12, T13),
+    (13, T14),
+    (14, T15)
+);
+impl_HttpHandlerFunc_for_func_with_params!(
+    (0, T1),
+    (1, T2),
+    (2, T3),
+    (3, T4),
+    (4, T5),
+    (5, T6),
+    (6, T7),
+    (7, T8),
+    (8, T9),
+    (9, T10),
+    (10, T11),
+    (11, T12),
+    (12, T13),
+    (13, T14),
+    (14, T15),
+    (15, T16)
+);
 
 /**
  * `RouteHandler` abstracts an `HttpHandlerFunc<FuncParams, ResponseType>` in a
@@ -364,7 +589,7 @@ impl_HttpHandlerFunc_for_func_with_params!((0, T1), (1, T2), (2, T3));
  * to record that a specific handler has been attached to a specific HTTP route.
  */
 #[async_trait]
-pub trait RouteHandler: Debug + Send + Sync {
+pub trait RouteHandler<Context: ServerContext>: Debug + Send + Sync {
     /**
      * Returns a description of this handler.  This might be a function name,
      * for example.  This is not guaranteed to be unique.
@@ -374,22 +599,27 @@ pub trait RouteHandler: Debug + Send + Sync {
     /**
      * Handle an incoming HTTP request.
      */
-    async fn handle_request(&self, rqctx: RequestContext) -> HttpHandlerResult;
+    async fn handle_request(
+        &self,
+        rqctx: Arc<RequestContext<Context>>,
+    ) -> HttpHandlerResult;
 }
 
 /**
- * `HttpRouteHandler` is the only type that implements `RouteHandler`.  The
- * reason both exist is that we need `HttpRouteHandler::new()` to consume an
- * arbitrary kind of `HttpHandlerFunc<FuncParams>` and return an object that's
- * _not_ parametrized by `FuncParams`.  In fact, the resulting
- * `HttpRouteHandler` _is_ parametrized by `FuncParams`, but we returned it
- * as a `RouteHandler` that does not have those type parameters, allowing the
- * caller to ignore the differences between different handler function type
- * signatures.
+ * `HttpRouteHandler` is the type that implements `RouteHandler` for handler
+ * functions.  (The router also implements it for requests that it handles
+ * itself, like automatic OPTIONS requests.)  The reason both exist is that we
+ * need `HttpRouteHandler::new()` to consume an arbitrary kind of
+ * `HttpHandlerFunc<FuncParams>` and return an object that's _not_
+ * parametrized by `FuncParams`.  In fact, the resulting `HttpRouteHandler`
+ * _is_ parametrized by `FuncParams`, but we returned it as a `RouteHandler`
+ * that does not have those type parameters, allowing the caller to ignore the
+ * differences between different handler function type signatures.
  */
-pub struct HttpRouteHandler<HandlerType, FuncParams, ResponseType>
+pub struct HttpRouteHandler<Context, HandlerType, FuncParams, ResponseType>
 where
-    HandlerType: HttpHandlerFunc<FuncParams, ResponseType>,
+    Context: ServerContext,
+    HandlerType: HttpHandlerFunc<Context, FuncParams, ResponseType>,
     FuncParams: Extractor,
     ResponseType: HttpResponse + Send + Sync + 'static,
 {
@@ -407,13 +637,14 @@ where
      * here causes the compiler to behave as though this struct referred to a
      * `FuncParams`, which allows us to use the type parameter below.
      */
-    phantom: PhantomData<(FuncParams, ResponseType)>,
+    phantom: PhantomData<(FuncParams, ResponseType, Context)>,
 }
 
-impl<HandlerType, FuncParams, ResponseType> Debug
-    for HttpRouteHandler<HandlerType, FuncParams, ResponseType>
+impl<Context, HandlerType, FuncParams, ResponseType> Debug
+    for HttpRouteHandler<Context, HandlerType, FuncParams, ResponseType>
 where
-    HandlerType: HttpHandlerFunc<FuncParams, ResponseType>,
+    Context: ServerContext,
+    HandlerType: HttpHandlerFunc<Context, FuncParams, ResponseType>,
     FuncParams: Extractor,
     ResponseType: HttpResponse + Send + Sync + 'static,
 {
@@ -423,10 +654,11 @@ where
 }
 
 #[async_trait]
-impl<HandlerType, FuncParams, ResponseType> RouteHandler
-    for HttpRouteHandler<HandlerType, FuncParams, ResponseType>
+impl<Context, HandlerType, FuncParams, ResponseType> RouteHandler<Context>
+    for HttpRouteHandler<Context, HandlerType, FuncParams, ResponseType>
 where
-    HandlerType: HttpHandlerFunc<FuncParams, ResponseType>,
+    Context: ServerContext,
+    HandlerType: HttpHandlerFunc<Context, FuncParams, ResponseType>,
     FuncParams: Extractor + 'static,
     ResponseType: HttpResponse + Send + Sync + 'static,
 {
@@ -436,7 +668,7 @@ where
 
     async fn handle_request(
         &self,
-        rqctx_raw: RequestContext,
+        rqctx: Arc<RequestContext<Context>>,
     ) -> HttpHandlerResult {
         /*
          * This is where the magic happens: in the code below, `funcparams` has
@@ -456,7 +688,6 @@ where
          * actual handler function.  From this point down, all of this is
          * resolved statically.
          */
-        let rqctx = Arc::new(rqctx_raw);
         let funcparams = Extractor::from_request(Arc::clone(&rqctx)).await?;
         let future = self.handler.handle_request(rqctx, funcparams);
         future.await
@@ -467,10 +698,11 @@ where
  * Public interfaces
  */
 
-impl<HandlerType, FuncParams, ResponseType>
-    HttpRouteHandler<HandlerType, FuncParams, ResponseType>
+impl<Context, HandlerType, FuncParams, ResponseType>
+    HttpRouteHandler<Context, HandlerType, FuncParams, ResponseType>
 where
-    HandlerType: HttpHandlerFunc<FuncParams, ResponseType>,
+    Context: ServerContext,
+    HandlerType: HttpHandlerFunc<Context, FuncParams, ResponseType>,
     FuncParams: Extractor + 'static,
     ResponseType: HttpResponse + Send + Sync + 'static,
 {
@@ -479,7 +711,7 @@ where
      * signatures, return a RouteHandler that can be used to respond to HTTP
      * requests using this function.
      */
-    pub fn new(handler: HandlerType) -> Box<dyn RouteHandler> {
+    pub fn new(handler: HandlerType) -> Box<dyn RouteHandler<Context>> {
         HttpRouteHandler::new_with_name(handler, "<unlabeled handler>")
     }
 
@@ -491,7 +723,7 @@ where
     pub fn new_with_name(
         handler: HandlerType,
         label: &str,
-    ) -> Box<dyn RouteHandler> {
+    ) -> Box<dyn RouteHandler<Context>> {
         Box::new(HttpRouteHandler {
             label: label.to_string(),
             handler,
@@ -565,8 +797,8 @@ impl<QueryType> Extractor for Query<QueryType>
 where
     QueryType: JsonSchema + DeserializeOwned + Send + Sync + 'static,
 {
-    async fn from_request(
-        rqctx: Arc<RequestContext>,
+    async fn from_request<Context: ServerContext>(
+        rqctx: Arc<RequestContext<Context>>,
     ) -> Result<Query<QueryType>, HttpError> {
         let request = rqctx.request.lock().await;
         http_request_load_query(&request)
@@ -610,8 +842,8 @@ impl<PathType> Extractor for Path<PathType>
 where
     PathType: DeserializeOwned + JsonSchema + Send + Sync + 'static,
 {
-    async fn from_request(
-        rqctx: Arc<RequestContext>,
+    async fn from_request<Context: ServerContext>(
+        rqctx: Arc<RequestContext<Context>>,
     ) -> Result<Path<PathType>, HttpError> {
         let params: PathType = http_extract_path_params(&rqctx.path_variables)?;
         Ok(Path {
@@ -624,9 +856,149 @@ where
     }
 }
 
+/*
+ * Header: request header extractor
+ */
+
+/**
+ * `Header<HeaderType>` is an extractor used to deserialize an instance of
+ * `HeaderType` from an HTTP request's headers.  `HeaderType` is any structure
+ * of yours that implements `serde::Deserialize`, with one field for each
+ * header of interest.  Header names are matched in lowercase, so fields for
+ * headers whose names contain hyphens need to be renamed (e.g., with
+ * `#[serde(rename_all = "kebab-case")]`).  Values are parsed according to
+ * the type of each field, as with `Path`.  Headers that appear more than once
+ * are combined into a single comma-separated value.  See this module's
+ * documentation for more information.
+ */
+pub struct Header<HeaderType: JsonSchema + Send + Sync> {
+    inner: HeaderType,
+}
+
+impl<HeaderType: JsonSchema + Send + Sync> Header<HeaderType> {
+    /*
+     * TODO drop this in favor of Deref?  + Display and Debug for convenience?
+     */
+    pub fn into_inner(self) -> HeaderType {
+        self.inner
+    }
+}
+
+/**
+ * Given an HTTP request, collect its headers and attempt to deserialize them
+ * as an instance of `HeaderType`.
+ */
+fn http_request_load_headers<HeaderType>(
+    request: &Request<Body>,
+) -> Result<Header<HeaderType>, HttpError>
+where
+    HeaderType: DeserializeOwned + JsonSchema + Send + Sync,
+{
+    let mut headers: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in request.headers() {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        headers
+            .entry(name.as_str().to_string())
+            .and_modify(|values| {
+                values.push_str(", ");
+                values.push_str(&value);
+            })
+            .or_insert_with(|| value.into_owned());
+    }
+
+    match from_map(&headers) {
+        Ok(h) => Ok(Header {
+            inner: h,
+        }),
+        Err(message) => Err(HttpError::for_bad_request(
+            None,
+            format!("unable to parse request headers: {}", message),
+        )),
+    }
+}
+
+/*
+ * The `Extractor` implementation for Header<HeaderType> describes how to
+ * construct an instance of `Header<HeaderType>` from an HTTP request: namely,
+ * by parsing the request's headers to an instance of `HeaderType`.
+ */
+#[async_trait]
+impl<HeaderType> Extractor for Header<HeaderType>
+where
+    HeaderType: DeserializeOwned + JsonSchema + Send + Sync + 'static,
+{
+    async fn from_request<Context: ServerContext>(
+        rqctx: Arc<RequestContext<Context>>,
+    ) -> Result<Header<HeaderType>, HttpError> {
+        let request = rqctx.request.lock().await;
+        http_request_load_headers(&request)
+    }
+
+    fn metadata() -> Vec<ApiEndpointParameter> {
+        HeaderType::metadata(&ApiEndpointParameterLocation::Header)
+    }
+}
+
+/*
+ * Cookies: request cookie extractor
+ */
+
+/**
+ * `Cookies<CookieType>` is an extractor used to deserialize an instance of
+ * `CookieType` from the cookies sent with an HTTP request (in its `Cookie`
+ * headers).  `CookieType` is any structure of yours that implements
+ * `serde::Deserialize`, with one field for each cookie of interest.  Values
+ * are parsed according to the type of each field, as with `Path`.  See this
+ * module's documentation for more information.
+ */
+pub struct Cookies<CookieType: JsonSchema + Send + Sync> {
+    inner: CookieType,
+}
+
+impl<CookieType: JsonSchema + Send + Sync> Cookies<CookieType> {
+    /*
+     * TODO drop this in favor of Deref?  + Display and Debug for convenience?
+     */
+    pub fn into_inner(self) -> CookieType {
+        self.inner
+    }
+}
+
+/*
+ * The `Extractor` implementation for Cookies<CookieType> describes how to
+ * construct an instance of `Cookies<CookieType>` from an HTTP request: namely,
+ * by parsing the request's `Cookie` headers to an instance of `CookieType`.
+ */
+#[async_trait]
+impl<CookieType> Extractor for Cookies<CookieType>
+where
+    CookieType: DeserializeOwned + JsonSchema + Send + Sync + 'static,
+{
+    async fn from_request<Context: ServerContext>(
+        rqctx: Arc<RequestContext<Context>>,
+    ) -> Result<Cookies<CookieType>, HttpError> {
+        let request = rqctx.request.lock().await;
+        let cookies = http_request_cookies(&request);
+        match from_map(&cookies) {
+            Ok(c) => Ok(Cookies {
+                inner: c,
+            }),
+            Err(message) => Err(HttpError::for_bad_request(
+                None,
+                format!("unable to parse cookies: {}", message),
+            )),
+        }
+    }
+
+    fn metadata() -> Vec<ApiEndpointParameter> {
+        CookieType::metadata(&ApiEndpointParameterLocation::Cookie)
+    }
+}
+
 /**
  * Convenience trait to generate parameter metadata from types implementing
- * `JsonSchema` for use with `Query` and `Path` `Extractors`.
+ * `JsonSchema` for use with `Query`, `Path`, `Header`, and `Cookies`
+ * `Extractors`.
  */
 pub(crate) trait GetMetadata {
     fn metadata(
@@ -667,7 +1039,8 @@ where
  * top-level structure must be flat--unflattened substructures will result
  * in an error.
  *
- * - `loc` is the input to GetMetadata::metadata, query or path parameters.
+ * - `loc` is the input to GetMetadata::metadata, query, path, header, or
+ * cookie parameters.
  * - `schema` is what we're processing.
  * - `definitions` is the map of referenced schemas created in the generation
  * step (as noted above, we would ideally just have these all inline).
@@ -838,8 +1211,8 @@ impl<BodyType: JsonSchema + DeserializeOwned + Send + Sync>
  * Given an HTTP request, attempt to read the body, parse it as JSON, and
  * deserialize an instance of `BodyType` from it.
  */
-async fn http_request_load_json_body<BodyType>(
-    rqctx: Arc<RequestContext>,
+async fn http_request_load_json_body<Context: ServerContext, BodyType>(
+    rqctx: Arc<RequestContext<Context>>,
 ) -> Result<TypedBody<BodyType>, HttpError>
 where
     BodyType: JsonSchema + DeserializeOwned + Send + Sync,
@@ -848,7 +1221,9 @@ where
     let mut request = rqctx.request.lock().await;
     let body_bytes = http_read_body(
         request.body_mut(),
-        server.config.request_body_max_bytes,
+        rqctx.request_body_max_bytes,
+        server.config.request_body_timeout,
+        server.config.request_body_min_bytes_per_sec,
     )
     .await?;
     let value: Result<BodyType, serde_json::Error> =
@@ -877,8 +1252,8 @@ impl<BodyType> Extractor for TypedBody<BodyType>
 where
     BodyType: JsonSchema + DeserializeOwned + Send + Sync + 'static,
 {
-    async fn from_request(
-        rqctx: Arc<RequestContext>,
+    async fn from_request<Context: ServerContext>(
+        rqctx: Arc<RequestContext<Context>>,
     ) -> Result<TypedBody<BodyType>, HttpError> {
         http_request_load_json_body(rqctx).await
     }
@@ -936,14 +1311,16 @@ impl UntypedBody {
 
 #[async_trait]
 impl Extractor for UntypedBody {
-    async fn from_request(
-        rqctx: Arc<RequestContext>,
+    async fn from_request<Context: ServerContext>(
+        rqctx: Arc<RequestContext<Context>>,
     ) -> Result<UntypedBody, HttpError> {
         let server = &rqctx.server;
         let mut request = rqctx.request.lock().await;
         let body_bytes = http_read_body(
             request.body_mut(),
-            server.config.request_body_max_bytes,
+            rqctx.request_body_max_bytes,
+            server.config.request_body_timeout,
+            server.config.request_body_min_bytes_per_sec,
         )
         .await?;
         Ok(UntypedBody {
@@ -969,6 +1346,92 @@ impl Extractor for UntypedBody {
     }
 }
 
+/*
+ * StreamingBody: body extractor that provides the body as it arrives.
+ */
+
+/**
+ * `StreamingBody` is an extractor for consuming the HTTP request body as a
+ * [`Stream`] of [`Bytes`] chunks as they arrive, rather than buffering the
+ * whole body in memory the way `UntypedBody` does.  This allows a handler to
+ * accept bodies too large to buffer, e.g., by writing each chunk to a file.
+ *
+ * The body may be at most `request_body_max_bytes` long (which endpoints
+ * expecting large bodies will want to raise with the endpoint attribute of
+ * the same name).  If the request's "Content-Length" header says the body is
+ * longer, extraction fails with a 400 error.  Otherwise, the stream produces
+ * a 400 error (and then ends) once it has read more than that many bytes.
+ * Since the handler decides how quickly the body is read, the server's
+ * `request_body_timeout_ms` and `request_body_min_bytes_per_sec` don't apply.
+ */
+pub struct StreamingBody {
+    body: Body,
+    cap: usize,
+    nbytesread: usize,
+}
+
+impl Stream for StreamingBody {
+    type Item = Result<Bytes, HttpError>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.nbytesread > this.cap {
+            return Poll::Ready(None);
+        }
+        let buf = match Pin::new(&mut this.body).poll_data(cx) {
+            Poll::Pending => return Poll::Pending,
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Ready(Some(Err(error))) => {
+                return Poll::Ready(Some(Err(HttpError::from(error))))
+            }
+            Poll::Ready(Some(Ok(buf))) => buf,
+        };
+        this.nbytesread = this.nbytesread.saturating_add(buf.len());
+        if this.nbytesread > this.cap {
+            return Poll::Ready(Some(Err(body_too_large(this.cap))));
+        }
+        Poll::Ready(Some(Ok(buf)))
+    }
+}
+
+fn body_too_large(cap: usize) -> HttpError {
+    HttpError::for_bad_request(
+        None,
+        format!("request body exceeded maximum size of {} bytes", cap),
+    )
+}
+
+#[async_trait]
+impl Extractor for StreamingBody {
+    async fn from_request<Context: ServerContext>(
+        rqctx: Arc<RequestContext<Context>>,
+    ) -> Result<StreamingBody, HttpError> {
+        let cap = rqctx.request_body_max_bytes;
+        let mut request = rqctx.request.lock().await;
+        let content_length = request
+            .headers()
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        if content_length.map_or(false, |length| length > cap as u64) {
+            return Err(body_too_large(cap));
+        }
+        Ok(StreamingBody {
+            body: std::mem::replace(request.body_mut(), Body::empty()),
+            cap,
+            nbytesread: 0,
+        })
+    }
+
+    fn metadata() -> Vec<ApiEndpointParameter> {
+        /* The body is described the same way as for `UntypedBody`. */
+        UntypedBody::metadata()
+    }
+}
+
 /*
  * Response Type Conversion
  *
@@ -1181,6 +1644,58 @@ impl From<HttpResponseUpdatedNoContent> for HttpHandlerResult {
     }
 }
 
+/**
+ * `HttpResponseSetCookies<T>` wraps one of the response types above (e.g.,
+ * `HttpResponseOk`), adding a `Set-Cookie` header for each of the given
+ * cookies.  The status code and body are those of the wrapped response.
+ *
+ * ```
+ * use dropshot::HttpResponseOk;
+ * use dropshot::HttpResponseSetCookies;
+ * use dropshot::SetCookie;
+ *
+ * let response: HttpResponseSetCookies<HttpResponseOk<()>> =
+ *     HttpResponseSetCookies::new(HttpResponseOk(()))
+ *         .cookie(SetCookie::new("session", "abc123").http_only(true));
+ * ```
+ */
+pub struct HttpResponseSetCookies<T: HttpTypedResponse> {
+    response: T,
+    cookies: Vec<SetCookie>,
+}
+impl<T: HttpTypedResponse> HttpResponseSetCookies<T> {
+    /** Wraps `response`, initially with no cookies. */
+    pub fn new(response: T) -> HttpResponseSetCookies<T> {
+        HttpResponseSetCookies {
+            response,
+            cookies: Vec::new(),
+        }
+    }
+
+    /** Adds a `Set-Cookie` header for `cookie` to the response. */
+    pub fn cookie(mut self, cookie: SetCookie) -> HttpResponseSetCookies<T> {
+        self.cookies.push(cookie);
+        self
+    }
+}
+impl<T: HttpTypedResponse> HttpTypedResponse for HttpResponseSetCookies<T> {
+    type Body = T::Body;
+    const STATUS_CODE: StatusCode = T::STATUS_CODE;
+    const DESCRIPTION: &'static str = T::DESCRIPTION;
+}
+impl<T: HttpTypedResponse> From<HttpResponseSetCookies<T>>
+    for HttpHandlerResult
+{
+    fn from(response: HttpResponseSetCookies<T>) -> HttpHandlerResult {
+        let mut result = response.response.into()?;
+        let headers = result.headers_mut();
+        for cookie in &response.cookies {
+            headers.append(http::header::SET_COOKIE, cookie.to_header_value()?);
+        }
+        Ok(result)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::GetMetadata;
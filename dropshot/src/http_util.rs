@@ -5,9 +5,12 @@
 
 use bytes::BufMut;
 use bytes::Bytes;
+use http::StatusCode;
 use hyper::body::HttpBody;
 use serde::de::DeserializeOwned;
 use std::collections::BTreeMap;
+use std::time::Duration;
+use std::time::Instant;
 
 use super::error::HttpError;
 use crate::from_map::from_map;
@@ -21,14 +24,28 @@ pub const CONTENT_TYPE_JSON: &str = "application/json";
 /** MIME type for newline-delimited JSON data */
 pub const CONTENT_TYPE_NDJSON: &str = "application/x-ndjson";
 
+/**
+ * how long a client may take to send the start of a request body before the
+ * minimum transfer rate for request bodies is enforced
+ */
+const BODY_MIN_RATE_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
 /**
  * Reads the rest of the body from the request up to the given number of bytes.
  * If the body fits within the specified cap, a buffer is returned with all the
  * bytes read.  If not, an error is returned.
+ *
+ * If `timeout` is specified, the whole body must arrive within that long.  If
+ * `min_bytes_per_sec` is specified, then after a brief grace period, the body
+ * must arrive at least that quickly on average.  If the client is too slow, a
+ * 408 ("Request Timeout") error is returned without waiting for the rest of the
+ * body.
  */
 pub async fn http_read_body<T>(
     body: &mut T,
     cap: usize,
+    timeout: Option<Duration>,
+    min_bytes_per_sec: Option<u64>,
 ) -> Result<Bytes, HttpError>
 where
     T: HttpBody<Data = Bytes, Error = hyper::Error> + std::marker::Unpin,
@@ -45,10 +62,70 @@ where
      * work too?
      * TODO do we need to use saturating_add() here?
      */
+    let start_time = Instant::now();
+    let min_bytes_per_sec = min_bytes_per_sec.filter(|rate| *rate > 0);
     let mut parts = std::vec::Vec::new();
     let mut nbytesread: usize = 0;
-    while let Some(maybebuf) = body.data().await {
-        let buf = maybebuf?;
+    loop {
+        /*
+         * Figure out when the next chunk of data must arrive: either when the
+         * overall timeout expires or when the average transfer rate (counting
+         * what we've received so far) would drop below the minimum.
+         */
+        let body_deadline = timeout.map(|timeout| start_time + timeout);
+        let rate_deadline = min_bytes_per_sec.map(|rate| {
+            let expected =
+                Duration::from_secs_f64(nbytesread as f64 / rate as f64);
+            start_time + expected.max(BODY_MIN_RATE_GRACE_PERIOD)
+        });
+        let deadline = match (body_deadline, rate_deadline) {
+            (Some(body_deadline), Some(rate_deadline)) => {
+                Some(body_deadline.min(rate_deadline))
+            }
+            (body_deadline, rate_deadline) => body_deadline.or(rate_deadline),
+        };
+        let maybebuf = match deadline {
+            None => body.data().await,
+            Some(deadline) => {
+                let data =
+                    tokio::time::timeout_at(deadline.into(), body.data());
+                match data.await {
+                    Ok(maybebuf) => maybebuf,
+                    Err(_) => {
+                        let message = if Some(deadline) == body_deadline {
+                            format!(
+                                "request body was not received within {} ms",
+                                timeout.unwrap().as_millis()
+                            )
+                        } else {
+                            format!(
+                                "request body was received too slowly \
+                                 (minimum rate is {} bytes per second)",
+                                min_bytes_per_sec.unwrap()
+                            )
+                        };
+                        /*
+                         * The rest of the body may still be on its way, so
+                         * the connection can't be reused.
+                         */
+                        return Err(HttpError::for_client_error(
+                            None,
+                            StatusCode::REQUEST_TIMEOUT,
+                            message,
+                        )
+                        .with_header(
+                            http::header::CONNECTION,
+                            http::header::HeaderValue::from_static("close"),
+                        ));
+                    }
+                }
+            }
+        };
+
+        let buf = match maybebuf {
+            None => break,
+            Some(maybebuf) => maybebuf?,
+        };
         let bufsize = buf.len();
 
         if nbytesread + bufsize > cap {
@@ -149,9 +226,6 @@ pub fn http_extract_path_params<T: DeserializeOwned>(
          * even with our own deserializer, we'd also have to build our
          * own serde::de::Error impl in order to distinguish this particular
          * case.  For now, we resort to parsing the error message.
-         * TODO-correctness The error message produced in the type-error case
-         * (that end users will see) does not indicate which path parameter was
-         * invalid.  That's pretty bad for end users.
          */
         assert!(!message.starts_with("missing field: "));
         HttpError::for_bad_request(
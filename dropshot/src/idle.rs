@@ -0,0 +1,264 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Facilities for closing connections that have been idle for too long
+ *
+ * Hyper has no notion of an idle connection, so we wrap each connection that
+ * the server accepts in an `IdleTimeoutConn`, which watches the traffic on the
+ * connection.  Once nothing has been read or written for the configured idle
+ * timeout and no requests are in progress, reading from the connection reports
+ * end-of-file, which causes Hyper to close it.
+ */
+
+use hyper::server::accept::Accept;
+use slog::Logger;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+use tokio::time::Instant;
+use tokio::time::Sleep;
+
+/**
+ * Counts the requests in progress on a connection.  A connection with requests
+ * in progress is never considered idle, even if the handlers take longer than
+ * the idle timeout.
+ */
+#[derive(Debug, Default)]
+pub(crate) struct ConnectionActivity {
+    requests: AtomicUsize,
+}
+
+impl ConnectionActivity {
+    /**
+     * Record that handling of a request has begun.  The request is considered
+     * finished when the returned guard is dropped.
+     */
+    pub fn request_started(self: &Arc<Self>) -> RequestActivityGuard {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        RequestActivityGuard {
+            activity: Arc::clone(self),
+        }
+    }
+
+    fn is_busy(&self) -> bool {
+        self.requests.load(Ordering::SeqCst) > 0
+    }
+}
+
+/**
+ * Marks the end of a request in its `ConnectionActivity` when dropped.
+ */
+pub(crate) struct RequestActivityGuard {
+    activity: Arc<ConnectionActivity>,
+}
+
+impl Drop for RequestActivityGuard {
+    fn drop(&mut self) {
+        self.activity.requests.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/**
+ * IdleTimeoutAcceptor is a Hyper `Accept` implementation that wraps each
+ * connection accepted by another one in an `IdleTimeoutConn`.
+ */
+pub(crate) struct IdleTimeoutAcceptor<A> {
+    inner: A,
+    idle_timeout: Option<Duration>,
+    log: Logger,
+}
+
+impl<A> IdleTimeoutAcceptor<A> {
+    pub fn new(
+        inner: A,
+        idle_timeout: Option<Duration>,
+        log: Logger,
+    ) -> IdleTimeoutAcceptor<A> {
+        IdleTimeoutAcceptor {
+            inner,
+            idle_timeout,
+            log,
+        }
+    }
+}
+
+impl<A> Accept for IdleTimeoutAcceptor<A>
+where
+    A: Accept + Unpin,
+{
+    type Conn = IdleTimeoutConn<A::Conn>;
+    type Error = A::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = self.get_mut();
+        let idle_timeout = this.idle_timeout;
+        let log = &this.log;
+        Pin::new(&mut this.inner).poll_accept(cx).map(|result| {
+            result.map(|result| {
+                result.map(|conn| {
+                    IdleTimeoutConn::new(conn, idle_timeout, log.new(o!()))
+                })
+            })
+        })
+    }
+}
+
+/**
+ * A connection that reports end-of-file once it has been idle for too long.
+ * See the module-level documentation for details.
+ */
+pub struct IdleTimeoutConn<C> {
+    inner: C,
+    activity: Arc<ConnectionActivity>,
+    /** how long the connection may be idle, and when that time is up */
+    idle_timer: Option<(Duration, Pin<Box<Sleep>>)>,
+    /** whether the idle timeout has expired */
+    expired: bool,
+    log: Logger,
+}
+
+impl<C> IdleTimeoutConn<C> {
+    fn new(
+        inner: C,
+        idle_timeout: Option<Duration>,
+        log: Logger,
+    ) -> IdleTimeoutConn<C> {
+        IdleTimeoutConn {
+            inner,
+            activity: Arc::new(ConnectionActivity::default()),
+            idle_timer: idle_timeout.map(|timeout| {
+                (timeout, Box::pin(tokio::time::sleep(timeout)))
+            }),
+            expired: false,
+            log,
+        }
+    }
+
+    /** Returns the underlying connection. */
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /** Returns the tracker for requests in progress on this connection. */
+    pub(crate) fn activity(&self) -> Arc<ConnectionActivity> {
+        Arc::clone(&self.activity)
+    }
+
+    /** Record that there has been traffic on the connection. */
+    fn touch(&mut self) {
+        if let Some((timeout, sleep)) = &mut self.idle_timer {
+            sleep.as_mut().reset(Instant::now() + *timeout);
+        }
+    }
+
+    /**
+     * Returns whether the connection has been idle for too long.  If not, and
+     * if the connection is idle, arranges for the current task to be woken up
+     * when the idle timeout expires.
+     */
+    fn poll_expired(&mut self, cx: &mut Context<'_>) -> bool {
+        if self.expired {
+            return true;
+        }
+
+        if self.activity.is_busy() {
+            self.touch();
+            return false;
+        }
+
+        if let Some((timeout, sleep)) = &mut self.idle_timer {
+            if sleep.as_mut().poll(cx).is_ready() {
+                debug!(self.log, "closing idle connection";
+                    "idle_timeout_ms" => timeout.as_millis() as u64,
+                );
+                self.expired = true;
+            }
+        }
+        self.expired
+    }
+}
+
+impl<C> AsyncRead for IdleTimeoutConn<C>
+where
+    C: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.expired {
+            return Poll::Ready(Ok(()));
+        }
+
+        match Pin::new(&mut this.inner).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                this.touch();
+                Poll::Ready(result)
+            }
+            Poll::Pending if this.poll_expired(cx) => Poll::Ready(Ok(())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<C> AsyncWrite for IdleTimeoutConn<C>
+where
+    C: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if result.is_ready() {
+            this.touch();
+        }
+        result
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let result = Pin::new(&mut this.inner).poll_write_vectored(cx, bufs);
+        if result.is_ready() {
+            this.touch();
+        }
+        result
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -48,7 +48,6 @@
  * use dropshot::ConfigLogging;
  * use dropshot::ConfigLoggingLevel;
  * use dropshot::HttpServer;
- * use std::sync::Arc;
  *
  * #[tokio::main]
  * async fn main() -> Result<(), String> {
@@ -70,9 +69,10 @@
  *             &ConfigDropshot {
  *                 bind_address: "127.0.0.1:0".parse().unwrap(),
  *                 request_body_max_bytes: 1024,
+ *                 ..Default::default()
  *             },
  *             api,
- *             Arc::new(()),
+ *             (),
  *             &log,
  *         )
  *         .map_err(|error| format!("failed to start server: {}", error))?;
@@ -89,6 +89,16 @@
  * For a given `ApiDescription`, you can also print out an OpenAPI spec
  * describing the API.  See [`ApiDescription::openapi`].
  *
+ * Large APIs can be assembled from several `ApiDescription`s (e.g., one per
+ * module) by mounting each under a path prefix using
+ * [`ApiDescription::mount`].
+ *
+ * Requests whose path matches no endpoint fail with a 404 ("Not Found") error
+ * unless the `ApiDescription` has a fallback handler (see
+ * [`ApiDescription::fallback`]), which can serve them some other way (e.g., by
+ * serving a single-page web app).  The fallback handler doesn't appear in the
+ * OpenAPI spec.
+ *
  *
  * ## API Handler Functions
  *
@@ -138,7 +148,7 @@
  *     path = "/projects/project1",
  * }]
  * async fn myapi_projects_get_project(
- *     rqctx: Arc<RequestContext>,
+ *     rqctx: Arc<RequestContext<()>>,
  * ) -> Result<HttpResponseOk<Project>, HttpError>
  * {
  *    let project = Project { name: String::from("project1") };
@@ -187,21 +197,71 @@
  * ```ignore
  * #[endpoint {
  *     // Required fields
- *     method = { DELETE | GET | PATCH | POST | PUT },
+ *     method = { DELETE | GET | HEAD | OPTIONS | PATCH | POST | PUT },
  *     path = "/path/name/with/{named}/{variables}",
  *
  *     // Optional fields
  *     tags = [ "all", "your", "OpenAPI", "tags" ],
+ *     timeout_ms = 30000,
+ *     concurrency_limit = 16,
+ *     request_body_max_bytes = 1048576,
+ *     allow_encoded_slashes = true,
  * }]
  * ```
  *
  * This is where you specify the HTTP method and path (including path variables)
  * for the API endpoint. These are used as part of endpoint registration and
- * appear in the OpenAPI spec output.
+ * appear in the OpenAPI spec output.  A variable in the last segment of the
+ * path may be written `{name:.*}` to match the rest of the path, "/"
+ * characters included (e.g., `path = "/artifacts/{path:.*}"`).  Other
+ * variables may constrain the values they match with `uuid`, `integer`, or a
+ * regular expression (e.g., `{id:uuid}` or `{code:[A-Z]{3}}`).  Paths may
+ * overlap: when more than one route matches a request, literal segments take
+ * precedence over variables, variables with constraints take precedence over
+ * those without, and wildcards come last.  Routes that would still be
+ * ambiguous (like `/projects/{id}` and `/projects/{name}`) can't be
+ * registered, and neither can routes for the same method that would have the
+ * same path in the OpenAPI spec (like `/projects/{id:uuid}` and
+ * `/projects/{id}`, which differ only in a constraint).
+ *
+ * You don't usually need HEAD or OPTIONS endpoints.  Unless it's turned off
+ * with [`ApiDescription::automatic_methods()`], Dropshot handles HEAD requests
+ * using the GET endpoint for the same path (discarding the body of its
+ * response) and answers OPTIONS requests with the list of methods supported
+ * for the path.
+ *
+ * Request paths are percent-decoded one segment at a time before they're
+ * matched against routes, so path variables (and literal segments) may
+ * contain any UTF-8 text, including characters like "{" that must be encoded
+ * in a URI.  Empty segments are ignored, as are trailing "/" characters
+ * (unless [`ApiDescription::redirect_trailing_slashes()`] is used), and "."
+ * and ".." segments are resolved.
  *
  * The tags field is used to categorize API endpoints and only impacts the
  * OpenAPI spec output.
  *
+ * The timeout_ms field limits how long (in milliseconds) the handler function
+ * may run, overriding the server-wide `handler_timeout_ms` configuration.  If
+ * the handler does not complete in time, it is cancelled and the client
+ * receives a 503 ("Service Unavailable") error.
+ *
+ * The concurrency_limit field limits how many requests for this endpoint may
+ * be handled at once.  Requests beyond the limit are handled as described for
+ * the server-wide `max_concurrent_requests` configuration: they wait for up to
+ * `concurrency_queue_timeout_ms` and then fail with a 503 ("Service
+ * Unavailable") error.  The limit must be at least 1.
+ *
+ * The request_body_max_bytes field limits the size of the request body for
+ * this endpoint, overriding the server-wide `request_body_max_bytes`
+ * configuration.  This is mostly useful for endpoints that take a
+ * `StreamingBody`.
+ *
+ * The allow_encoded_slashes field allows the endpoint's path variables to
+ * contain "/" characters, which clients send percent-encoded as "%2F" so that
+ * they don't separate segments of the path.  Without it, such requests fail
+ * with a 400 ("Bad Request") error.  (Wildcard variables match "/" characters
+ * that separate segments either way.)
+ *
  *
  * ### Function parameters
  *
@@ -209,18 +269,34 @@
  *
  * ```ignore
  * async fn f(
- *      rqctx: Arc<RequestContext>,
+ *      rqctx: Arc<RequestContext<Context>>,
  *      [query_params: Query<Q>,]
  *      [path_params: Path<P>,]
+ *      [header_params: Header<H>,]
+ *      [cookie_params: Cookies<C>,]
  *      [body_param: TypedBody<J>,]
  *      [body_param: UntypedBody<J>,]
+ *      [body_param: StreamingBody,]
  * ) -> Result<HttpResponse*, HttpError>
  * ```
  *
+ * `Context` is the type of the server-wide state that was provided when the
+ * server was created (the `private` argument to [`HttpServer::new()`]), and
+ * handlers access it using [`RequestContext::context()`].  Servers that don't
+ * need any state use `()`.  All of the handlers registered with an
+ * `ApiDescription` must use the same `Context` type; registering one that
+ * doesn't is a compile-time error.
+ *
+ * The `RequestContext` also has an `extensions` map in which extractors and
+ * handlers can store values of any type for the duration of the request.  For
+ * example, a custom extractor that authenticates the client might store the
+ * authenticated user there for the handler to use.
+ *
  * Other than the RequestContext, parameters may appear in any order.  The types
- * `Query`, `Path`, `TypedBody`, and `UntypedBody` are called **Extractors**
- * because they cause information to be pulled out of the request and made
- * available to the handler function.
+ * `Query`, `Path`, `Header`, `Cookies`, `TypedBody`, `UntypedBody`, and
+ * `StreamingBody` are called **Extractors** because they cause information to
+ * be pulled out of the request and made available to the handler function.  A
+ * handler function may take up to 16 extractors.
  *
  * * [`Query`]`<Q>` extracts parameters from a query string, deserializing them
  *   into an instance of type `Q`. `Q` must implement `serde::Deserialize` and
@@ -228,15 +304,30 @@
  * * [`Path`]`<P>` extracts parameters from HTTP path, deserializing them into
  *   an instance of type `P`. `P` must implement `serde::Deserialize` and
  *   `schemars::JsonSchema`.
+ * * [`Header`]`<H>` extracts values from the request headers, deserializing
+ *   them into an instance of type `H`. `H` must implement `serde::Deserialize`
+ *   and `schemars::JsonSchema`.  Header names are matched in lowercase, so
+ *   fields for headers like "X-Request-Count" should be renamed accordingly
+ *   (e.g., with `#[serde(rename_all = "kebab-case")]`).
+ * * [`Cookies`]`<C>` extracts the cookies sent in the request's `Cookie`
+ *   headers, deserializing them into an instance of type `C`. `C` must
+ *   implement `serde::Deserialize` and `schemars::JsonSchema`.
  * * [`TypedBody`]`<J>` extracts content from the request body by parsing the
  *   body as JSON and deserializing it into an instance of type `J`. `J` must
  *   implement `serde::Deserialize` and `schemars::JsonSchema`.
  * * [`UntypedBody`] extracts the raw bytes of the request body.
- *
- * If the handler takes a `Query<Q>`, `Path<P>`, `TypedBody<J>`, or
- * `UntypedBody`, and the corresponding extraction cannot be completed, the
- * request fails with status code 400 and an error message reflecting a
- * validation error.
+ * * [`StreamingBody`] provides the raw bytes of the request body as a stream
+ *   of chunks as they arrive, so that bodies too large to buffer in memory
+ *   can be processed incrementally (e.g., written to a file).  Like
+ *   `UntypedBody`, it's described as `application/octet-stream` in the
+ *   OpenAPI spec.
+ *
+ * If the handler takes a `Query<Q>`, `Path<P>`, `Header<H>`, `Cookies<C>`,
+ * `TypedBody<J>`, or `UntypedBody`, and the corresponding extraction cannot be
+ * completed, the request fails with status code 400 and an error message
+ * reflecting a validation error.  For `Path<P>`, `Header<H>`, and
+ * `Cookies<C>`, the message names the parameter, header, or cookie that was
+ * missing or could not be parsed.
  *
  * As with any serde-deserializable type, you can make fields optional by having
  * the corresponding property of the type be an `Option`.  Here's an example of
@@ -262,7 +353,7 @@
  * }
  *
  * async fn myapi_projects_get(
- *     _: Arc<RequestContext>,
+ *     _: Arc<RequestContext<()>>,
  *     query: Query<MyQueryArgs>)
  *     -> Result<Response<Body>, HttpError>
  * {
@@ -308,6 +399,11 @@
  * | [`HttpResponseDeleted`] | 204 |
  * | [`HttpResponseUpdatedNoContent`] | 204 |
  *
+ * Any of these can be wrapped in [`HttpResponseSetCookies`] to set cookies in
+ * the user agent.  Each [`SetCookie`] added to it produces a `Set-Cookie`
+ * header, with attributes like `SameSite`, `Secure`, `HttpOnly`, and an
+ * expiry.  The status code and body are unchanged.
+ *
  * In situations where the response schema is not fixed, the endpoint should
  * return `Response<Body>`, which also implements `HttpResponse`. Note that
  * the OpenAPI spec will not include any status code or type information in
@@ -315,9 +411,31 @@
  *
  * ## What about generic handlers that run on all requests?
  *
- * There's no mechanism in Dropshot for this.  Instead, it's recommended that
- * users commonize code using regular Rust functions and calling them.  See the
- * design notes in the README for more on this.
+ * Code that should run before or after the handlers for many endpoints can be
+ * implemented as [`Middleware`] and registered with the `ApiDescription`, for
+ * all endpoints ([`ApiDescription::middleware()`]) or those having a
+ * particular tag ([`ApiDescription::tag_middleware()`]), or with a single
+ * endpoint ([`ApiEndpoint::middleware()`]).  Middleware can examine and modify
+ * the request, fail it with an `HttpError` before the handler runs, and modify
+ * the response afterwards.  See [`Middleware`] for the order in which it runs.
+ *
+ * Still, when a handler depends on the result of some common step (e.g.,
+ * authenticating the user), it's recommended to make that a regular Rust
+ * function (or an extractor) that the handler calls so that the dependency is
+ * explicit.  See the design notes in the README for more on this.
+ *
+ * Code that doesn't need to know which endpoint a request is for (e.g.,
+ * generic request logging, compression, or tracing) can instead be written as
+ * a [Tower](https://docs.rs/tower) layer and applied to the whole server with
+ * [`HttpServer::new_with_layer()`].  This also makes it possible to use the
+ * many layers that the Tower ecosystem already provides.
+ *
+ * ## Using Dropshot within another server
+ *
+ * [`ApiService`] is a Hyper (Tower) `Service` that handles requests using the
+ * endpoints in an `ApiDescription` without creating an `HttpServer`.  This
+ * allows a Dropshot API to be served alongside other services within a larger
+ * Hyper application.
  *
  *
  * ## Support for paginated resources
@@ -476,7 +594,7 @@
  *     path = "/list_stuff"
  * }]
  * async fn my_list_api(
- *     rqctx: Arc<RequestContext>,
+ *     rqctx: Arc<RequestContext<()>>,
  *     pag_params: Query<PaginationParams<MyScanParams, MyPageSelector>>,
  *     extra_params: Query<MyExtraQueryParams>,
  * ) -> Result<HttpResponseOk<ResultsPage<String>>, HttpError>
@@ -500,14 +618,22 @@
 
 mod api_description;
 mod config;
+mod cookie;
 mod error;
 mod from_map;
 mod handler;
 mod http_util;
+mod idle;
 mod logging;
+mod middleware;
 mod pagination;
 mod router;
 mod server;
+mod shutdown;
+mod socket_activation;
+mod tls;
+#[cfg(unix)]
+mod unix;
 
 pub mod test_util;
 
@@ -521,18 +647,29 @@ pub use api_description::ApiEndpointParameterLocation;
 pub use api_description::ApiEndpointResponse;
 pub use api_description::OpenApiDefinition;
 pub use config::ConfigDropshot;
+pub use config::ConfigHttp2;
+pub use config::ConfigTls;
+pub use config::ConfigTlsVersion;
+pub use config::ConfigUnixSocket;
+pub use cookie::SameSite;
+pub use cookie::SetCookie;
 pub use error::HttpError;
 pub use error::HttpErrorResponseBody;
+pub use handler::Cookies;
 pub use handler::Extractor;
+pub use handler::Header;
 pub use handler::HttpResponse;
 pub use handler::HttpResponseAccepted;
 pub use handler::HttpResponseCreated;
 pub use handler::HttpResponseDeleted;
 pub use handler::HttpResponseOk;
+pub use handler::HttpResponseSetCookies;
 pub use handler::HttpResponseUpdatedNoContent;
 pub use handler::Path;
 pub use handler::Query;
 pub use handler::RequestContext;
+pub use handler::RequestContextArgument;
+pub use handler::StreamingBody;
 pub use handler::TypedBody;
 pub use handler::UntypedBody;
 pub use http_util::CONTENT_TYPE_JSON;
@@ -542,12 +679,22 @@ pub use http_util::HEADER_REQUEST_ID;
 pub use logging::ConfigLogging;
 pub use logging::ConfigLoggingIfExists;
 pub use logging::ConfigLoggingLevel;
+pub use middleware::Middleware;
 pub use pagination::EmptyScanParams;
 pub use pagination::PaginationOrder;
 pub use pagination::PaginationParams;
 pub use pagination::ResultsPage;
 pub use pagination::WhichPage;
+pub use server::ApiService;
+pub use server::ConnectionInfo;
+pub use server::ConnectionPeer;
 pub use server::HttpServer;
+pub use server::ServerContext;
+pub use server::ServerRequestHandler;
+pub use server::TlsConn;
+pub use server::UnixPeerCredentials;
+pub use socket_activation::listeners_from_env;
+pub use tls::ClientCertificate;
 
 /*
  * Users of the `endpoint` macro need `http::Method` available.
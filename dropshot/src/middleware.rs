@@ -0,0 +1,127 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Middleware: code that runs before and after the handler for each request
+ */
+
+use super::error::HttpError;
+use super::handler::HttpHandlerResult;
+use super::handler::RequestContext;
+use super::handler::RouteHandler;
+use super::server::ServerContext;
+
+use async_trait::async_trait;
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::fmt::Result as FmtResult;
+use std::sync::Arc;
+
+/**
+ * `Middleware` implements behavior common to many endpoints (e.g.,
+ * authentication, auditing, or adding response headers) that would otherwise
+ * be duplicated in each handler function.
+ *
+ * Middleware is registered with an `ApiDescription` for all of its endpoints
+ * ([`ApiDescription::middleware()`](crate::ApiDescription::middleware)), for
+ * the endpoints having a particular tag
+ * ([`ApiDescription::tag_middleware()`](crate::ApiDescription::tag_middleware)),
+ * or for a single endpoint
+ * ([`ApiEndpoint::middleware()`](crate::ApiEndpoint::middleware)).  For each
+ * request, the middleware that applies to the endpoint forms a chain: first
+ * the API-wide middleware, then the middleware for each of the endpoint's tags,
+ * then the endpoint's own middleware, each group in the order in which it was
+ * registered.
+ *
+ * Dropshot invokes [`Middleware::request()`] for each middleware in the chain
+ * in order, then runs the handler.  If any of these returns an error, the rest
+ * of the chain and the handler are skipped.  Then Dropshot invokes
+ * [`Middleware::response()`] in reverse order for each middleware whose
+ * `request()` was invoked (including one that returned an error).  So
+ * middleware that comes earlier in the chain "wraps" middleware that comes
+ * later.
+ *
+ * Middleware runs after the request has been routed, so requests for which
+ * there's no endpoint don't run any middleware.  Time spent in middleware
+ * counts against the handler's timeout.
+ */
+#[async_trait]
+pub trait Middleware<Context: ServerContext>: Send + Sync + 'static {
+    /**
+     * Returns a name for this middleware to use in log entries.  By default,
+     * this is the name of the type.
+     */
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
+    /**
+     * Invoked before the handler runs.  This may examine or modify the request
+     * (`rqctx.request`) or add values to `rqctx.extensions` for later
+     * middleware or the handler to use.  Returning an error causes the request
+     * to fail with that error without running the handler.
+     */
+    async fn request(
+        &self,
+        _rqctx: &Arc<RequestContext<Context>>,
+    ) -> Result<(), HttpError> {
+        Ok(())
+    }
+
+    /**
+     * Invoked after the handler (or a later middleware) has produced `result`,
+     * which is either the response or the error that the request failed with.
+     * This may modify the response (e.g., to add headers), replace it, or turn
+     * it into an error.
+     */
+    async fn response(
+        &self,
+        _rqctx: &Arc<RequestContext<Context>>,
+        result: HttpHandlerResult,
+    ) -> HttpHandlerResult {
+        result
+    }
+}
+
+impl<Context: ServerContext> Debug for dyn Middleware<Context> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.name())
+    }
+}
+
+/**
+ * Runs the request through the middleware chain `middleware` and `handler`, as
+ * described for [`Middleware`].  If the request fails because of a middleware
+ * (rather than the handler), the name of that middleware is stored into
+ * `failed_middleware` so that it can be logged.
+ */
+pub(crate) async fn middleware_dispatch<Context: ServerContext>(
+    rqctx: Arc<RequestContext<Context>>,
+    middleware: &[Arc<dyn Middleware<Context>>],
+    handler: &dyn RouteHandler<Context>,
+    failed_middleware: &mut Option<String>,
+) -> HttpHandlerResult {
+    let mut nstarted = 0;
+    let mut result = Ok(());
+    for m in middleware {
+        nstarted += 1;
+        if let Err(error) = m.request(&rqctx).await {
+            *failed_middleware = Some(m.name().to_string());
+            result = Err(error);
+            break;
+        }
+    }
+
+    let mut result = match result {
+        Ok(()) => handler.handle_request(Arc::clone(&rqctx)).await,
+        Err(error) => Err(error),
+    };
+
+    for m in middleware[..nstarted].iter().rev() {
+        let succeeded = result.is_ok();
+        result = m.response(&rqctx, result).await;
+        if succeeded && result.is_err() {
+            *failed_middleware = Some(m.name().to_string());
+        }
+    }
+
+    result
+}
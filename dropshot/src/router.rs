@@ -4,13 +4,31 @@
  */
 
 use super::error::HttpError;
+use super::handler::HttpHandlerResult;
+use super::handler::RequestContext;
 use super::handler::RouteHandler;
+use super::middleware::Middleware;
+use super::server::check_limit;
+use super::server::ServerContext;
 
 use crate::ApiEndpoint;
+use async_trait::async_trait;
+use http::header::HeaderValue;
 use http::Method;
 use http::StatusCode;
+use hyper::Body;
+use hyper::Response;
+use percent_encoding::percent_decode_str;
+use percent_encoding::utf8_percent_encode;
+use percent_encoding::AsciiSet;
+use percent_encoding::CONTROLS;
+use regex::Regex;
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use uuid::Uuid;
 
 /**
  * `HttpRouter` is a simple data structure for routing incoming HTTP requests to
@@ -30,6 +48,53 @@ use std::collections::BTreeSet;
  * `"bar456"`).  Only one segment is matched per variable, so `"/foo/{bar}"`
  * will not match `"/foo/123/456"`.
  *
+ * A variable may be followed by a constraint on the values it matches, as in
+ * `"/foo/{bar:uuid}"`.  The constraint may be `uuid`, `integer` (an optional
+ * "-" followed by decimal digits), or a regular expression that must match the
+ * whole segment (e.g., `{bar:[a-z]+}`).  See `PathConstraint`.
+ *
+ * A variable in the last segment may instead be written `{name:.*}` to match
+ * the rest of the path, including any "/" characters.  A handler registered for
+ * `"/artifacts/{path:.*}"` matches `"/artifacts/a/b/c.txt"` (with `"path"`
+ * assigned to `"a/b/c.txt"`) and even `"/artifacts"` (with `"path"` assigned to
+ * the empty string), unless a handler was registered for `"/artifacts"`
+ * itself.
+ *
+ * Routes may overlap.  For example, `"/projects/new"`, `"/projects/{id:uuid}"`,
+ * and `"/projects/{name}"` may all be registered.  When more than one of them
+ * matches a segment of a request's path, the router tries them in order:
+ * literal strings first, then variables with constraints (`uuid`, then
+ * `integer`, then a regular expression), then a variable without a
+ * constraint, and finally a wildcard.  If the rest of the path can't be matched
+ * that way, the router goes back and tries the next of them.  The first route
+ * that matches the whole path is the one used, even if it has no handler for
+ * the request's method.  So if only `POST "/projects/new"` and
+ * `GET "/projects/{name}"` are registered, `GET "/projects/new"` fails with a
+ * 405 ("Method Not Allowed") error.
+ *
+ * The OpenAPI definition can't express constraints or wildcards, so routes
+ * for the same method whose paths differ only in those (like
+ * `"/projects/{id:uuid}"` and `"/projects/{id}"`) can't both be registered.
+ * Giving the variables different names (like `"/projects/{name}"`) makes them
+ * distinct.
+ *
+ * Unless automatic methods are turned off (see `set_automatic_methods()`), the
+ * router also handles HEAD requests for resources that have a GET handler but
+ * no HEAD handler (using the GET handler) and OPTIONS requests for resources
+ * that have no OPTIONS handler (listing the methods that the resource
+ * supports).  Requests for a method that a resource doesn't support fail with
+ * a 405 ("Method Not Allowed") error that lists the supported methods in an
+ * "Allow" header.
+ *
+ * Request paths are normalized before they're matched against routes (see
+ * `request_path_to_segments()`): each segment is percent-decoded separately,
+ * so `"/foo/%7Bbar%7D"` matches a route for `"/foo/{bar}"` with `"bar"`
+ * assigned to `"{bar}"`.  A decoded segment may contain a "/" (if it was sent
+ * as "%2F"), but such requests fail with a 400 ("Bad Request") error unless
+ * the endpoint that they match allows encoded slashes.  A trailing "/" is
+ * ignored, unless the router is configured to redirect such requests (see
+ * `set_redirect_trailing_slash()`).
+ *
  * The implementation here is essentially a trie where edges represent segments
  * of the URI path.  ("Segments" here are chunks of the path separated by one or
  * more "/" characters.)  To register or look up the path `"/foo/bar/baz"`, we
@@ -41,14 +106,19 @@ use std::collections::BTreeSet;
  * These could be relaxed, but it's not clear that's useful, and enforcing them
  * makes it easier to catch some types of bugs:
  *
- * * A particular resource (node) may have child resources (edges) with either
- *   literal path segments or variable path segments, but not both.  For
- *   example, you can't register both `"/projects/{id}"` and
- *   `"/projects/default"`.
+ * * A particular resource (node) may have at most one child resource (edge)
+ *   for each kind of variable: one without a constraint, one each for `uuid`
+ *   and `integer`, one with a regular expression, and one wildcard.  Since we
+ *   can't tell which of two regular expressions ought to take precedence, you
+ *   can't register both `"/items/{id:[0-9]+}"` and `"/items/{id:[0-9a-f]+}"`.
+ *
+ * * A wildcard path segment must be the last segment of the path.
  *
  * * If a given resource has an edge with a variable name, all routes through
- *   this node must use the same name for that variable.  That is, you can't
+ *   this edge must use the same name for that variable.  That is, you can't
  *   define routes for `"/projects/{id}"` and `"/projects/{project_id}/info"`.
+ *   (You can define routes for `"/projects/{id:uuid}"` and
+ *   `"/projects/{name}/info"`, since those are different edges.)
  *
  * * A given path cannot use the same variable name twice.  For example, you
  *   can't register path `"/projects/{id}/instances/{id}"`.
@@ -61,9 +131,25 @@ use std::collections::BTreeSet;
  *   read-only.  This behavior isn't enforced by `HttpRouter`.
  */
 #[derive(Debug)]
-pub struct HttpRouter {
+pub struct HttpRouter<Context: ServerContext> {
     /** root of the trie */
-    root: Box<HttpRouterNode>,
+    root: Box<HttpRouterNode<Context>>,
+    /** whether HEAD and OPTIONS requests are handled automatically */
+    automatic_methods: bool,
+    /** whether paths with a trailing "/" are redirected to those without */
+    redirect_trailing_slash: bool,
+    /** handler for requests whose path matches no route */
+    fallback: Option<FallbackHandler<Context>>,
+}
+
+/**
+ * Handler (and the middleware that runs around it) for requests whose path
+ * matches no route.  See `HttpRouter::set_fallback()`.
+ */
+#[derive(Debug)]
+struct FallbackHandler<Context: ServerContext> {
+    handler: Box<dyn RouteHandler<Context>>,
+    middleware: Vec<Arc<dyn Middleware<Context>>>,
 }
 
 /**
@@ -73,38 +159,67 @@ pub struct HttpRouter {
  * number of variable assignments (e.g., `"/projects/123/instances"` and
  * `"/projects/456/instances"`).
  *
- * Edges of the tree come in one of type types: edges for literal strings and
- * edges for variable strings.  A given node has either literal string edges or
- * variable edges, but not both.  However, we don't necessarily know what type
- * of outgoing edges a node will have when we create it.
+ * Edges of the tree come in three types: edges for literal strings, edges for
+ * variable strings, and edges for wildcards matching the rest of the path.  A
+ * given node may have any combination of them.
  */
 #[derive(Debug)]
-struct HttpRouterNode {
+struct HttpRouterNode<Context: ServerContext> {
     /** Handlers, etc. for each of the HTTP methods defined for this node. */
-    methods: BTreeMap<String, ApiEndpoint>,
+    methods: BTreeMap<String, ApiEndpoint<Context>>,
+    /**
+     * Slots for requests in progress for each of the HTTP methods defined for
+     * this node whose endpoint limits its concurrency.
+     */
+    concurrency_limits: BTreeMap<String, Semaphore>,
+    /**
+     * Handles OPTIONS requests for this node if there's no endpoint for them
+     * and automatic methods are turned on.
+     */
+    options: OptionsHandler,
     /** Edges linking to child nodes. */
-    edges: Option<HttpRouterEdges>,
+    edges: HttpRouterEdges<Context>,
 }
 
 #[derive(Debug)]
-enum HttpRouterEdges {
+struct HttpRouterEdges<Context: ServerContext> {
     /** Outgoing edges for literal paths. */
-    Literals(BTreeMap<String, Box<HttpRouterNode>>),
-    /** Outgoing edges for variable-named paths. */
-    Variable(String, Box<HttpRouterNode>),
+    literals: BTreeMap<String, Box<HttpRouterNode<Context>>>,
+    /**
+     * Outgoing edges for variable-named paths, in the order in which they're
+     * tried (see `PathConstraint::precedence()`).
+     */
+    variables: Vec<HttpRouterVariableEdge<Context>>,
+    /** Outgoing edge for a variable that matches the rest of the path. */
+    wildcard: Option<(String, Box<HttpRouterNode<Context>>)>,
+}
+
+#[derive(Debug)]
+struct HttpRouterVariableEdge<Context: ServerContext> {
+    varname: String,
+    /** restriction on the segments matched by this edge, if any */
+    constraint: Option<PathConstraint>,
+    node: Box<HttpRouterNode<Context>>,
 }
 
 /**
  * `PathSegment` represents a segment in a URI path when the router is being
  * configured.  Each segment may be either a literal string or a variable (the
- * latter indicated by being wrapped in braces.
+ * latter indicated by being wrapped in braces).  A variable's name may be
+ * followed by ":" and a constraint (e.g., `{id:uuid}`).  The special constraint
+ * `.*` (e.g., `{path:.*}`) makes the variable a wildcard that matches the rest
+ * of the path.
  */
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum PathSegment {
     /** a path segment for a literal string */
     Literal(String),
     /** a path segment for a variable */
     Varname(String),
+    /** a path segment for a variable whose values are constrained */
+    VarnameConstrained(String, PathConstraint),
+    /** a path segment for a variable matching the rest of the path */
+    VarnameWildcard(String),
 }
 
 impl PathSegment {
@@ -112,28 +227,130 @@ impl PathSegment {
      * Given a `&str` representing a path segment from a Uri, return a
      * PathSegment.  This is used to parse a sequence of path segments to the
      * corresponding `PathSegment`, which basically means determining whether
-     * it's a variable or a literal.
+     * it's a variable or a literal.  Panics if the segment is invalid, so it's
+     * only for paths that have already been checked with `parse()` (like
+     * those of registered routes).
      */
     pub fn from(segment: &str) -> PathSegment {
-        if segment.starts_with('{') || segment.ends_with('}') {
-            assert!(
-                segment.starts_with('{'),
-                "{}",
-                "HTTP URI path segment variable missing leading \"{\""
-            );
-            assert!(
-                segment.ends_with('}'),
-                "{}",
-                "HTTP URI path segment variable missing trailing \"}\""
-            );
-            assert!(
-                segment.len() > 2,
-                "HTTP URI path segment variable name cannot be empty"
-            );
-
-            PathSegment::Varname((&segment[1..segment.len() - 1]).to_string())
-        } else {
-            PathSegment::Literal(segment.to_string())
+        PathSegment::parse(segment).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    /**
+     * Like `from()`, but returns an error describing the problem if the
+     * segment is invalid (e.g., a variable with an empty name, or whose
+     * constraint is not a valid regular expression).
+     */
+    pub fn parse(segment: &str) -> Result<PathSegment, String> {
+        if !segment.starts_with('{') && !segment.ends_with('}') {
+            return Ok(PathSegment::Literal(segment.to_string()));
+        }
+        if !segment.starts_with('{') {
+            return Err(String::from(
+                "HTTP URI path segment variable missing leading \"{\"",
+            ));
+        }
+        if !segment.ends_with('}') {
+            return Err(String::from(
+                "HTTP URI path segment variable missing trailing \"}\"",
+            ));
+        }
+
+        let var = &segment[1..segment.len() - 1];
+        let (name, pattern) = match var.split_once(':') {
+            None => (var, None),
+            Some((name, pattern)) => (name, Some(pattern)),
+        };
+        if name.is_empty() {
+            return Err(String::from(
+                "HTTP URI path segment variable name cannot be empty",
+            ));
+        }
+        Ok(match pattern {
+            None => PathSegment::Varname(name.to_string()),
+            Some(".*") => PathSegment::VarnameWildcard(name.to_string()),
+            Some(pattern) => PathSegment::VarnameConstrained(
+                name.to_string(),
+                PathConstraint::parse(name, pattern)?,
+            ),
+        })
+    }
+}
+
+/**
+ * `PathConstraint` restricts the path segments that a variable matches.  It's
+ * specified after the variable's name, as in `{id:uuid}`.
+ */
+#[derive(Clone, Debug)]
+pub enum PathConstraint {
+    /** `uuid`: matches a UUID (e.g., "e7de9ff9-5e6c-4d47-a58d-6fcd3b7d0a6c") */
+    Uuid,
+    /** `integer`: matches an optional "-" followed by decimal digits */
+    Integer,
+    /**
+     * any other pattern: matches segments that the regular expression matches
+     * in their entirety
+     */
+    Regex(String, Regex),
+}
+
+impl PathConstraint {
+    /**
+     * Parses the constraint `pattern` given for variable `varname`.  Returns an
+     * error if `pattern` is not a valid regular expression.
+     */
+    fn parse(varname: &str, pattern: &str) -> Result<PathConstraint, String> {
+        match pattern {
+            "uuid" => Ok(PathConstraint::Uuid),
+            "integer" => Ok(PathConstraint::Integer),
+            _ => match Regex::new(&format!("^(?:{})$", pattern)) {
+                Ok(regex) => {
+                    Ok(PathConstraint::Regex(pattern.to_string(), regex))
+                }
+                Err(error) => Err(format!(
+                    "HTTP URI path segment variable \"{}\" has invalid \
+                     pattern \"{}\": {}",
+                    varname, pattern, error
+                )),
+            },
+        }
+    }
+
+    /**
+     * Returns the constraint as it was written in the path.
+     */
+    pub fn pattern(&self) -> &str {
+        match self {
+            PathConstraint::Uuid => "uuid",
+            PathConstraint::Integer => "integer",
+            PathConstraint::Regex(pattern, _) => pattern,
+        }
+    }
+
+    /**
+     * Returns whether `segment` satisfies the constraint.
+     */
+    pub fn matches(&self, segment: &str) -> bool {
+        match self {
+            PathConstraint::Uuid => Uuid::parse_str(segment).is_ok(),
+            PathConstraint::Integer => {
+                let digits = segment.strip_prefix('-').unwrap_or(segment);
+                !digits.is_empty() && digits.bytes().all(|c| c.is_ascii_digit())
+            }
+            PathConstraint::Regex(_, regex) => regex.is_match(segment),
+        }
+    }
+
+    /**
+     * Returns where variable edges with `constraint` are tried relative to
+     * other variable edges from the same node: those with lower values first.
+     * No two edges from a node may have the same precedence.
+     */
+    fn precedence(constraint: &Option<PathConstraint>) -> u8 {
+        match constraint {
+            Some(PathConstraint::Uuid) => 0,
+            Some(PathConstraint::Integer) => 1,
+            Some(PathConstraint::Regex(..)) => 2,
+            None => 3,
         }
     }
 }
@@ -145,124 +362,415 @@ impl PathSegment {
  * corresponding values in the actual path.
  */
 #[derive(Debug)]
-pub struct RouterLookupResult<'a> {
-    pub handler: &'a dyn RouteHandler,
+pub struct RouterLookupResult<'a, Context: ServerContext> {
+    pub handler: &'a dyn RouteHandler<Context>,
+    /** middleware that runs around the handler, outermost first */
+    pub middleware: &'a [Arc<dyn Middleware<Context>>],
+    /** how long the handler may run, if the endpoint specifies a limit */
+    pub timeout: Option<Duration>,
+    /**
+     * slots for requests in progress, if the endpoint limits its concurrency
+     */
+    pub concurrency_limit: Option<&'a Semaphore>,
+    /**
+     * maximum number of bytes allowed in the request body, if the endpoint
+     * specifies a limit
+     */
+    pub request_body_max_bytes: Option<usize>,
     pub variables: BTreeMap<String, String>,
+    /**
+     * whether the handler is the GET handler being used for a HEAD request,
+     * in which case the body of its response should be discarded
+     */
+    pub discard_body: bool,
 }
 
-impl HttpRouterNode {
+impl<Context: ServerContext> HttpRouterNode<Context> {
     pub fn new() -> Self {
         HttpRouterNode {
             methods: BTreeMap::new(),
-            edges: None,
+            concurrency_limits: BTreeMap::new(),
+            options: OptionsHandler::new(""),
+            edges: HttpRouterEdges {
+                literals: BTreeMap::new(),
+                variables: Vec::new(),
+                wildcard: None,
+            },
+        }
+    }
+
+    /**
+     * Returns the value of the "Allow" header for this node: the methods for
+     * which it has endpoints, plus HEAD (if it has a GET endpoint) and OPTIONS
+     * if those are handled automatically.
+     */
+    fn allowed_methods(&self, automatic_methods: bool) -> String {
+        let mut methods =
+            self.methods.keys().map(String::as_str).collect::<BTreeSet<_>>();
+        if automatic_methods {
+            if methods.contains("GET") {
+                methods.insert("HEAD");
+            }
+            methods.insert("OPTIONS");
+        }
+        methods.into_iter().collect::<Vec<_>>().join(", ")
+    }
+
+    /**
+     * Returns the child nodes of this node, in the order in which they're tried
+     * when looking up a route.
+     */
+    fn children(&self) -> impl Iterator<Item = &HttpRouterNode<Context>> {
+        let edges = &self.edges;
+        edges
+            .literals
+            .values()
+            .chain(edges.variables.iter().map(|edge| &edge.node))
+            .chain(edges.wildcard.iter().map(|(_, node)| node))
+            .map(|node| &**node)
+    }
+
+    fn visit_endpoints_mut(
+        &mut self,
+        visitor: &mut dyn FnMut(&mut ApiEndpoint<Context>),
+    ) {
+        self.methods.values_mut().for_each(|endpoint| visitor(endpoint));
+        let edges = &mut self.edges;
+        for node in edges.literals.values_mut() {
+            node.visit_endpoints_mut(visitor);
+        }
+        for edge in &mut edges.variables {
+            edge.node.visit_endpoints_mut(visitor);
+        }
+        if let Some((_, node)) = &mut edges.wildcard {
+            node.visit_endpoints_mut(visitor);
+        }
+    }
+
+    fn into_endpoints(self, endpoints: &mut Vec<ApiEndpoint<Context>>) {
+        endpoints.extend(self.methods.into_values());
+        let edges = self.edges;
+        for (_, node) in edges.literals {
+            node.into_endpoints(endpoints);
+        }
+        for edge in edges.variables {
+            edge.node.into_endpoints(endpoints);
+        }
+        if let Some((_, node)) = edges.wildcard {
+            node.into_endpoints(endpoints);
         }
     }
+
+    /**
+     * Returns the first endpoint registered at or below this node, which is
+     * used to describe conflicts with the routes through it.
+     */
+    fn first_endpoint(&self) -> Option<&ApiEndpoint<Context>> {
+        self.methods
+            .values()
+            .next()
+            .or_else(|| self.children().find_map(|node| node.first_endpoint()))
+    }
+
+    /**
+     * Finds the node with handlers that matches the path `segments` relative to
+     * this one, trying this node's edges in order of precedence (see
+     * `HttpRouter`).  On success, the values of the variables along the way are
+     * added to `variables`.
+     */
+    fn find<'a>(
+        &'a self,
+        segments: &[&str],
+        variables: &mut BTreeMap<String, String>,
+    ) -> Option<&'a HttpRouterNode<Context>> {
+        let edges = &self.edges;
+        let (segment, rest) = match segments.split_first() {
+            Some(split) => split,
+            /*
+             * A wildcard matches the rest of the path, however many segments
+             * that is.  That includes none at all (e.g., "/artifacts" for
+             * "/artifacts/{path:.*}"), but only when there's nothing registered
+             * for the resource itself.
+             */
+            None if !self.methods.is_empty() => return Some(self),
+            None => {
+                return edges.wildcard.as_ref().map(|(varname, node)| {
+                    variables.insert(varname.clone(), String::new());
+                    &**node
+                });
+            }
+        };
+
+        if let Some(node) = edges.literals.get(*segment) {
+            if let Some(found) = node.find(rest, variables) {
+                return Some(found);
+            }
+        }
+
+        for edge in &edges.variables {
+            if let Some(constraint) = &edge.constraint {
+                if !constraint.matches(segment) {
+                    continue;
+                }
+            }
+            if let Some(found) = edge.node.find(rest, variables) {
+                variables.insert(edge.varname.clone(), segment.to_string());
+                return Some(found);
+            }
+        }
+
+        edges.wildcard.as_ref().map(|(varname, node)| {
+            variables.insert(varname.clone(), segments.join("/"));
+            &**node
+        })
+    }
 }
 
-impl HttpRouter {
+impl<Context: ServerContext> HttpRouter<Context> {
     /**
      * Returns a new `HttpRouter` with no routes configured.
      */
     pub fn new() -> Self {
         HttpRouter {
             root: Box::new(HttpRouterNode::new()),
+            automatic_methods: true,
+            redirect_trailing_slash: false,
+            fallback: None,
         }
     }
 
+    /**
+     * Sets whether HEAD and OPTIONS requests are handled automatically for
+     * resources that have no endpoints for them (see the `HttpRouter` docs).
+     * They are by default.
+     */
+    pub fn set_automatic_methods(&mut self, enabled: bool) {
+        self.automatic_methods = enabled;
+    }
+
+    /**
+     * Sets whether requests for paths with a trailing "/" are redirected to
+     * the same path without it (see the `HttpRouter` docs).  They aren't by
+     * default.
+     */
+    pub fn set_redirect_trailing_slash(&mut self, enabled: bool) {
+        self.redirect_trailing_slash = enabled;
+    }
+
+    /**
+     * Sets the handler for requests whose path matches no route, which
+     * otherwise fail with a 404 ("Not Found") error.  `middleware` runs around
+     * it, outermost first.  The fallback handler isn't a route, so it doesn't
+     * appear in `into_endpoints()` or when iterating over the router.
+     */
+    pub fn set_fallback(
+        &mut self,
+        handler: Box<dyn RouteHandler<Context>>,
+        middleware: Vec<Arc<dyn Middleware<Context>>>,
+    ) {
+        self.fallback = Some(FallbackHandler {
+            handler,
+            middleware,
+        });
+    }
+
+    /**
+     * Invokes `visitor` on each of the endpoints in the router, allowing it to
+     * modify them.
+     */
+    pub fn visit_endpoints_mut(
+        &mut self,
+        visitor: &mut dyn FnMut(&mut ApiEndpoint<Context>),
+    ) {
+        self.root.visit_endpoints_mut(visitor);
+    }
+
     /**
      * Configure a route for HTTP requests based on the HTTP `method` and
      * URI `path`.  See the `HttpRouter` docs for information about how `path`
      * is processed.  Requests matching `path` will be resolved to `handler`.
+     * Panics if the path is invalid or the route conflicts with one that's
+     * already been registered (see `try_insert()`).
      */
-    pub fn insert(&mut self, endpoint: ApiEndpoint) {
-        let method = endpoint.method.clone();
-        let path = endpoint.path.clone();
+    pub fn insert(&mut self, endpoint: ApiEndpoint<Context>) {
+        if let Err(error) = self.try_insert(endpoint) {
+            panic!("{}", error);
+        }
+    }
+
+    /**
+     * Like `insert()`, but if the path is invalid or the route conflicts with
+     * one that's already been registered, this returns an error describing the
+     * problem (and leaves the router unchanged) rather than panicking.
+     */
+    pub fn try_insert(
+        &mut self,
+        endpoint: ApiEndpoint<Context>,
+    ) -> Result<(), String> {
+        self.check_insert(&endpoint)?;
+
+        let mut node: &mut Box<HttpRouterNode<Context>> = &mut self.root;
+        for raw_segment in path_to_segments(&endpoint.path) {
+            /*
+             * check_insert() has already verified that this route doesn't
+             * conflict with the existing edges.
+             */
+            let edges = &mut node.edges;
+            node = match PathSegment::from(raw_segment) {
+                PathSegment::Literal(lit) => edges
+                    .literals
+                    .entry(lit)
+                    .or_insert_with(|| Box::new(HttpRouterNode::new())),
+
+                PathSegment::Varname(varname) => {
+                    edges.variable_edge(varname, None)
+                }
 
+                PathSegment::VarnameConstrained(varname, constraint) => {
+                    edges.variable_edge(varname, Some(constraint))
+                }
+
+                PathSegment::VarnameWildcard(varname) => {
+                    &mut edges
+                        .wildcard
+                        .get_or_insert_with(|| {
+                            (varname, Box::new(HttpRouterNode::new()))
+                        })
+                        .1
+                }
+            };
+        }
+
+        let methodname = endpoint.method.as_str().to_uppercase();
+        if let Some(limit) = endpoint.concurrency_limit {
+            node.concurrency_limits
+                .insert(methodname.clone(), Semaphore::new(limit));
+        }
+        node.methods.insert(methodname, endpoint);
+        node.options = OptionsHandler::new(&node.allowed_methods(true));
+        Ok(())
+    }
+
+    /**
+     * Checks whether `endpoint` could be inserted into the router without
+     * conflicting with the routes already registered, without modifying the
+     * router.  If its path is invalid or it conflicts, returns an error
+     * describing the problem.
+     */
+    pub fn check_insert(
+        &self,
+        endpoint: &ApiEndpoint<Context>,
+    ) -> Result<(), String> {
+        let method = &endpoint.method;
+        let path = &endpoint.path;
+        if let Some(limit) = endpoint.concurrency_limit {
+            check_limit("concurrency_limit", limit).map_err(|error| {
+                format!("endpoint \"{}\": {}", endpoint.operation_id, error)
+            })?;
+        }
         let all_segments = path_to_segments(path.as_str());
         let mut varnames: BTreeSet<String> = BTreeSet::new();
 
-        let mut node: &mut Box<HttpRouterNode> = &mut self.root;
-        for raw_segment in all_segments {
-            let segment = PathSegment::from(raw_segment);
+        /*
+         * Walk the existing routes as far as they go along this path.  `node`
+         * becomes `None` once we leave them, after which the only things left
+         * to check are that variable names aren't reused within the path and
+         * that a wildcard comes last.
+         */
+        let mut node: Option<&HttpRouterNode<Context>> = Some(&self.root);
+        for (i, raw_segment) in all_segments.iter().enumerate() {
+            let segment = PathSegment::parse(raw_segment)?;
+            let edges = node.map(|n| &n.edges);
 
             node = match segment {
-                PathSegment::Literal(lit) => {
-                    let edges = node.edges.get_or_insert(
-                        HttpRouterEdges::Literals(BTreeMap::new()),
-                    );
-                    match edges {
-                        /*
-                         * We do not allow both literal and variable edges from
-                         * the same node.  This could be supported (with some
-                         * caveats about how matching would work), but it seems
-                         * more likely to be a mistake.
-                         */
-                        HttpRouterEdges::Variable(varname, _) => {
-                            panic!(
-                                "URI path \"{}\": attempted to register route \
-                                 for literal path segment \"{}\" when a route \
-                                 exists for variable path segment (variable \
-                                 name: \"{}\")",
-                                path, lit, varname
-                            );
+                PathSegment::Literal(lit) => edges
+                    .and_then(|edges| edges.literals.get(&lit))
+                    .map(|node| &**node),
+
+                PathSegment::Varname(new_varname) => {
+                    check_varname_unique(path, &mut varnames, &new_varname)?;
+                    match edges.and_then(|edges| edges.find_variable(&None)) {
+                        None => None,
+                        Some(edge) => {
+                            check_varname_same(
+                                endpoint,
+                                &new_varname,
+                                &edge.varname,
+                                &edge.node,
+                            )?;
+                            Some(&*edge.node)
                         }
-                        HttpRouterEdges::Literals(ref mut literals) => literals
-                            .entry(lit)
-                            .or_insert_with(|| Box::new(HttpRouterNode::new())),
                     }
                 }
 
-                PathSegment::Varname(new_varname) => {
+                PathSegment::VarnameConstrained(new_varname, constraint) => {
+                    check_varname_unique(path, &mut varnames, &new_varname)?;
+                    let new_pattern = constraint.pattern().to_string();
+                    let constraint = Some(constraint);
+                    match edges.and_then(|e| e.find_variable(&constraint)) {
+                        None => None,
+                        Some(edge) => {
+                            /*
+                             * We can't tell which of two regular expressions
+                             * ought to take precedence over the other, so we
+                             * don't allow different ones from the same
+                             * resource.
+                             */
+                            if let Some(pattern) = edge
+                                .constraint
+                                .as_ref()
+                                .map(PathConstraint::pattern)
+                                .filter(|pattern| *pattern != new_pattern)
+                            {
+                                return Err(format!(
+                                    "URI path \"{}\": attempted to register \
+                                     route for variable path segment \
+                                     (variable name: \"{}\") with pattern \
+                                     \"{}\" when a route exists for variable \
+                                     path segment (variable name: \"{}\") \
+                                     with a different pattern (\"{}\"){}",
+                                    path,
+                                    new_varname,
+                                    new_pattern,
+                                    edge.varname,
+                                    pattern,
+                                    describe_conflict(endpoint, &edge.node),
+                                ));
+                            }
+                            check_varname_same(
+                                endpoint,
+                                &new_varname,
+                                &edge.varname,
+                                &edge.node,
+                            )?;
+                            Some(&*edge.node)
+                        }
+                    }
+                }
+
+                PathSegment::VarnameWildcard(new_varname) => {
+                    check_varname_unique(path, &mut varnames, &new_varname)?;
                     /*
-                     * Do not allow the same variable name to be used more than
-                     * once in the path.  Again, this could be supported (with
-                     * some caveats), but it seems more likely to be a mistake.
+                     * A wildcard consumes the rest of the path, so nothing can
+                     * come after it.
                      */
-                    if varnames.contains(&new_varname) {
-                        panic!(
-                            "URI path \"{}\": variable name \"{}\" is used \
-                             more than once",
+                    if i != all_segments.len() - 1 {
+                        return Err(format!(
+                            "URI path \"{}\": wildcard path segment (variable \
+                             name: \"{}\") must be the last segment",
                             path, new_varname
-                        );
-                    }
-                    varnames.insert(new_varname.clone());
-
-                    let edges =
-                        node.edges.get_or_insert(HttpRouterEdges::Variable(
-                            new_varname.clone(),
-                            Box::new(HttpRouterNode::new()),
                         ));
-                    match edges {
-                        /*
-                         * See the analogous check above about combining literal
-                         * and variable path segments from the same resource.
-                         */
-                        HttpRouterEdges::Literals(_) => panic!(
-                            "URI path \"{}\": attempted to register route for \
-                             variable path segment (variable name: \"{}\") \
-                             when a route already exists for a literal path \
-                             segment",
-                            path, new_varname
-                        ),
-
-                        HttpRouterEdges::Variable(varname, ref mut node) => {
-                            if *new_varname != *varname {
-                                /*
-                                 * Don't allow people to use different names for
-                                 * the same part of the path.  Again, this could
-                                 * be supported, but it seems likely to be
-                                 * confusing and probably a mistake.
-                                 */
-                                panic!(
-                                    "URI path \"{}\": attempted to use \
-                                     variable name \"{}\", but a different \
-                                     name (\"{}\") has already been used for \
-                                     this",
-                                    path, new_varname, varname
-                                );
-                            }
-
-                            node
+                    }
+                    match edges.and_then(|edges| edges.wildcard.as_ref()) {
+                        None => None,
+                        Some((varname, node)) => {
+                            check_varname_same(
+                                endpoint,
+                                &new_varname,
+                                varname,
+                                node,
+                            )?;
+                            Some(&**node)
                         }
                     }
                 }
@@ -270,85 +778,350 @@ impl HttpRouter {
         }
 
         let methodname = method.as_str().to_uppercase();
-        if node.methods.get(&methodname).is_some() {
-            panic!(
+        if let Some(existing) = node.and_then(|n| n.methods.get(&methodname)) {
+            return Err(format!(
                 "URI path \"{}\": attempted to create duplicate route for \
-                 method \"{}\"",
-                path, method,
-            );
+                 method \"{}\" (endpoint \"{}\" conflicts with endpoint \
+                 \"{}\" for {} \"{}\")",
+                path,
+                method,
+                endpoint.operation_id,
+                existing.operation_id,
+                existing.method,
+                existing.path,
+            ));
         }
 
-        node.methods.insert(methodname, endpoint);
+        /*
+         * The OpenAPI definition has no syntax for constraints on variables,
+         * so routes whose paths differ only in their variables' constraints
+         * (including whether a variable is a wildcard) have the same path
+         * there, and an operation for one would replace that for the other.
+         */
+        let template = openapi_path(path);
+        for (existing_path, existing_method, existing) in self {
+            if existing_method == methodname
+                && openapi_path(&existing_path) == template
+            {
+                return Err(format!(
+                    "URI path \"{}\": attempted to register route for method \
+                     \"{}\" whose path in the OpenAPI definition (\"{}\") is \
+                     the same as that of an existing route (endpoint \"{}\" \
+                     conflicts with endpoint \"{}\" for {} \"{}\")",
+                    path,
+                    method,
+                    template,
+                    endpoint.operation_id,
+                    existing.operation_id,
+                    existing.method,
+                    existing.path,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Consumes the router, returning all of the endpoints registered with it.
+     */
+    pub fn into_endpoints(self) -> Vec<ApiEndpoint<Context>> {
+        let mut endpoints = Vec::new();
+        self.root.into_endpoints(&mut endpoints);
+        endpoints
     }
 
     /**
      * Look up the route handler for an HTTP request having method `method` and
-     * URI path `path`.  A successful lookup produces a `RouterLookupResult`,
-     * which includes both the handler that can process this request and a map
-     * of variables assigned based on the request path as part of the lookup.
-     * On failure, this returns an `HttpError` appropriate for the failure
-     * mode.
-     *
-     * TODO-cleanup
-     * consider defining a separate struct type for url-encoded vs. not?
+     * URI path `path`, which may be followed by the URI's query string (which
+     * is only used if the request is redirected).  A successful lookup
+     * produces a `RouterLookupResult`, which includes both the handler that
+     * can process this request and a map of variables assigned based on the
+     * request path as part of the lookup.  On failure, this returns an
+     * `HttpError` appropriate for the failure mode.  See the `HttpRouter` docs
+     * for how `path` is normalized.
      */
     pub fn lookup_route<'a, 'b>(
         &'a self,
         method: &'b Method,
         path: &'b str,
-    ) -> Result<RouterLookupResult<'a>, HttpError> {
-        let all_segments = path_to_segments(path);
-        let mut node = &self.root;
+    ) -> Result<RouterLookupResult<'a, Context>, HttpError> {
+        let (path, query) = match path.split_once('?') {
+            None => (path, None),
+            Some((path, query)) => (path, Some(query)),
+        };
+        let all_segments = request_path_to_segments(path)?;
+        let all_segments =
+            all_segments.iter().map(String::as_str).collect::<Vec<_>>();
         let mut variables: BTreeMap<String, String> = BTreeMap::new();
 
-        for segment in all_segments {
-            let segment_string = segment.to_string();
+        /*
+         * If the path leads only to nodes with no handlers at all, use the
+         * fallback handler or report a 404.  We could probably treat this as a
+         * 405 as well.
+         */
+        let node = match self.root.find(&all_segments, &mut variables) {
+            Some(node) => node,
+            None => {
+                return match &self.fallback {
+                    Some(fallback) => Ok(RouterLookupResult {
+                        handler: &*fallback.handler,
+                        middleware: &fallback.middleware,
+                        timeout: None,
+                        concurrency_limit: None,
+                        request_body_max_bytes: None,
+                        variables,
+                        discard_body: false,
+                    }),
+                    None => Err(HttpError::for_not_found(
+                        None,
+                        String::from("no route found (no path in router)"),
+                    )),
+                };
+            }
+        };
+
+        /*
+         * A HEAD request is just like a GET request except that the response
+         * has no body, so it can be handled by the GET handler.
+         */
+        let methodname = method.as_str().to_uppercase();
+        let (methodname, discard_body) =
+            if node.methods.contains_key(&methodname) {
+                (methodname, false)
+            } else if self.automatic_methods
+                && *method == Method::HEAD
+                && node.methods.contains_key("GET")
+            {
+                (String::from("GET"), true)
+            } else {
+                (methodname, false)
+            };
 
-            node = match &node.edges {
-                None => None,
-                Some(HttpRouterEdges::Literals(edges)) => {
-                    edges.get(&segment_string)
+        let result = match node.methods.get(&methodname) {
+            Some(endpoint) => {
+                /*
+                 * Literal segments can't contain "/", so if any segment of the
+                 * path did, it was matched by one of the endpoint's variables.
+                 */
+                if !endpoint.allow_encoded_slashes
+                    && all_segments.iter().any(|s| s.contains('/'))
+                {
+                    return Err(HttpError::for_bad_request(
+                        None,
+                        String::from(
+                            "URI path segment contains an encoded \"/\"",
+                        ),
+                    ));
+                }
+                RouterLookupResult {
+                    handler: &*endpoint.handler,
+                    middleware: &endpoint.middleware,
+                    timeout: endpoint.timeout,
+                    concurrency_limit: node.concurrency_limits.get(&methodname),
+                    request_body_max_bytes: endpoint.request_body_max_bytes,
+                    variables,
+                    discard_body,
                 }
-                Some(HttpRouterEdges::Variable(varname, ref node)) => {
-                    variables.insert(varname.clone(), segment_string);
-                    Some(node)
+            }
+            None if self.automatic_methods && *method == Method::OPTIONS => {
+                RouterLookupResult {
+                    handler: &node.options,
+                    middleware: &[],
+                    timeout: None,
+                    concurrency_limit: None,
+                    request_body_max_bytes: None,
+                    variables,
+                    discard_body: false,
                 }
             }
-            .ok_or_else(|| {
-                HttpError::for_not_found(
+            None => {
+                return Err(HttpError::for_status(
                     None,
-                    String::from("no route found (no path in router)"),
+                    StatusCode::METHOD_NOT_ALLOWED,
                 )
-            })?
-        }
+                .with_header(
+                    http::header::ALLOW,
+                    allow_header(&node.allowed_methods(self.automatic_methods)),
+                ));
+            }
+        };
 
         /*
-         * As a somewhat special case, if one requests a node with no handlers
-         * at all, report a 404.  We could probably treat this as a 405 as well.
+         * The canonical path for a resource has no trailing "/".  We only
+         * redirect to it if there's a resource there to be found.  The
+         * location is built from the normalized segments rather than from the
+         * path we were given, so that it always starts with exactly one "/".
+         * Otherwise, a request for "//evil.example/" would redirect the client
+         * to "//evil.example", which is another host altogether.
          */
-        if node.methods.is_empty() {
-            return Err(HttpError::for_not_found(
-                None,
-                String::from("route has no handlers"),
-            ));
+        if self.redirect_trailing_slash
+            && path.ends_with('/')
+            && !all_segments.is_empty()
+        {
+            let canonical = all_segments
+                .iter()
+                .map(|s| utf8_percent_encode(s, PATH_SEGMENT).to_string())
+                .collect::<Vec<_>>()
+                .join("/");
+            let location = match query {
+                None => format!("/{}", canonical),
+                Some(query) => format!("/{}?{}", canonical, query),
+            };
+            return Err(redirect(StatusCode::PERMANENT_REDIRECT, &location));
         }
 
-        let methodname = method.as_str().to_uppercase();
-        node.methods
-            .get(&methodname)
-            .map(|handler| RouterLookupResult {
-                handler: &*handler.handler,
-                variables,
-            })
-            .ok_or_else(|| {
-                HttpError::for_status(None, StatusCode::METHOD_NOT_ALLOWED)
-            })
+        Ok(result)
+    }
+}
+
+impl<Context: ServerContext> HttpRouterEdges<Context> {
+    /**
+     * Returns the variable edge whose constraint has the same precedence as
+     * `constraint` (and so is the only one that could conflict with it).
+     */
+    fn find_variable(
+        &self,
+        constraint: &Option<PathConstraint>,
+    ) -> Option<&HttpRouterVariableEdge<Context>> {
+        let precedence = PathConstraint::precedence(constraint);
+        self.variables.iter().find(|edge| {
+            PathConstraint::precedence(&edge.constraint) == precedence
+        })
+    }
+
+    /**
+     * Returns the node for the variable edge with `constraint`, first adding
+     * it (in order of precedence) if there isn't one.
+     */
+    fn variable_edge(
+        &mut self,
+        varname: String,
+        constraint: Option<PathConstraint>,
+    ) -> &mut Box<HttpRouterNode<Context>> {
+        let precedence = PathConstraint::precedence(&constraint);
+        let index = self.variables.partition_point(|edge| {
+            PathConstraint::precedence(&edge.constraint) < precedence
+        });
+        let exists = self.variables.get(index).map_or(false, |edge| {
+            PathConstraint::precedence(&edge.constraint) == precedence
+        });
+        if !exists {
+            self.variables.insert(index, HttpRouterVariableEdge {
+                varname,
+                constraint,
+                node: Box::new(HttpRouterNode::new()),
+            });
+        }
+        &mut self.variables[index].node
+    }
+}
+
+/**
+ * `OptionsHandler` handles OPTIONS requests for a resource that has no endpoint
+ * for them by listing the methods that the resource supports in the "Allow"
+ * header of an otherwise empty response.
+ */
+#[derive(Debug)]
+struct OptionsHandler {
+    allow: HeaderValue,
+}
+
+impl OptionsHandler {
+    fn new(allowed_methods: &str) -> OptionsHandler {
+        OptionsHandler {
+            allow: allow_header(allowed_methods),
+        }
+    }
+}
+
+#[async_trait]
+impl<Context: ServerContext> RouteHandler<Context> for OptionsHandler {
+    fn label(&self) -> &str {
+        "automatic OPTIONS handler"
+    }
+
+    async fn handle_request(
+        &self,
+        _rqctx: Arc<RequestContext<Context>>,
+    ) -> HttpHandlerResult {
+        Ok(Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(http::header::ALLOW, self.allow.clone())
+            .body(Body::empty())?)
     }
 }
 
-impl<'a> IntoIterator for &'a HttpRouter {
-    type Item = (String, String, &'a ApiEndpoint);
-    type IntoIter = HttpRouterIter<'a>;
+/**
+ * Characters that are percent-encoded when a decoded path segment is put back
+ * into a URI path.  Besides those that can't appear in a path at all, this
+ * includes "/" (which would otherwise split the segment in two), "%", and
+ * "\" (which some clients treat like "/").
+ */
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/**
+ * Returns an error that redirects the client to `location` with status code
+ * `status_code`.
+ */
+fn redirect(status_code: StatusCode, location: &str) -> HttpError {
+    let message = status_code.canonical_reason().unwrap().to_string();
+    let error = HttpError {
+        status_code,
+        error_code: None,
+        external_message: message,
+        internal_message: format!("redirecting to \"{}\"", location),
+        headers: None,
+    };
+    match HeaderValue::from_str(location) {
+        Ok(location) => error.with_header(http::header::LOCATION, location),
+        Err(_) => HttpError::for_bad_request(
+            None,
+            String::from("invalid characters in URI path"),
+        ),
+    }
+}
+
+fn allow_header(allowed_methods: &str) -> HeaderValue {
+    HeaderValue::from_str(allowed_methods)
+        .expect("HTTP method names are valid header values")
+}
+
+/**
+ * Describes which of the existing routes through `node` the new `endpoint`
+ * conflicts with, for use at the end of an error message.
+ */
+fn describe_conflict<Context: ServerContext>(
+    endpoint: &ApiEndpoint<Context>,
+    node: &HttpRouterNode<Context>,
+) -> String {
+    match node.first_endpoint() {
+        None => String::new(),
+        Some(existing) => format!(
+            " (endpoint \"{}\" conflicts with endpoint \"{}\" for {} \"{}\")",
+            endpoint.operation_id,
+            existing.operation_id,
+            existing.method,
+            existing.path,
+        ),
+    }
+}
+
+impl<'a, Context: ServerContext> IntoIterator for &'a HttpRouter<Context> {
+    type Item = (String, String, &'a ApiEndpoint<Context>);
+    type IntoIter = HttpRouterIter<'a, Context>;
     fn into_iter(self) -> Self::IntoIter {
         HttpRouterIter::new(self)
     }
@@ -364,15 +1137,16 @@ impl<'a> IntoIterator for &'a HttpRouter {
  * the root node's `methods` iterator and a stack consisting of a
  * blank string and an iterator over the root node's children.
  */
-pub struct HttpRouterIter<'a> {
-    method: Box<dyn Iterator<Item = (&'a String, &'a ApiEndpoint)> + 'a>,
-    path: Vec<(PathSegment, Box<PathIter<'a>>)>,
+pub struct HttpRouterIter<'a, Context: ServerContext> {
+    method:
+        Box<dyn Iterator<Item = (&'a String, &'a ApiEndpoint<Context>)> + 'a>,
+    path: Vec<(PathSegment, Box<PathIter<'a, Context>>)>,
 }
-type PathIter<'a> =
-    dyn Iterator<Item = (PathSegment, &'a Box<HttpRouterNode>)> + 'a;
+type PathIter<'a, Context> =
+    dyn Iterator<Item = (PathSegment, &'a Box<HttpRouterNode<Context>>)> + 'a;
 
-impl<'a> HttpRouterIter<'a> {
-    fn new(router: &'a HttpRouter) -> Self {
+impl<'a, Context: ServerContext> HttpRouterIter<'a, Context> {
+    fn new(router: &'a HttpRouter<Context>) -> Self {
         HttpRouterIter {
             method: Box::new(router.root.methods.iter()),
             path: vec![(
@@ -388,17 +1162,30 @@ impl<'a> HttpRouterIter<'a> {
      * path parameter variable, and a modified iterator in the case of
      * literal, explicit path segments.
      */
-    fn iter_node(node: &'a HttpRouterNode) -> Box<PathIter<'a>> {
-        match &node.edges {
-            Some(HttpRouterEdges::Literals(map)) => Box::new(
-                map.iter()
-                    .map(|(s, node)| (PathSegment::Literal(s.clone()), node)),
-            ),
-            Some(HttpRouterEdges::Variable(ref varname, ref node)) => Box::new(
-                std::iter::once((PathSegment::Varname(varname.clone()), node)),
-            ),
-            None => Box::new(std::iter::empty()),
-        }
+    fn iter_node(
+        node: &'a HttpRouterNode<Context>,
+    ) -> Box<PathIter<'a, Context>> {
+        let edges = &node.edges;
+        Box::new(
+            edges
+                .literals
+                .iter()
+                .map(|(s, node)| (PathSegment::Literal(s.clone()), node))
+                .chain(edges.variables.iter().map(|edge| {
+                    let varname = edge.varname.clone();
+                    let segment = match &edge.constraint {
+                        None => PathSegment::Varname(varname),
+                        Some(constraint) => PathSegment::VarnameConstrained(
+                            varname,
+                            constraint.clone(),
+                        ),
+                    };
+                    (segment, &edge.node)
+                }))
+                .chain(edges.wildcard.iter().map(|(varname, node)| {
+                    (PathSegment::VarnameWildcard(varname.clone()), node)
+                })),
+        )
     }
 
     /**
@@ -411,6 +1198,10 @@ impl<'a> HttpRouterIter<'a> {
             .map(|(c, _)| match c {
                 PathSegment::Literal(s) => s.clone(),
                 PathSegment::Varname(s) => format!("{{{}}}", s),
+                PathSegment::VarnameConstrained(s, constraint) => {
+                    format!("{{{}:{}}}", s, constraint.pattern())
+                }
+                PathSegment::VarnameWildcard(s) => format!("{{{}:.*}}", s),
             })
             .collect();
 
@@ -419,8 +1210,8 @@ impl<'a> HttpRouterIter<'a> {
     }
 }
 
-impl<'a> Iterator for HttpRouterIter<'a> {
-    type Item = (String, String, &'a ApiEndpoint);
+impl<'a, Context: ServerContext> Iterator for HttpRouterIter<'a, Context> {
+    type Item = (String, String, &'a ApiEndpoint<Context>);
 
     fn next(&mut self) -> Option<Self::Item> {
         // If there are no path components left then we've reached the end of
@@ -459,47 +1250,139 @@ impl<'a> Iterator for HttpRouterIter<'a> {
 }
 
 /**
- * Helper function for taking a Uri path and producing a `Vec<String>` of
- * URL-encoded strings, each representing one segment of the path.
+ * Do not allow the same variable name to be used more than once in the path.
+ * This could be supported (with some caveats), but it seems more likely to be
+ * a mistake.
+ */
+fn check_varname_unique(
+    path: &str,
+    varnames: &mut BTreeSet<String>,
+    new_varname: &str,
+) -> Result<(), String> {
+    if !varnames.insert(new_varname.to_string()) {
+        return Err(format!(
+            "URI path \"{}\": variable name \"{}\" is used more than once",
+            path, new_varname
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * Don't allow people to use different names for the same part of the path.
+ * This could be supported, but it seems likely to be confusing and probably a
+ * mistake.
+ */
+fn check_varname_same<Context: ServerContext>(
+    endpoint: &ApiEndpoint<Context>,
+    new_varname: &str,
+    varname: &str,
+    node: &HttpRouterNode<Context>,
+) -> Result<(), String> {
+    if new_varname != varname {
+        return Err(format!(
+            "URI path \"{}\": attempted to use variable name \"{}\", but a \
+             different name (\"{}\") has already been used for this{}",
+            endpoint.path,
+            new_varname,
+            varname,
+            describe_conflict(endpoint, node),
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * Helper function for taking a Uri path and producing a `Vec<&str>` of
+ * strings, each representing one segment of the path.  The segments are not
+ * decoded: this is used for the paths of routes, while request paths are
+ * processed with [`request_path_to_segments`].
  */
 pub fn path_to_segments(path: &str) -> Vec<&str> {
-    /*
-     * We're given the "path" portion of a URI and we want to construct an
-     * array of the segments of the path.   Relevant references:
-     *
-     *    RFC 7230 HTTP/1.1 Syntax and Routing
-     *             (particularly: 2.7.3 on normalization)
-     *    RFC 3986 Uniform Resource Identifier (URI): Generic Syntax
-     *             (particularly: 6.2.2 on comparison)
-     *
-     * TODO-hardening We should revisit this.  We want to consider a few things:
-     * - whether our input is already (still?) percent-encoded or not
-     * - whether our returned representation is percent-encoded or not
-     * - what it means (and what we should do) if the path does not begin with
-     *   a leading "/"
-     * - whether we want to collapse consecutive "/" characters (presumably we
-     *   do, both at the start of the path and later)
-     * - how to handle paths that end in "/" (in some cases, ought this send a
-     *   300-level redirect?)
-     * - are there other normalization considerations? e.g., ".", ".."
-     *
-     * It seems obvious to reach for the Rust "url" crate. That crate parses
-     * complete URLs, which include a scheme and authority section that does
-     * not apply here. We could certainly make one up (e.g.,
-     * "http://127.0.0.1") and construct a URL whose path matches the path we
-     * were given. However, while it seems natural that our internal
-     * representation would not be percent-encoded, the "url" crate
-     * percent-encodes any path that it's given. Further, we probably want to
-     * treat consecutive "/" characters as equivalent to a single "/", but that
-     * crate treats them separately (which is not unreasonable, since it's not
-     * clear that the above RFCs say anything about whether empty segments
-     * should be ignored). The net result is that that crate doesn't buy us
-     * much here, but it does create more work, so we'll just split it
-     * ourselves.
-     */
     path.split('/').filter(|segment| !segment.is_empty()).collect::<Vec<_>>()
 }
 
+/**
+ * Returns the path under which the route for `path` appears in the OpenAPI
+ * definition.  OpenAPI path templates have no syntax for constraints on a
+ * variable's values or for a variable that matches the rest of the path, so
+ * every variable appears as just its name (e.g., `"/items/{id:uuid}"` and
+ * `"/items/{id:.*}"` both become `"/items/{id}"`).  `path` must be valid.
+ */
+pub fn openapi_path(path: &str) -> String {
+    let segments = path_to_segments(path)
+        .into_iter()
+        .map(|segment| match PathSegment::from(segment) {
+            PathSegment::Literal(_) => segment.to_string(),
+            PathSegment::Varname(v)
+            | PathSegment::VarnameConstrained(v, _)
+            | PathSegment::VarnameWildcard(v) => format!("{{{}}}", v),
+        })
+        .collect::<Vec<_>>();
+    format!("/{}", segments.join("/"))
+}
+
+/**
+ * Splits the "path" portion of a request URI into normalized, percent-decoded
+ * segments.  Relevant references:
+ *
+ *    RFC 7230 HTTP/1.1 Syntax and Routing
+ *             (particularly: 2.7.3 on normalization)
+ *    RFC 3986 Uniform Resource Identifier (URI): Generic Syntax
+ *             (particularly: 5.2.4 on dot segments and 6.2.2 on comparison)
+ *
+ * - Consecutive "/" characters are treated as one, and a leading or trailing
+ *   "/" is ignored.  (Whether a trailing "/" results in a redirect is up to
+ *   the router.)
+ * - Each segment is percent-decoded separately, so that an encoded "/"
+ *   ("%2F") is part of a segment rather than a separator.  Decoded segments
+ *   must be valid UTF-8.
+ * - Segments that are "." are removed, and segments that are ".." remove the
+ *   segment before them.  ".." at the root is ignored.
+ * - Segments that only become "." or ".." when decoded (e.g., "%2e%2e"), or
+ *   that contain one as a "/"-separated component once decoded (e.g.,
+ *   "..%2Fetc"), are rejected with a 400 error.  Otherwise, they'd reach a
+ *   handler as part of a variable's value, and a handler serving files (say)
+ *   could be tricked into reading outside the directory it serves.
+ *
+ * It seems obvious to reach for the Rust "url" crate. That crate parses
+ * complete URLs, which include a scheme and authority section that does not
+ * apply here. We could certainly make one up (e.g., "http://127.0.0.1") and
+ * construct a URL whose path matches the path we were given. However, the
+ * "url" crate does not decode the path, treats consecutive "/" characters as
+ * separate (empty) segments, and does not treat encoded dot segments as dot
+ * segments.  The net result is that that crate doesn't buy us much here, but
+ * it does create more work, so we'll just split it ourselves.
+ */
+pub fn request_path_to_segments(path: &str) -> Result<Vec<String>, HttpError> {
+    let mut segments = Vec::new();
+    for raw_segment in path_to_segments(path) {
+        let segment =
+            percent_decode_str(raw_segment).decode_utf8().map_err(|_| {
+                HttpError::for_bad_request(
+                    None,
+                    String::from("URI path is not valid UTF-8 when decoded"),
+                )
+            })?;
+        match raw_segment {
+            "." => (),
+            ".." => {
+                segments.pop();
+            }
+            _ if segment.split('/').any(|c| c == "." || c == "..") => {
+                return Err(HttpError::for_bad_request(
+                    None,
+                    String::from(
+                        "URI path segment contains an encoded \".\" or \"..\"",
+                    ),
+                ));
+            }
+            _ => segments.push(segment.into_owned()),
+        }
+    }
+    Ok(segments)
+}
+
 #[cfg(test)]
 mod test {
     use super::super::error::HttpError;
@@ -516,24 +1399,24 @@ mod test {
     use std::sync::Arc;
 
     async fn test_handler(
-        _: Arc<RequestContext>,
+        _: Arc<RequestContext<()>>,
     ) -> Result<Response<Body>, HttpError> {
         panic!("test handler is not supposed to run");
     }
 
-    fn new_handler() -> Box<dyn RouteHandler> {
+    fn new_handler() -> Box<dyn RouteHandler<()>> {
         HttpRouteHandler::new(test_handler)
     }
 
-    fn new_handler_named(name: &str) -> Box<dyn RouteHandler> {
+    fn new_handler_named(name: &str) -> Box<dyn RouteHandler<()>> {
         HttpRouteHandler::new_with_name(test_handler, name)
     }
 
     fn new_endpoint(
-        handler: Box<dyn RouteHandler>,
+        handler: Box<dyn RouteHandler<()>>,
         method: Method,
         path: &str,
-    ) -> ApiEndpoint {
+    ) -> ApiEndpoint<()> {
         ApiEndpoint {
             operation_id: "test_handler".to_string(),
             handler: handler,
@@ -547,9 +1430,24 @@ mod test {
             },
             description: None,
             tags: vec![],
+            timeout: None,
+            concurrency_limit: None,
+            request_body_max_bytes: None,
+            middleware: vec![],
+            allow_encoded_slashes: false,
         }
     }
 
+    fn new_endpoint_id(
+        operation_id: &str,
+        method: Method,
+        path: &str,
+    ) -> ApiEndpoint<()> {
+        let mut endpoint = new_endpoint(new_handler(), method, path);
+        endpoint.operation_id = operation_id.to_string();
+        endpoint
+    }
+
     #[test]
     #[should_panic(
         expected = "HTTP URI path segment variable name cannot be empty"
@@ -613,71 +1511,297 @@ mod test {
     }
 
     #[test]
-    #[should_panic(expected = "URI path \"/projects/{id}/insts/{id}\": \
-                               variable name \"id\" is used more than once")]
-    fn test_duplicate_varname() {
+    #[should_panic(expected = "URI path \"/projects/{id}/insts/{id}\": \
+                               variable name \"id\" is used more than once")]
+    fn test_duplicate_varname() {
+        let mut router = HttpRouter::new();
+        router.insert(new_endpoint(
+            new_handler(),
+            Method::GET,
+            "/projects/{id}/insts/{id}",
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "URI path \"/projects/{id}\": attempted to use \
+                               variable name \"id\", but a different name \
+                               (\"project_id\") has already been used for \
+                               this")]
+    fn test_inconsistent_varname() {
+        let mut router = HttpRouter::new();
+        router.insert(new_endpoint(
+            new_handler(),
+            Method::GET,
+            "/projects/{project_id}",
+        ));
+        router.insert(new_endpoint(
+            new_handler(),
+            Method::GET,
+            "/projects/{id}",
+        ));
+    }
+
+    #[test]
+    fn test_variable_after_literal() {
+        let mut router = HttpRouter::new();
+        router.insert(new_endpoint(
+            new_handler_named("h1"),
+            Method::GET,
+            "/projects/default",
+        ));
+        router.insert(new_endpoint(
+            new_handler_named("h2"),
+            Method::GET,
+            "/projects/{id}",
+        ));
+
+        let result =
+            router.lookup_route(&Method::GET, "/projects/default").unwrap();
+        assert_eq!(result.handler.label(), "h1");
+        assert!(result.variables.is_empty());
+        let result = router.lookup_route(&Method::GET, "/projects/p1").unwrap();
+        assert_eq!(result.handler.label(), "h2");
+        assert_eq!(result.variables.get("id").unwrap(), "p1");
+    }
+
+    #[test]
+    fn test_literal_after_variable() {
+        let mut router = HttpRouter::new();
+        router.insert(new_endpoint(
+            new_handler_named("h1"),
+            Method::GET,
+            "/projects/{id}",
+        ));
+        router.insert(new_endpoint(
+            new_handler_named("h2"),
+            Method::GET,
+            "/projects/{id}/instances",
+        ));
+        router.insert(new_endpoint(
+            new_handler_named("h3"),
+            Method::GET,
+            "/projects/default",
+        ));
+        router.insert(new_endpoint(
+            new_handler_named("h4"),
+            Method::POST,
+            "/projects/new",
+        ));
+
+        /* The literal takes precedence over the variable. */
+        let result =
+            router.lookup_route(&Method::GET, "/projects/default").unwrap();
+        assert_eq!(result.handler.label(), "h3");
+        assert!(result.variables.is_empty());
+
+        /*
+         * When the rest of the path doesn't match any route through the
+         * literal, the variable matches it instead.
+         */
+        let result = router
+            .lookup_route(&Method::GET, "/projects/default/instances")
+            .unwrap();
+        assert_eq!(result.handler.label(), "h2");
+        assert_eq!(result.variables.get("id").unwrap(), "default");
+
+        /*
+         * But the literal still takes precedence when it has no handler for the
+         * request's method.
+         */
+        let error =
+            router.lookup_route(&Method::GET, "/projects/new").unwrap_err();
+        assert_eq!(error.status_code, StatusCode::METHOD_NOT_ALLOWED);
+        let result =
+            router.lookup_route(&Method::POST, "/projects/new").unwrap();
+        assert_eq!(result.handler.label(), "h4");
+
+        let ret: Vec<_> = router.into_iter().map(|x| x.0).collect();
+        assert_eq!(ret, vec![
+            "/projects/default",
+            "/projects/new",
+            "/projects/{id}",
+            "/projects/{id}/instances",
+        ]);
+    }
+
+    #[test]
+    fn test_variable_constraints() {
         let mut router = HttpRouter::new();
         router.insert(new_endpoint(
-            new_handler(),
+            new_handler_named("h1"),
             Method::GET,
-            "/projects/{id}/insts/{id}",
+            "/items/{rest:.*}",
         ));
-    }
-
-    #[test]
-    #[should_panic(expected = "URI path \"/projects/{id}\": attempted to use \
-                               variable name \"id\", but a different name \
-                               (\"project_id\") has already been used for \
-                               this")]
-    fn test_inconsistent_varname() {
-        let mut router = HttpRouter::new();
         router.insert(new_endpoint(
-            new_handler(),
+            new_handler_named("h2"),
             Method::GET,
-            "/projects/{project_id}",
+            "/items/{name}",
         ));
         router.insert(new_endpoint(
-            new_handler(),
+            new_handler_named("h3"),
             Method::GET,
-            "/projects/{id}",
+            "/items/{code:[A-Z]{3}}",
         ));
-    }
-
-    #[test]
-    #[should_panic(expected = "URI path \"/projects/{id}\": attempted to \
-                               register route for variable path segment \
-                               (variable name: \"id\") when a route already \
-                               exists for a literal path segment")]
-    fn test_variable_after_literal() {
-        let mut router = HttpRouter::new();
         router.insert(new_endpoint(
-            new_handler(),
+            new_handler_named("h4"),
             Method::GET,
-            "/projects/default",
+            "/items/{number:integer}",
         ));
         router.insert(new_endpoint(
-            new_handler(),
+            new_handler_named("h5"),
             Method::GET,
-            "/projects/{id}",
+            "/items/{id:uuid}",
+        ));
+        router.insert(new_endpoint(
+            new_handler_named("h6"),
+            Method::GET,
+            "/items/all",
         ));
+
+        let cases = vec![
+            ("/items/all", "h6", None),
+            (
+                "/items/e7de9ff9-5e6c-4d47-a58d-6fcd3b7d0a6c",
+                "h5",
+                Some(("id", "e7de9ff9-5e6c-4d47-a58d-6fcd3b7d0a6c")),
+            ),
+            ("/items/123", "h4", Some(("number", "123"))),
+            ("/items/-123", "h4", Some(("number", "-123"))),
+            ("/items/ABC", "h3", Some(("code", "ABC"))),
+            ("/items/ABCD", "h2", Some(("name", "ABCD"))),
+            ("/items/-", "h2", Some(("name", "-"))),
+            ("/items/1.5", "h2", Some(("name", "1.5"))),
+            ("/items/e7de9ff9", "h2", Some(("name", "e7de9ff9"))),
+            ("/items/all/1", "h1", Some(("rest", "all/1"))),
+            ("/items/123/1", "h1", Some(("rest", "123/1"))),
+        ];
+        for (path, label, variable) in cases {
+            let result = router.lookup_route(&Method::GET, path).unwrap();
+            assert_eq!(result.handler.label(), label, "path {}", path);
+            match variable {
+                None => assert!(result.variables.is_empty()),
+                Some((name, value)) => {
+                    assert_eq!(result.variables.len(), 1);
+                    assert_eq!(result.variables.get(name).unwrap(), value);
+                }
+            }
+        }
+
+        /* Edges are listed in the order in which they're tried. */
+        let ret: Vec<_> = router.into_iter().map(|x| x.0).collect();
+        assert_eq!(ret, vec![
+            "/items/all",
+            "/items/{id:uuid}",
+            "/items/{number:integer}",
+            "/items/{code:[A-Z]{3}}",
+            "/items/{name}",
+            "/items/{rest:.*}",
+        ]);
     }
 
     #[test]
-    #[should_panic(expected = "URI path \"/projects/default\": attempted to \
-                               register route for literal path segment \
-                               \"default\" when a route exists for variable \
-                               path segment (variable name: \"id\")")]
-    fn test_literal_after_variable() {
+    fn test_variable_bad_pattern() {
         let mut router = HttpRouter::new();
-        router.insert(new_endpoint(
-            new_handler(),
+        let error = router
+            .try_insert(new_endpoint(
+                new_handler(),
+                Method::GET,
+                "/items/{code:[A-Z}",
+            ))
+            .unwrap_err();
+        assert!(error.starts_with(
+            "HTTP URI path segment variable \"code\" has invalid pattern \
+             \"[A-Z\": "
+        ));
+        assert!(router.into_iter().next().is_none());
+    }
+
+    #[test]
+    fn test_variable_constraint_conflicts() {
+        let mut router = HttpRouter::new();
+        router.insert(new_endpoint_id(
+            "item_by_code",
             Method::GET,
-            "/projects/{id}",
+            "/items/{code:[A-Z]+}/info",
         ));
-        router.insert(new_endpoint(
-            new_handler(),
+        router.insert(new_endpoint_id(
+            "item_by_id",
             Method::GET,
-            "/projects/default",
+            "/items/{id:uuid}",
+        ));
+
+        let conflicts = vec![
+            (
+                "/items/{code:[a-z]+}",
+                "URI path \"/items/{code:[a-z]+}\": attempted to register \
+                 route for variable path segment (variable name: \"code\") \
+                 with pattern \"[a-z]+\" when a route exists for variable \
+                 path segment (variable name: \"code\") with a different \
+                 pattern (\"[A-Z]+\") (endpoint \"new_item\" conflicts with \
+                 endpoint \"item_by_code\" for GET \
+                 \"/items/{code:[A-Z]+}/info\")",
+            ),
+            (
+                "/items/{sku:[A-Z]+}",
+                "URI path \"/items/{sku:[A-Z]+}\": attempted to use variable \
+                 name \"sku\", but a different name (\"code\") has already \
+                 been used for this (endpoint \"new_item\" conflicts with \
+                 endpoint \"item_by_code\" for GET \
+                 \"/items/{code:[A-Z]+}/info\")",
+            ),
+            (
+                "/items/{item_id:uuid}/info",
+                "URI path \"/items/{item_id:uuid}/info\": attempted to use \
+                 variable name \"item_id\", but a different name (\"id\") has \
+                 already been used for this (endpoint \"new_item\" conflicts \
+                 with endpoint \"item_by_id\" for GET \"/items/{id:uuid}\")",
+            ),
+            (
+                "/items/{id:uuid}",
+                "URI path \"/items/{id:uuid}\": attempted to create duplicate \
+                 route for method \"GET\" (endpoint \"new_item\" conflicts \
+                 with endpoint \"item_by_id\" for GET \"/items/{id:uuid}\")",
+            ),
+            (
+                "/items/{id:integer}",
+                "URI path \"/items/{id:integer}\": attempted to register \
+                 route for method \"GET\" whose path in the OpenAPI \
+                 definition (\"/items/{id}\") is the same as that of an \
+                 existing route (endpoint \"new_item\" conflicts with \
+                 endpoint \"item_by_id\" for GET \"/items/{id:uuid}\")",
+            ),
+            (
+                "/items/{id}",
+                "URI path \"/items/{id}\": attempted to register route for \
+                 method \"GET\" whose path in the OpenAPI definition \
+                 (\"/items/{id}\") is the same as that of an existing route \
+                 (endpoint \"new_item\" conflicts with endpoint \
+                 \"item_by_id\" for GET \"/items/{id:uuid}\")",
+            ),
+        ];
+        for (path, expected_error) in conflicts {
+            let error = router
+                .try_insert(new_endpoint_id("new_item", Method::GET, path))
+                .unwrap_err();
+            assert_eq!(error, expected_error);
+        }
+
+        /*
+         * Variables with different kinds of constraints (or none) don't
+         * conflict, as long as their routes can be told apart in the OpenAPI
+         * definition: either the variables have different names, or the
+         * routes are for different methods.
+         */
+        router.insert(new_endpoint_id(
+            "item_by_number",
+            Method::GET,
+            "/items/{number:integer}",
+        ));
+        router.insert(new_endpoint_id("item_put", Method::PUT, "/items/{id}"));
+        router.insert(new_endpoint_id(
+            "item_by_code",
+            Method::GET,
+            "/items/{code:[A-Z]+}",
         ));
     }
 
@@ -920,9 +2044,131 @@ mod test {
         assert_eq!(result.handler.label(), "h7");
     }
 
+    #[test]
+    fn test_wildcard() {
+        let mut router = HttpRouter::new();
+        router.insert(new_endpoint(
+            new_handler_named("h8"),
+            Method::GET,
+            "/artifacts/{path:.*}",
+        ));
+        router.insert(new_endpoint(
+            new_handler_named("h9"),
+            Method::GET,
+            "/projects/{project_id}/files/{path:.*}",
+        ));
+        router.insert(new_endpoint(
+            new_handler_named("h10"),
+            Method::GET,
+            "/projects/{project_id}/files",
+        ));
+
+        let result =
+            router.lookup_route(&Method::GET, "/artifacts/a/b/c.txt").unwrap();
+        assert_eq!(result.handler.label(), "h8");
+        assert_eq!(result.variables.get("path").unwrap(), "a/b/c.txt");
+        let result =
+            router.lookup_route(&Method::GET, "/artifacts//a//b/").unwrap();
+        assert_eq!(result.variables.get("path").unwrap(), "a/b");
+
+        /*
+         * The wildcard can match nothing at all, but not if the resource
+         * itself has handlers.
+         */
+        let result = router.lookup_route(&Method::GET, "/artifacts/").unwrap();
+        assert_eq!(result.handler.label(), "h8");
+        assert_eq!(result.variables.get("path").unwrap(), "");
+        let result =
+            router.lookup_route(&Method::GET, "/projects/p1/files").unwrap();
+        assert_eq!(result.handler.label(), "h10");
+        assert_eq!(result.variables.get("path"), None);
+        let result =
+            router.lookup_route(&Method::GET, "/projects/p1/files/x").unwrap();
+        assert_eq!(result.handler.label(), "h9");
+        assert_eq!(result.variables.get("project_id").unwrap(), "p1");
+        assert_eq!(result.variables.get("path").unwrap(), "x");
+
+        let ret: Vec<_> = router.into_iter().map(|x| x.0).collect();
+        assert_eq!(ret, vec![
+            "/artifacts/{path:.*}",
+            "/projects/{project_id}/files",
+            "/projects/{project_id}/files/{path:.*}",
+        ]);
+    }
+
+    #[test]
+    fn test_wildcard_conflicts() {
+        let mut router = HttpRouter::new();
+        router.insert(new_endpoint_id(
+            "artifact_view",
+            Method::GET,
+            "/artifacts/{path:.*}",
+        ));
+        router.insert(new_endpoint(
+            new_handler(),
+            Method::GET,
+            "/projects/{id}",
+        ));
+
+        let conflicts = vec![
+            (
+                "/files/{path:.*}/info",
+                "URI path \"/files/{path:.*}/info\": wildcard path segment \
+                 (variable name: \"path\") must be the last segment",
+            ),
+            (
+                "/artifacts/{rest:.*}",
+                "URI path \"/artifacts/{rest:.*}\": attempted to use variable \
+                 name \"rest\", but a different name (\"path\") has already \
+                 been used for this (endpoint \"test_handler\" conflicts with \
+                 endpoint \"artifact_view\" for GET \"/artifacts/{path:.*}\")",
+            ),
+            (
+                "/projects/{id}/{id:.*}",
+                "URI path \"/projects/{id}/{id:.*}\": variable name \"id\" is \
+                 used more than once",
+            ),
+            (
+                "/artifacts/{path}",
+                "URI path \"/artifacts/{path}\": attempted to register route \
+                 for method \"GET\" whose path in the OpenAPI definition \
+                 (\"/artifacts/{path}\") is the same as that of an existing \
+                 route (endpoint \"test_handler\" conflicts with endpoint \
+                 \"artifact_view\" for GET \"/artifacts/{path:.*}\")",
+            ),
+        ];
+        for (path, expected_error) in conflicts {
+            let error = router
+                .try_insert(new_endpoint(new_handler(), Method::GET, path))
+                .unwrap_err();
+            assert_eq!(error, expected_error);
+        }
+
+        /*
+         * The same wildcard can be used for other methods, and wildcards can
+         * be used alongside other routes from the same resource.
+         */
+        router.insert(new_endpoint(
+            new_handler(),
+            Method::PUT,
+            "/artifacts/{path:.*}",
+        ));
+        router.insert(new_endpoint(
+            new_handler(),
+            Method::GET,
+            "/artifacts/latest",
+        ));
+        router.insert(new_endpoint(
+            new_handler(),
+            Method::GET,
+            "/projects/{path:.*}",
+        ));
+        router.insert(new_endpoint(new_handler(), Method::GET, "/{path:.*}"));
+    }
+
     #[test]
     fn test_iter_null() {
-        let router = HttpRouter::new();
+        let router = HttpRouter::<()>::new();
         let ret: Vec<_> = router.into_iter().map(|x| (x.0, x.1)).collect();
         assert_eq!(ret, vec![]);
     }
@@ -966,4 +2212,328 @@ mod test {
             ("/".to_string(), "POST".to_string(),),
         ]);
     }
+
+    #[test]
+    fn test_try_insert_conflict() {
+        let mut router = HttpRouter::new();
+        router.insert(new_endpoint_id(
+            "project_view",
+            Method::GET,
+            "/projects/{id}",
+        ));
+
+        /*
+         * A conflicting route is reported as an error, and none of it is added
+         * to the router.
+         */
+        let error = router
+            .try_insert(new_endpoint_id(
+                "instance_list",
+                Method::GET,
+                "/projects/{project_id}/instances",
+            ))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "URI path \"/projects/{project_id}/instances\": attempted to use \
+             variable name \"project_id\", but a different name (\"id\") has \
+             already been used for this (endpoint \"instance_list\" conflicts \
+             with endpoint \"project_view\" for GET \"/projects/{id}\")"
+        );
+        let error = router
+            .try_insert(new_endpoint_id(
+                "project_view2",
+                Method::GET,
+                "/projects//{id}",
+            ))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "URI path \"/projects//{id}\": attempted to create duplicate \
+             route for method \"GET\" (endpoint \"project_view2\" conflicts \
+             with endpoint \"project_view\" for GET \"/projects/{id}\")"
+        );
+        let ret: Vec<_> = router.into_iter().map(|x| (x.0, x.1)).collect();
+        assert_eq!(ret, vec![(
+            "/projects/{id}".to_string(),
+            "GET".to_string()
+        )]);
+
+        router
+            .try_insert(new_endpoint(
+                new_handler(),
+                Method::GET,
+                "/projects/{id}/instances",
+            ))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_automatic_methods() {
+        let mut router = HttpRouter::new();
+        router.insert(new_endpoint(
+            new_handler_named("h1"),
+            Method::GET,
+            "/foo",
+        ));
+        router.insert(new_endpoint(
+            new_handler_named("h2"),
+            Method::PUT,
+            "/foo",
+        ));
+        router.insert(new_endpoint(
+            new_handler_named("h3"),
+            Method::POST,
+            "/bar",
+        ));
+        router.insert(new_endpoint(
+            new_handler_named("h4"),
+            Method::GET,
+            "/baz",
+        ));
+        router.insert(new_endpoint(
+            new_handler_named("h5"),
+            Method::HEAD,
+            "/baz",
+        ));
+
+        let allow = |error: HttpError| {
+            let headers = error.headers.unwrap();
+            headers
+                .get(http::header::ALLOW)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        /* HEAD requests are handled by the GET handler, if there is one. */
+        let result = router.lookup_route(&Method::HEAD, "/foo").unwrap();
+        assert_eq!(result.handler.label(), "h1");
+        assert!(result.discard_body);
+        let error = router.lookup_route(&Method::HEAD, "/bar").unwrap_err();
+        assert_eq!(error.status_code, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(allow(error), "OPTIONS, POST");
+        let result = router.lookup_route(&Method::HEAD, "/baz").unwrap();
+        assert_eq!(result.handler.label(), "h5");
+        assert!(!result.discard_body);
+        let result = router.lookup_route(&Method::GET, "/foo").unwrap();
+        assert!(!result.discard_body);
+
+        /* OPTIONS requests are handled by the router itself. */
+        let result = router.lookup_route(&Method::OPTIONS, "/foo").unwrap();
+        assert_eq!(result.handler.label(), "automatic OPTIONS handler");
+        assert!(result.middleware.is_empty());
+        assert!(!result.discard_body);
+        let error =
+            router.lookup_route(&Method::OPTIONS, "/nonexistent").unwrap_err();
+        assert_eq!(error.status_code, StatusCode::NOT_FOUND);
+
+        /* Other methods fail with the list of those that are allowed. */
+        let error = router.lookup_route(&Method::DELETE, "/foo").unwrap_err();
+        assert_eq!(error.status_code, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(allow(error), "GET, HEAD, OPTIONS, PUT");
+
+        router.set_automatic_methods(false);
+        let error = router.lookup_route(&Method::HEAD, "/foo").unwrap_err();
+        assert_eq!(error.status_code, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(allow(error), "GET, PUT");
+        let error = router.lookup_route(&Method::OPTIONS, "/foo").unwrap_err();
+        assert_eq!(error.status_code, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(allow(error), "GET, PUT");
+        let result = router.lookup_route(&Method::HEAD, "/baz").unwrap();
+        assert_eq!(result.handler.label(), "h5");
+    }
+
+    #[test]
+    fn test_path_normalization() {
+        let mut router = HttpRouter::new();
+        router.insert(new_endpoint(
+            new_handler_named("h1"),
+            Method::GET,
+            "/projects/{name}",
+        ));
+        router.insert(new_endpoint(
+            new_handler_named("h2"),
+            Method::GET,
+            "/my projects",
+        ));
+        router.insert(new_endpoint(
+            new_handler_named("h3"),
+            Method::GET,
+            "/files/{path:.*}",
+        ));
+
+        /* Each segment is decoded, and may contain arbitrary UTF-8. */
+        let result = router
+            .lookup_route(&Method::GET, "/projects/%7Bevil%7D%20%C3%A9t%C3%A9")
+            .unwrap();
+        assert_eq!(result.handler.label(), "h1");
+        assert_eq!(result.variables.get("name").unwrap(), "{evil} été");
+        let result =
+            router.lookup_route(&Method::GET, "/my%20projects").unwrap();
+        assert_eq!(result.handler.label(), "h2");
+        let error =
+            router.lookup_route(&Method::GET, "/projects/%FF").unwrap_err();
+        assert_eq!(error.status_code, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error.external_message,
+            "URI path is not valid UTF-8 when decoded"
+        );
+
+        /*
+         * Dot segments are resolved, but encoded ones are rejected, even when
+         * they're part of a segment containing an encoded "/".
+         */
+        let result =
+            router.lookup_route(&Method::GET, "/files/./a/../b/c").unwrap();
+        assert_eq!(result.handler.label(), "h3");
+        assert_eq!(result.variables.get("path").unwrap(), "b/c");
+        for path in &["/files/a/%2e%2E/c", "/files/%2E", "/files/..%2Fetc"] {
+            let error = router.lookup_route(&Method::GET, path).unwrap_err();
+            assert_eq!(error.status_code, StatusCode::BAD_REQUEST);
+        }
+        let result =
+            router.lookup_route(&Method::GET, "/../projects/p1").unwrap();
+        assert_eq!(result.variables.get("name").unwrap(), "p1");
+        let result =
+            router.lookup_route(&Method::GET, "/projects/p1/../p2").unwrap();
+        assert_eq!(result.variables.get("name").unwrap(), "p2");
+
+        /* Encoded slashes aren't allowed unless the endpoint opts in. */
+        let error =
+            router.lookup_route(&Method::GET, "/projects/a%2Fb").unwrap_err();
+        assert_eq!(error.status_code, StatusCode::BAD_REQUEST);
+        let error =
+            router.lookup_route(&Method::GET, "/files/a%2fb").unwrap_err();
+        assert_eq!(error.status_code, StatusCode::BAD_REQUEST);
+        let result =
+            router.lookup_route(&Method::OPTIONS, "/projects/a%2Fb").unwrap();
+        assert_eq!(result.handler.label(), "automatic OPTIONS handler");
+        router.insert(
+            new_endpoint(
+                new_handler_named("h4"),
+                Method::GET,
+                "/objects/{key}",
+            )
+            .allow_encoded_slashes(),
+        );
+        let result =
+            router.lookup_route(&Method::GET, "/objects/a%2Fb%2F").unwrap();
+        assert_eq!(result.handler.label(), "h4");
+        assert_eq!(result.variables.get("key").unwrap(), "a/b/");
+    }
+
+    #[test]
+    fn test_trailing_slash() {
+        let mut router = HttpRouter::new();
+        router.insert(new_endpoint(
+            new_handler_named("h1"),
+            Method::GET,
+            "/projects/{name}",
+        ));
+        router.insert(new_endpoint(new_handler_named("h2"), Method::GET, "/"));
+
+        /* By default, a trailing "/" is ignored. */
+        let result =
+            router.lookup_route(&Method::GET, "/projects/p1/").unwrap();
+        assert_eq!(result.handler.label(), "h1");
+
+        router.set_redirect_trailing_slash(true);
+        let location = |error: HttpError| {
+            assert_eq!(error.status_code, StatusCode::PERMANENT_REDIRECT);
+            let headers = error.headers.unwrap();
+            headers
+                .get(http::header::LOCATION)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        let error =
+            router.lookup_route(&Method::GET, "/projects/p1/").unwrap_err();
+        assert_eq!(location(error), "/projects/p1");
+        let error = router
+            .lookup_route(&Method::GET, "/projects/%7Bp1%7D//?a=b/")
+            .unwrap_err();
+        assert_eq!(location(error), "/projects/%7Bp1%7D?a=b/");
+
+        /*
+         * The location always starts with exactly one "/", so that it can't
+         * be taken for a reference to another host.
+         */
+        let error = router
+            .lookup_route(&Method::GET, "//projects/evil.example/")
+            .unwrap_err();
+        assert_eq!(location(error), "/projects/evil.example");
+        let error = router
+            .lookup_route(&Method::GET, "/projects/\\evil.example/")
+            .unwrap_err();
+        assert_eq!(location(error), "/projects/%5Cevil.example");
+        let result =
+            router.lookup_route(&Method::GET, "/projects/p1?a=b/").unwrap();
+        assert_eq!(result.handler.label(), "h1");
+        assert_eq!(result.variables.get("name").unwrap(), "p1");
+
+        /* Requests that would fail anyway aren't redirected. */
+        let error =
+            router.lookup_route(&Method::GET, "/nonexistent/").unwrap_err();
+        assert_eq!(error.status_code, StatusCode::NOT_FOUND);
+        let error =
+            router.lookup_route(&Method::PUT, "/projects/p1/").unwrap_err();
+        assert_eq!(error.status_code, StatusCode::METHOD_NOT_ALLOWED);
+
+        /* The root is never redirected. */
+        let result = router.lookup_route(&Method::GET, "/").unwrap();
+        assert_eq!(result.handler.label(), "h2");
+    }
+
+    #[test]
+    fn test_fallback() {
+        let mut router = HttpRouter::new();
+        router.insert(new_endpoint(
+            new_handler_named("h1"),
+            Method::GET,
+            "/projects/{name}",
+        ));
+
+        let error =
+            router.lookup_route(&Method::GET, "/nonexistent").unwrap_err();
+        assert_eq!(error.status_code, StatusCode::NOT_FOUND);
+
+        router.set_fallback(new_handler_named("fallback"), vec![]);
+        for path in &["/nonexistent", "/", "/projects", "/projects/p1/info"] {
+            let result = router.lookup_route(&Method::PUT, path).unwrap();
+            assert_eq!(result.handler.label(), "fallback");
+            assert!(result.variables.is_empty());
+        }
+
+        /* The fallback isn't used for paths that match a route. */
+        let result = router.lookup_route(&Method::GET, "/projects/p1").unwrap();
+        assert_eq!(result.handler.label(), "h1");
+        let error =
+            router.lookup_route(&Method::PUT, "/projects/p1").unwrap_err();
+        assert_eq!(error.status_code, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(router.into_endpoints().len(), 1);
+    }
+
+    #[test]
+    fn test_into_endpoints() {
+        let mut router = HttpRouter::new();
+        router.insert(new_endpoint(new_handler(), Method::GET, "/"));
+        router.insert(new_endpoint(new_handler(), Method::PUT, "/foo/{bar}"));
+        router.insert(new_endpoint(new_handler(), Method::GET, "/foo/{bar}"));
+        router.insert(new_endpoint(new_handler(), Method::GET, "/qux/baz"));
+        let endpoints = router
+            .into_endpoints()
+            .into_iter()
+            .map(|e| (e.method, e.path))
+            .collect::<Vec<_>>();
+        assert_eq!(endpoints, vec![
+            (Method::GET, "/".to_string()),
+            (Method::GET, "/foo/{bar}".to_string()),
+            (Method::PUT, "/foo/{bar}".to_string()),
+            (Method::GET, "/qux/baz".to_string()),
+        ]);
+    }
 }
@@ -5,44 +5,203 @@
 
 use super::api_description::ApiDescription;
 use super::config::ConfigDropshot;
+use super::config::ConfigHttp2;
+use super::config::ConfigUnixSocket;
 use super::error::HttpError;
 use super::handler::RequestContext;
 use super::http_util::HEADER_REQUEST_ID;
+use super::idle::ConnectionActivity;
+use super::idle::IdleTimeoutAcceptor;
+use super::idle::IdleTimeoutConn;
+use super::middleware::middleware_dispatch;
 use super::router::HttpRouter;
+use super::shutdown::drain_deadline;
+use super::shutdown::ConnectionExecutor;
+use super::shutdown::InFlightRequests;
+use super::tls::tls_server_config;
+use super::tls::ClientCertificate;
+use super::tls::TlsCertResolver;
+#[cfg(unix)]
+use super::unix::unix_listener_bind;
+#[cfg(unix)]
+use super::unix::unix_peer_credentials;
+#[cfg(unix)]
+use super::unix::UnixAcceptor;
 
 use futures::future::BoxFuture;
 use futures::lock::Mutex;
+use futures::stream::FuturesUnordered;
 use futures::FutureExt;
+use futures::StreamExt;
+use hyper::body::HttpBody;
+use hyper::server::accept::Accept;
+use hyper::server::conn::AddrIncoming;
 use hyper::server::conn::AddrStream;
 use hyper::service::Service;
 use hyper::Body;
 use hyper::Request;
 use hyper::Response;
-use std::any::Any;
+use std::fmt;
+use std::future::Future;
 use std::net::SocketAddr;
 use std::num::NonZeroUsize;
+use std::pin::Pin;
 use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Duration;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::ReadBuf;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+use tokio::sync::SemaphorePermit;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tower_layer::Identity;
+use tower_layer::Layer;
 use uuid::Uuid;
 
 use slog::Logger;
 
 /* TODO Replace this with something else? */
-type GenericError = Box<dyn std::error::Error + Send + Sync>;
+pub(crate) type GenericError = Box<dyn std::error::Error + Send + Sync>;
+
+/**
+ * smallest allowed value for `request_header_max_bytes` (Hyper's minimum read
+ * buffer size)
+ */
+const REQUEST_HEADER_MIN_MAX_BYTES: usize = 8192;
+
+/** largest flow control window allowed by HTTP/2 (RFC 7540, section 6.9.1) */
+const HTTP2_MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+/**
+ * Checks the value of `name`, a limit on how many requests (or connections)
+ * may be in progress at once.  It must allow at least one, and since it's
+ * enforced with a semaphore, it can't be more than the number of permits a
+ * semaphore can have.
+ */
+pub(crate) fn check_limit(name: &str, limit: usize) -> Result<(), String> {
+    if limit == 0 || limit > Semaphore::MAX_PERMITS {
+        return Err(format!(
+            "{} must be between 1 and {}",
+            name,
+            Semaphore::MAX_PERMITS
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * `ServerContext` describes the server-wide state that consumers provide when
+ * creating an `HttpServer`.  Request handlers access it using
+ * `RequestContext::context()`.  It's implemented for any type that can be
+ * shared safely among the server's request handlers.
+ */
+pub trait ServerContext: Send + Sync + 'static {}
+
+impl<T: 'static> ServerContext for T where T: Send + Sync {}
 
 /**
  * Stores shared state used by the Dropshot server.
  */
-pub struct DropshotState {
+pub struct DropshotState<C: ServerContext> {
     /** caller-specific state */
-    pub private: Arc<dyn Any + Send + Sync + 'static>,
+    pub private: C,
     /** static server configuration parameters */
     pub config: ServerConfig,
     /** request router */
-    pub router: HttpRouter,
+    pub router: HttpRouter<C>,
     /** server-wide log handle */
     pub log: Logger,
+    /** requests that have been received but not yet completed */
+    pub(crate) in_flight: Arc<InFlightRequests>,
+    /** slots for requests in progress, if the server limits its concurrency */
+    pub(crate) request_slots: Option<Semaphore>,
+    /** slots for open connections, if the server limits how many it accepts */
+    pub(crate) connection_slots: Option<Arc<Semaphore>>,
+}
+
+impl<C: ServerContext> DropshotState<C> {
+    /**
+     * Set up the state for serving `api` with the given configuration and
+     * caller-specific state.  Fails if the limits in `config` are invalid.
+     */
+    fn new(
+        config: &ConfigDropshot,
+        api: ApiDescription<C>,
+        private: C,
+        log: &Logger,
+    ) -> Result<DropshotState<C>, String> {
+        if let Some(max) = config.max_concurrent_requests {
+            check_limit("max_concurrent_requests", max)?;
+        }
+        if let Some(max) = config.max_connections {
+            check_limit("max_connections", max)?;
+        }
+
+        let state = DropshotState {
+            private,
+            config: ServerConfig {
+                /* We start aggressively to ensure test coverage. */
+                request_body_max_bytes: config.request_body_max_bytes,
+                handler_timeout: config
+                    .handler_timeout_ms
+                    .map(Duration::from_millis),
+                request_header_timeout: config
+                    .request_header_timeout_ms
+                    .map(Duration::from_millis),
+                request_body_timeout: config
+                    .request_body_timeout_ms
+                    .map(Duration::from_millis),
+                request_body_min_bytes_per_sec: config
+                    .request_body_min_bytes_per_sec,
+                concurrency_queue_timeout: Duration::from_millis(
+                    config.concurrency_queue_timeout_ms,
+                ),
+                max_connections: config.max_connections,
+                idle_timeout: config.idle_timeout_ms.map(Duration::from_millis),
+                keep_alive: config.keep_alive,
+                request_header_max_bytes: config.request_header_max_bytes,
+                http2: config.http2.clone(),
+                tls_handshake_timeout: config
+                    .tls
+                    .as_ref()
+                    .map(|tls| Duration::from_millis(tls.handshake_timeout_ms)),
+                page_max_nitems: NonZeroUsize::new(10000).unwrap(),
+                page_default_nitems: NonZeroUsize::new(100).unwrap(),
+            },
+            router: api.into_router(),
+            log: log.new(o!()),
+            in_flight: Arc::new(InFlightRequests::default()),
+            request_slots: config.max_concurrent_requests.map(Semaphore::new),
+            connection_slots: config
+                .max_connections
+                .map(|max| Arc::new(Semaphore::new(max))),
+        };
+
+        for (path, method, _) in &state.router {
+            debug!(state.log, "registered endpoint";
+                "method" => &method,
+                "path" => &path
+            );
+        }
+
+        Ok(state)
+    }
+
+    /**
+     * Returns the number of requests that the server has received but not yet
+     * finished handling.
+     */
+    pub fn requests_in_flight(&self) -> usize {
+        self.in_flight.count()
+    }
 }
 
 /**
@@ -52,6 +211,28 @@ pub struct DropshotState {
 pub struct ServerConfig {
     /** maximum allowed size of a request body */
     pub request_body_max_bytes: usize,
+    /** default limit on how long a request handler may run */
+    pub handler_timeout: Option<Duration>,
+    /** limit on how long a client may take to send request headers */
+    pub request_header_timeout: Option<Duration>,
+    /** limit on how long a client may take to send a request body */
+    pub request_body_timeout: Option<Duration>,
+    /** minimum average rate at which a client must send a request body */
+    pub request_body_min_bytes_per_sec: Option<u64>,
+    /** how long a request may wait for its turn when at a concurrency limit */
+    pub concurrency_queue_timeout: Duration,
+    /** maximum number of connections open at once */
+    pub max_connections: Option<usize>,
+    /** how long a connection may be idle before the server closes it */
+    pub idle_timeout: Option<Duration>,
+    /** whether HTTP/1 connections may be reused for more than one request */
+    pub keep_alive: bool,
+    /** maximum size of the headers of an HTTP/1 request */
+    pub request_header_max_bytes: Option<usize>,
+    /** HTTP/2 settings, if the server accepts HTTP/2 */
+    pub http2: Option<ConfigHttp2>,
+    /** how long a client may take to complete a TLS handshake */
+    pub tls_handshake_timeout: Option<Duration>,
     /** maximum size of any page of results */
     pub page_max_nitems: NonZeroUsize,
     /** default size for a page of results */
@@ -66,19 +247,62 @@ pub struct ServerConfig {
  * (i.e., it should consume self).  But you should be able to close() it.  Once
  * you've called close(), you shouldn't be able to call it again.
  */
-pub struct HttpServer {
-    app_state: Arc<DropshotState>,
+pub struct HttpServer<C: ServerContext> {
+    app_state: Arc<DropshotState<C>>,
     server_future: Option<BoxFuture<'static, Result<(), hyper::Error>>>,
-    local_addr: SocketAddr,
-    close_channel: Option<tokio::sync::oneshot::Sender<()>>,
+    local_addrs: Vec<SocketAddr>,
+    /**
+     * used to begin a graceful shutdown, optionally with a deadline after
+     * which remaining connections are closed
+     */
+    close_channel: Option<tokio::sync::oneshot::Sender<Option<Duration>>>,
+    /** source of TLS certificates, if this server terminates TLS */
+    tls_resolver: Option<Arc<TlsCertResolver>>,
 }
 
-impl HttpServer {
+impl<C: ServerContext> HttpServer<C> {
+    /**
+     * Returns the address on which the server is listening for the configured
+     * `bind_address` (or the first listener passed to `new_with_listeners()`).
+     * This is useful when `bind_address` specified port 0.  See `local_addrs()`
+     * for servers listening on more than one address.
+     */
     pub fn local_addr(&self) -> SocketAddr {
-        self.local_addr
+        self.local_addrs[0]
+    }
+
+    /**
+     * Returns all of the TCP addresses on which the server is listening: the
+     * one for `bind_address` first, followed by those for
+     * `additional_bind_addresses` in the order they were configured.  (For
+     * servers created with `new_with_listeners()`, these are in the same order
+     * as the listeners.)
+     */
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
     }
 
-    pub fn close(mut self) {
+    /**
+     * Begin a graceful shutdown of the server.  The server stops accepting
+     * connections and closes idle ones, but waits as long as it takes for
+     * requests in progress to complete.  See `close_with_drain_timeout()` to
+     * put a limit on that.
+     */
+    pub fn close(self) {
+        self.send_close(None)
+    }
+
+    /**
+     * Like `close()`, but if connections remain open `drain_timeout` after
+     * shutdown begins (generally because requests on them have not finished),
+     * those connections are forcibly closed, cancelling the requests in
+     * progress.  Each cancelled request is logged.
+     */
+    pub fn close_with_drain_timeout(self, drain_timeout: Duration) {
+        self.send_close(Some(drain_timeout))
+    }
+
+    fn send_close(mut self, drain_timeout: Option<Duration>) {
         /*
          * It should be impossible to close a channel that's already been closed
          * because close() consumes self.  It should also be impossible to fail
@@ -87,7 +311,15 @@ impl HttpServer {
          */
         let channel =
             self.close_channel.take().expect("already closed somehow");
-        channel.send(()).expect("failed to send close signal");
+        channel.send(drain_timeout).expect("failed to send close signal");
+    }
+
+    /**
+     * Returns the number of requests that the server has received but not yet
+     * finished handling.
+     */
+    pub fn requests_in_flight(&self) -> usize {
+        self.app_state.requests_in_flight()
     }
 
     /*
@@ -116,75 +348,811 @@ impl HttpServer {
      * `HttpServer` (and await the result) to actually start the server.  You
      * can call `close()` to begin a graceful shutdown of the server, which will
      * be complete when the `run()` Future is resolved.
+     *
+     * If `config` specifies more than one address to listen on, all of them
+     * serve the same API using the same state, and `close()` shuts all of them
+     * down.
+     *
+     * If `config` includes a TLS configuration, the server serves HTTPS using
+     * the certificate chain and private key described there.
+     *
      * TODO-cleanup We should be able to take a reference to the ApiDescription.
      * We currently can't because we need to hang onto the router.
      */
     pub fn new(
         config: &ConfigDropshot,
-        api: ApiDescription,
-        private: Arc<dyn Any + Send + Sync + 'static>,
+        api: ApiDescription<C>,
+        private: C,
+        log: &Logger,
+    ) -> Result<HttpServer<C>, GenericError> {
+        HttpServer::new_with_layer(config, api, private, log, Identity::new())
+    }
+
+    /**
+     * Like `new()`, but each connection's [`ServerRequestHandler`] (the Hyper
+     * service that routes requests to the API's handlers) is wrapped with the
+     * Tower `layer`.  To apply several layers, combine them into one (e.g.,
+     * with `tower::ServiceBuilder`).  The outermost layer sees each request
+     * first and each response last.
+     *
+     * Layers run outside of Dropshot's request handling, so they see requests
+     * for which there's no endpoint and the error responses that Dropshot
+     * generates, but they cannot use extractors or the `RequestContext`.
+     * Values that a layer adds to a request's extensions are available to
+     * handlers and middleware in `RequestContext::extensions`.  An error
+     * returned by a layer causes Hyper to close the connection without
+     * sending a response.
+     */
+    pub fn new_with_layer<L>(
+        config: &ConfigDropshot,
+        api: ApiDescription<C>,
+        private: C,
+        log: &Logger,
+        layer: L,
+    ) -> Result<HttpServer<C>, GenericError>
+    where
+        L: Layer<ServerRequestHandler<C>> + Send + Sync + 'static,
+        L::Service:
+            Service<Request<Body>, Response = Response<Body>> + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<GenericError>,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        let bind_addresses = std::iter::once(&config.bind_address)
+            .chain(config.additional_bind_addresses.iter());
+        let mut listeners = Vec::new();
+        for bind_address in bind_addresses {
+            let listener =
+                std::net::TcpListener::bind(bind_address).map_err(|error| {
+                    format!("binding to {}: {}", bind_address, error)
+                })?;
+            listeners.push(listener);
+        }
+
+        HttpServer::start(config, listeners, api, private, log, layer)
+    }
+
+    /**
+     * Like `new()`, but rather than binding new sockets for the addresses in
+     * `config`, the server accepts connections on `listeners`, which must
+     * already be bound and listening.  This allows a process supervisor (or a
+     * previous instance of the server) to own the listening sockets, as with
+     * systemd-style socket activation (see [`crate::listeners_from_env`]) or
+     * when handing sockets from an old process to a new one during a restart.
+     *
+     * `config.bind_address` and `config.additional_bind_addresses` are
+     * ignored.  The rest of `config` (including TLS and Unix domain socket
+     * configuration) applies as usual.  `local_addr()` reports the address of
+     * the first listener.  At least one listener must be provided.
+     */
+    pub fn new_with_listeners(
+        config: &ConfigDropshot,
+        listeners: Vec<std::net::TcpListener>,
+        api: ApiDescription<C>,
+        private: C,
         log: &Logger,
-    ) -> Result<HttpServer, hyper::Error> {
+    ) -> Result<HttpServer<C>, GenericError> {
+        HttpServer::start(config, listeners, api, private, log, Identity::new())
+    }
+
+    /**
+     * Common implementation of the constructors: serve `api` on each of
+     * `listeners` (and the Unix domain socket in `config`, if any), wrapping
+     * each connection's request handler with `layer`.
+     */
+    fn start<L>(
+        config: &ConfigDropshot,
+        listeners: Vec<std::net::TcpListener>,
+        api: ApiDescription<C>,
+        private: C,
+        log: &Logger,
+        layer: L,
+    ) -> Result<HttpServer<C>, GenericError>
+    where
+        L: Layer<ServerRequestHandler<C>> + Send + Sync + 'static,
+        L::Service:
+            Service<Request<Body>, Response = Response<Body>> + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<GenericError>,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        if listeners.is_empty() {
+            return Err("at least one listener is required".into());
+        }
+        if let Some(max_bytes) = config.request_header_max_bytes {
+            if max_bytes < REQUEST_HEADER_MIN_MAX_BYTES {
+                return Err(format!(
+                    "request_header_max_bytes must be at least {}",
+                    REQUEST_HEADER_MIN_MAX_BYTES
+                )
+                .into());
+            }
+        }
+        if let Some(http2) = &config.http2 {
+            let window_sizes = [
+                (
+                    "initial_stream_window_size",
+                    http2.initial_stream_window_size,
+                ),
+                (
+                    "initial_connection_window_size",
+                    http2.initial_connection_window_size,
+                ),
+            ];
+            for (name, size) in window_sizes.iter() {
+                if size.map_or(false, |size| size > HTTP2_MAX_WINDOW_SIZE) {
+                    return Err(format!(
+                        "http2.{} must be at most {}",
+                        name, HTTP2_MAX_WINDOW_SIZE
+                    )
+                    .into());
+                }
+            }
+        }
+
         /* TODO-cleanup too many Arcs? */
         let log_close = log.new(o!());
-        let app_state = Arc::new(DropshotState {
-            private,
-            config: ServerConfig {
-                /* We start aggressively to ensure test coverage. */
-                request_body_max_bytes: config.request_body_max_bytes,
-                page_max_nitems: NonZeroUsize::new(10000).unwrap(),
-                page_default_nitems: NonZeroUsize::new(100).unwrap(),
-            },
-            router: api.into_router(),
-            log: log.new(o!()),
+        let app_state =
+            Arc::new(DropshotState::new(config, api, private, log)?);
+        let layer: RequestHandlerLayer<C> = Arc::new(move |handler| {
+            LayeredRequestHandler::new(layer.layer(handler))
         });
 
-        for (path, method, _) in &app_state.router {
-            debug!(app_state.log, "registered endpoint";
-                "method" => &method,
-                "path" => &path
+        let make_service =
+            ServerConnectionHandler::new(Arc::clone(&app_state), layer);
+        let connections = ConnectionExecutor::default();
+        let (tx, rx) = tokio::sync::oneshot::channel::<Option<Duration>>();
+        let shutdown = async move {
+            let drain_timeout = rx.await.expect(
+                "dropshot server shutting down without invoking close()",
             );
+            info!(log_close, "received request to begin graceful shutdown";
+                "drain_timeout_ms" =>
+                    drain_timeout.map(|timeout| timeout.as_millis() as u64),
+            );
+            drain_timeout
         }
+        .shared();
 
-        let make_service = ServerConnectionHandler::new(Arc::clone(&app_state));
-        let builder = hyper::Server::try_bind(&config.bind_address)?;
-        let server = builder.serve(make_service);
-        let local_addr = server.local_addr();
-        info!(app_state.log, "listening"; "local_addr" => %local_addr);
+        /*
+         * We run a separate Hyper server for each socket that we listen on.
+         * They all share the same state (including the router), and they all
+         * begin shutting down when `close()` is invoked.
+         */
+        let mut servers = Vec::new();
+        let mut local_addrs = Vec::new();
 
-        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
-        let graceful = server.with_graceful_shutdown(async move {
-            rx.await.expect(
-                "dropshot server shutting down without invoking close()",
-            );
-            info!(log_close, "received request to begin graceful shutdown");
-        });
+        let (tls_acceptor, tls_resolver) = match &config.tls {
+            None => (None, None),
+            Some(tls_config) => {
+                let resolver = Arc::new(TlsCertResolver::new(tls_config)?);
+                let server_config = tls_server_config(
+                    tls_config,
+                    Arc::clone(&resolver),
+                    config.http2.is_some(),
+                )?;
+                let acceptor = TlsAcceptor::from(Arc::new(server_config));
+                (Some(acceptor), Some(resolver))
+            }
+        };
+
+        for listener in listeners {
+            let (server, local_addr) = HttpServer::serve_tcp(
+                &app_state,
+                listener,
+                tls_acceptor.as_ref(),
+                make_service.clone(),
+                connections.clone(),
+                shutdown.clone().map(|_| ()),
+            )?;
+            servers.push(server);
+            local_addrs.push(local_addr);
+        }
+
+        if let Some(unix_config) = &config.unix_socket {
+            servers.push(HttpServer::serve_unix(
+                &app_state,
+                unix_config,
+                make_service,
+                connections.clone(),
+                shutdown.clone().map(|_| ()),
+            )?);
+        }
+
+        /*
+         * The servers complete once all of their connections have closed.  If
+         * shutdown was requested with a drain timeout, `drain_deadline` closes
+         * whatever connections remain when it expires.
+         */
+        let servers = futures::future::try_join_all(servers);
+        let deadline = drain_deadline(
+            shutdown,
+            Arc::clone(&app_state.in_flight),
+            connections,
+            app_state.log.new(o!()),
+        );
+        let server_future = async move {
+            tokio::select! {
+                result = servers => result.map(|_| ()),
+                _ = deadline => unreachable!("drain deadline completed"),
+            }
+        }
+        .boxed();
 
         Ok(HttpServer {
             app_state,
-            server_future: Some(graceful.boxed()),
-            local_addr,
+            server_future: Some(server_future),
+            local_addrs,
             close_channel: Some(tx),
+            tls_resolver,
         })
     }
 
-    pub fn app_private(&self) -> Arc<dyn Any + Send + Sync + 'static> {
-        Arc::clone(&self.app_state.private)
+    /**
+     * Load the TLS certificate chain and private key again from the files
+     * named in the server's TLS configuration.  This allows a renewed
+     * certificate to be put into service without restarting the server.
+     * Connections established after this returns use the new certificate;
+     * existing connections are unaffected.
+     *
+     * Returns an error if the server was not configured to use TLS or if the
+     * certificate chain or private key could not be loaded.  In the latter
+     * case, the server continues using the previous certificate chain and key.
+     */
+    pub fn refresh_tls(&self) -> Result<(), GenericError> {
+        let resolver = self
+            .tls_resolver
+            .as_ref()
+            .ok_or("server is not configured to use TLS")?;
+        resolver.reload()?;
+        info!(self.app_state.log, "reloaded TLS certificate");
+        Ok(())
+    }
+
+    /**
+     * Create a Hyper server builder for connections accepted by `acceptor`,
+     * applying the server's connection and HTTP protocol settings.
+     */
+    fn http_builder<A>(
+        app_state: &DropshotState<C>,
+        acceptor: A,
+    ) -> hyper::server::Builder<IdleTimeoutAcceptor<A>> {
+        let config = &app_state.config;
+        let acceptor = IdleTimeoutAcceptor::new(
+            acceptor,
+            config.idle_timeout,
+            app_state.log.new(o!()),
+        );
+        let mut builder =
+            hyper::Server::builder(acceptor).http1_keepalive(config.keep_alive);
+        if let Some(timeout) = config.request_header_timeout {
+            builder = builder.http1_header_read_timeout(timeout);
+        }
+        if let Some(max_bytes) = config.request_header_max_bytes {
+            builder = builder.http1_max_buf_size(max_bytes);
+        }
+
+        match &config.http2 {
+            None => builder.http1_only(true),
+            Some(http2) => {
+                builder = builder
+                    .http2_max_concurrent_streams(http2.max_concurrent_streams)
+                    .http2_initial_stream_window_size(
+                        http2.initial_stream_window_size,
+                    )
+                    .http2_initial_connection_window_size(
+                        http2.initial_connection_window_size,
+                    )
+                    .http2_adaptive_window(http2.adaptive_window)
+                    .http2_keep_alive_interval(
+                        http2.keep_alive_interval_ms.map(Duration::from_millis),
+                    );
+                if let Some(timeout_ms) = http2.keep_alive_timeout_ms {
+                    builder = builder.http2_keep_alive_timeout(
+                        Duration::from_millis(timeout_ms),
+                    );
+                }
+                builder
+            }
+        }
+    }
+
+    /**
+     * Set up a Hyper server that accepts connections on the TCP socket
+     * `listener` until `shutdown` completes.  If `tls_acceptor` is provided,
+     * the server completes a TLS handshake on each connection before serving
+     * HTTP on it.
+     */
+    fn serve_tcp<F>(
+        app_state: &Arc<DropshotState<C>>,
+        listener: std::net::TcpListener,
+        tls_acceptor: Option<&TlsAcceptor>,
+        make_service: ServerConnectionHandler<C>,
+        executor: ConnectionExecutor,
+        shutdown: F,
+    ) -> Result<
+        (BoxFuture<'static, Result<(), hyper::Error>>, SocketAddr),
+        GenericError,
+    >
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (server, local_addr) = match tls_acceptor {
+            None => {
+                listener.set_nonblocking(true)?;
+                let tcp_listener = TcpListener::from_std(listener)?;
+                let acceptor = AddrIncoming::from_listener(tcp_listener)?;
+                let local_addr = acceptor.local_addr();
+                let server = HttpServer::http_builder(app_state, acceptor)
+                    .executor(executor)
+                    .serve(make_service);
+                (server.with_graceful_shutdown(shutdown).boxed(), local_addr)
+            }
+            Some(tls_acceptor) => {
+                listener.set_nonblocking(true)?;
+                let tcp_listener = TcpListener::from_std(listener)?;
+                let local_addr = tcp_listener.local_addr()?;
+                let acceptor = HttpsAcceptor::new(
+                    app_state,
+                    tls_acceptor.clone(),
+                    tcp_listener,
+                );
+                let server = HttpServer::http_builder(app_state, acceptor)
+                    .executor(executor)
+                    .serve(make_service);
+                (server.with_graceful_shutdown(shutdown).boxed(), local_addr)
+            }
+        };
+
+        info!(app_state.log, "listening";
+            "local_addr" => %local_addr,
+            "tls" => tls_acceptor.is_some(),
+            "http2" => app_state.config.http2.is_some(),
+        );
+        Ok((server, local_addr))
+    }
+
+    /**
+     * Set up a Hyper server that accepts connections on the Unix domain socket
+     * described by `config` until `shutdown` completes, after which the socket
+     * is removed.
+     */
+    #[cfg(unix)]
+    fn serve_unix<F>(
+        app_state: &Arc<DropshotState<C>>,
+        config: &ConfigUnixSocket,
+        make_service: ServerConnectionHandler<C>,
+        executor: ConnectionExecutor,
+        shutdown: F,
+    ) -> Result<BoxFuture<'static, Result<(), hyper::Error>>, GenericError>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let listener = unix_listener_bind(config)?;
+        let acceptor = UnixAcceptor::new(app_state.log.new(o!()), listener);
+        let server = HttpServer::http_builder(app_state, acceptor)
+            .executor(executor)
+            .serve(make_service);
+        info!(app_state.log, "listening";
+            "unix_socket" => ?config.path,
+        );
+
+        let log = app_state.log.new(o!());
+        let path = config.path.clone();
+        Ok(async move {
+            let result = server.with_graceful_shutdown(shutdown).await;
+            if let Err(error) = std::fs::remove_file(&path) {
+                warn!(log, "failed to remove Unix socket";
+                    "unix_socket" => ?path,
+                    "error" => %error,
+                );
+            }
+            result
+        }
+        .boxed())
+    }
+
+    #[cfg(not(unix))]
+    fn serve_unix<F>(
+        _app_state: &Arc<DropshotState<C>>,
+        _config: &ConfigUnixSocket,
+        _make_service: ServerConnectionHandler<C>,
+        _executor: ConnectionExecutor,
+        _shutdown: F,
+    ) -> Result<BoxFuture<'static, Result<(), hyper::Error>>, GenericError>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        Err("Unix domain sockets are not supported on this platform".into())
+    }
+
+    /**
+     * Returns the server-wide state provided when the server was created (the
+     * same object that request handlers access with
+     * `RequestContext::context()`).
+     */
+    pub fn app_private(&self) -> &C {
+        &self.app_state.private
+    }
+}
+
+/**
+ * A connection accepted by a server that terminates TLS.  The TLS handshake has
+ * already been completed.
+ */
+pub struct TlsConn {
+    stream: TlsStream<TcpStream>,
+    remote_addr: SocketAddr,
+    /**
+     * this connection's slot, if the server limits how many connections it
+     * accepts (taken before the handshake and held until the connection is
+     * closed)
+     */
+    _connection_slot: Option<OwnedSemaphorePermit>,
+}
+
+impl TlsConn {
+    /** Returns the address of the remote peer of this connection. */
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    /**
+     * Returns the verified certificate that the client presented during the
+     * handshake, if any.
+     */
+    fn client_certificate(
+        &self,
+    ) -> Result<Option<ClientCertificate>, GenericError> {
+        let (_, session) = self.stream.get_ref();
+        match session.peer_certificates() {
+            Some(certs) if !certs.is_empty() => {
+                Ok(Some(ClientCertificate::from_der(&certs[0].0)?))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl AsyncRead for TlsConn {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsConn {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+/**
+ * HttpsAcceptor is a Hyper `Accept` implementation that accepts TCP connections
+ * and completes a TLS handshake on each one before handing it to Hyper.
+ * Handshakes proceed concurrently with each other and with accepting new
+ * connections so that a slow (or malicious) client cannot prevent other
+ * clients from connecting.  Connections whose handshake fails or doesn't
+ * finish within the configured timeout are logged and dropped.
+ *
+ * Connections count against the server's `max_connections` from the time
+ * they're accepted, not just once their handshake is complete, so that clients
+ * can't exceed the limit by leaving handshakes unfinished.
+ */
+struct HttpsAcceptor {
+    log: Logger,
+    tls_acceptor: TlsAcceptor,
+    tcp_listener: TcpListener,
+    backoff: AcceptBackoff,
+    handshake_timeout: Duration,
+    connection_slots: Option<Arc<Semaphore>>,
+    max_connections: Option<usize>,
+    handshakes: FuturesUnordered<
+        BoxFuture<'static, (SocketAddr, std::io::Result<TlsConn>)>,
+    >,
+}
+
+impl HttpsAcceptor {
+    fn new<C: ServerContext>(
+        app_state: &DropshotState<C>,
+        tls_acceptor: TlsAcceptor,
+        tcp_listener: TcpListener,
+    ) -> HttpsAcceptor {
+        let log = app_state.log.new(o!());
+        HttpsAcceptor {
+            backoff: AcceptBackoff::new(log.new(o!())),
+            log,
+            tls_acceptor,
+            tcp_listener,
+            handshake_timeout: app_state.config.tls_handshake_timeout.unwrap(),
+            connection_slots: app_state.connection_slots.clone(),
+            max_connections: app_state.config.max_connections,
+            handshakes: FuturesUnordered::new(),
+        }
+    }
+}
+
+impl Accept for HttpsAcceptor {
+    type Conn = TlsConn;
+    type Error = std::io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = self.get_mut();
+
+        /* Start a handshake for each connection that's ready to be accepted. */
+        while this.backoff.poll_ready(cx).is_ready() {
+            match this.tcp_listener.poll_accept(cx) {
+                Poll::Ready(Ok((tcp_stream, remote_addr))) => {
+                    let connection_slot = match &this.connection_slots {
+                        None => None,
+                        Some(slots) => {
+                            match Arc::clone(slots).try_acquire_owned() {
+                                Ok(permit) => Some(permit),
+                                Err(_) => {
+                                    warn!(this.log,
+                                        "refusing connection: too many \
+                                         connections";
+                                        "remote_addr" => %remote_addr,
+                                        "max_connections" =>
+                                            this.max_connections,
+                                    );
+                                    continue;
+                                }
+                            }
+                        }
+                    };
+                    let handshake = tokio::time::timeout(
+                        this.handshake_timeout,
+                        this.tls_acceptor.accept(tcp_stream),
+                    );
+                    this.handshakes.push(Box::pin(async move {
+                        let stream = match handshake.await {
+                            Ok(stream) => stream,
+                            Err(_) => Err(std::io::Error::new(
+                                std::io::ErrorKind::TimedOut,
+                                "handshake timed out",
+                            )),
+                        };
+                        let conn = stream.map(|stream| TlsConn {
+                            stream,
+                            remote_addr,
+                            _connection_slot: connection_slot,
+                        });
+                        (remote_addr, conn)
+                    }));
+                }
+                Poll::Ready(Err(error)) => this.backoff.accept_failed(&error),
+                Poll::Pending => break,
+            }
+        }
+
+        loop {
+            match this.handshakes.poll_next_unpin(cx) {
+                Poll::Ready(Some((_, Ok(conn)))) => {
+                    return Poll::Ready(Some(Ok(conn)));
+                }
+                Poll::Ready(Some((remote_addr, Err(error)))) => {
+                    warn!(this.log, "TLS handshake failed";
+                        "remote_addr" => %remote_addr,
+                        "error" => %error,
+                    );
+                }
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
     }
 }
 
+/**
+ * AcceptBackoff implements the policy for errors from accept(2) used by our
+ * Hyper `Accept` implementations.  These errors are generally transient (e.g.,
+ * the client closed the connection before we accepted it, or we ran out of file
+ * descriptors), so we log them and move on rather than returning them to Hyper,
+ * which would shut down the server.  Like Hyper's own `AddrIncoming`, we pause
+ * for a moment before accepting again after errors other than those caused by
+ * the client so that we don't spin while out of resources.
+ */
+pub(crate) struct AcceptBackoff {
+    log: Logger,
+    sleep: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl AcceptBackoff {
+    pub fn new(log: Logger) -> AcceptBackoff {
+        AcceptBackoff {
+            log,
+            sleep: None,
+        }
+    }
+
+    /**
+     * Returns `Poll::Ready` when it's okay to try accepting a connection.
+     */
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if let Some(sleep) = &mut self.sleep {
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.sleep = None;
+        }
+        Poll::Ready(())
+    }
+
+    /**
+     * Record that an attempt to accept a connection failed with `error`.
+     */
+    pub fn accept_failed(&mut self, error: &std::io::Error) {
+        match error.kind() {
+            std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionReset => {
+                debug!(self.log, "failed to accept connection";
+                    "error" => %error
+                );
+            }
+            _ => {
+                warn!(self.log, "failed to accept connection";
+                    "error" => %error
+                );
+                self.sleep =
+                    Some(Box::pin(tokio::time::sleep(Duration::from_secs(1))));
+            }
+        }
+    }
+}
+
+/**
+ * Identifies the remote end of a connection.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionPeer {
+    /** the connection arrived over TCP from this address */
+    Tcp(SocketAddr),
+    /** the connection arrived on a Unix domain socket from this process */
+    Unix(UnixPeerCredentials),
+    /**
+     * the request was passed to an [`ApiService`] by code that didn't say
+     * where it came from
+     */
+    Unknown,
+}
+
+impl fmt::Display for ConnectionPeer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionPeer::Tcp(addr) => write!(f, "{}", addr),
+            ConnectionPeer::Unix(creds) => {
+                write!(f, "unix (uid {}, gid {}", creds.uid, creds.gid)?;
+                if let Some(pid) = creds.pid {
+                    write!(f, ", pid {}", pid)?;
+                }
+                write!(f, ")")
+            }
+            ConnectionPeer::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/**
+ * Credentials of the process on the other end of a Unix domain socket, as
+ * reported by the operating system when the connection was accepted.
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnixPeerCredentials {
+    /** effective user id of the peer process */
+    pub uid: u32,
+    /** effective group id of the peer process */
+    pub gid: u32,
+    /** process id of the peer, if the operating system provides it */
+    pub pid: Option<i32>,
+}
+
+/**
+ * Describes the connection on which a request arrived.  This is shared by all
+ * requests received on the same connection.
+ */
+#[derive(Debug)]
+pub struct ConnectionInfo {
+    /** remote end of the connection */
+    pub peer: ConnectionPeer,
+    /**
+     * certificate presented by the client, if the server terminates TLS and
+     * the client authenticated itself with a certificate
+     */
+    pub client_certificate: Option<ClientCertificate>,
+}
+
 /**
  * Initial entry point for handling a new connection to the HTTP server.
  * This is invoked by Hyper when a new connection is accepted.  This function
  * must return a Hyper Service object that will handle requests for this
- * connection.
+ * connection.  `has_connection_slot` indicates that the connection already
+ * holds a slot counting against `max_connections` (as TLS connections do, from
+ * before their handshake), so it doesn't need another one.
  */
-async fn http_connection_handle(
-    server: Arc<DropshotState>,
-    remote_addr: SocketAddr,
-) -> Result<ServerRequestHandler, GenericError> {
-    info!(server.log, "accepted connection"; "remote_addr" => %remote_addr);
-    Ok(ServerRequestHandler::new(server))
+async fn http_connection_handle<C: ServerContext>(
+    server: Arc<DropshotState<C>>,
+    activity: Arc<ConnectionActivity>,
+    layer: RequestHandlerLayer<C>,
+    peer: Result<ConnectionPeer, GenericError>,
+    client_certificate: Result<Option<ClientCertificate>, GenericError>,
+    has_connection_slot: bool,
+) -> Result<LayeredRequestHandler, GenericError> {
+    let (peer, client_certificate) = match (peer, client_certificate) {
+        (Ok(peer), Ok(client_certificate)) => (peer, client_certificate),
+        (Err(error), _) | (_, Err(error)) => {
+            warn!(server.log, "rejecting connection"; "error" => %error);
+            return Err(error);
+        }
+    };
+
+    /*
+     * If the server is already serving as many connections as it may, we
+     * close this one right away.  The slot is held by the request handler for
+     * this connection, which Hyper drops when the connection is closed.
+     */
+    let connection_slot = match &server.connection_slots {
+        _ if has_connection_slot => None,
+        None => None,
+        Some(slots) => match Arc::clone(slots).try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            Err(_) => {
+                warn!(server.log, "refusing connection: too many connections";
+                    "remote_addr" => %peer,
+                    "max_connections" => server.config.max_connections,
+                );
+                return Err("too many connections".into());
+            }
+        },
+    };
+
+    match &client_certificate {
+        None => {
+            info!(server.log, "accepted connection";
+                "remote_addr" => %peer
+            );
+        }
+        Some(cert) => {
+            info!(server.log, "accepted connection";
+                "remote_addr" => %peer,
+                "client_subject" => &cert.subject,
+                "client_fingerprint" => &cert.fingerprint_sha256,
+            );
+        }
+    }
+
+    let connection = ConnectionInfo {
+        peer,
+        client_certificate,
+    };
+    Ok(layer(ServerRequestHandler::new(
+        server,
+        Arc::new(connection),
+        activity,
+        connection_slot,
+    )))
 }
 
 /**
@@ -193,8 +1161,9 @@ async fn http_connection_handle(
  * Result that either represents a valid HTTP response or an error (which will
  * also get turned into an HTTP response).
  */
-async fn http_request_handle_wrap(
-    server: Arc<DropshotState>,
+async fn http_request_handle_wrap<C: ServerContext>(
+    server: Arc<DropshotState<C>>,
+    connection: Arc<ConnectionInfo>,
     request: Request<Body>,
 ) -> Result<Response<Body>, GenericError> {
     /*
@@ -210,13 +1179,24 @@ async fn http_request_handle_wrap(
         "uri" => format!("{}", request.uri()),
     ));
     trace!(request_log, "incoming request");
+    let _in_flight = server.in_flight.start(
+        &request_id,
+        request.method(),
+        request.uri().path(),
+    );
+    let mut handler_timeout = None;
+    let mut failed_middleware = None;
     let maybe_response = http_request_handle(
         Arc::clone(&server),
+        connection,
         request,
         &request_id,
         request_log.new(o!()),
+        &mut handler_timeout,
+        &mut failed_middleware,
     )
     .await;
+    let timeout_ms = handler_timeout.map(|timeout| timeout.as_millis() as u64);
 
     let response = match maybe_response {
         Err(error) => {
@@ -224,11 +1204,18 @@ async fn http_request_handle_wrap(
             let message_internal = error.internal_message.clone();
             let r = error.into_response(&request_id);
 
+            /*
+             * Errors from middleware are reported just like errors from the
+             * handler, except that we also log which middleware failed the
+             * request.
+             */
             /* TODO-debug: add request and response headers here */
             info!(request_log, "request completed";
                 "response_code" => r.status().as_str().to_string(),
                 "error_message_internal" => message_internal,
                 "error_message_external" => message_external,
+                "timeout_ms" => timeout_ms,
+                "middleware" => failed_middleware,
             );
 
             r
@@ -237,7 +1224,8 @@ async fn http_request_handle_wrap(
         Ok(response) => {
             /* TODO-debug: add request and response headers here */
             info!(request_log, "request completed";
-                "response_code" => response.status().as_str().to_string()
+                "response_code" => response.status().as_str().to_string(),
+                "timeout_ms" => timeout_ms,
             );
 
             response
@@ -247,41 +1235,145 @@ async fn http_request_handle_wrap(
     Ok(response)
 }
 
-async fn http_request_handle(
-    server: Arc<DropshotState>,
-    request: Request<Body>,
+/*
+ * On return, `handler_timeout` describes the limit (if any) that applied to
+ * the handler for this request and `failed_middleware` names the middleware
+ * (if any) that caused the request to fail so that the caller can log them.
+ */
+async fn http_request_handle<C: ServerContext>(
+    server: Arc<DropshotState<C>>,
+    connection: Arc<ConnectionInfo>,
+    mut request: Request<Body>,
     request_id: &str,
     request_log: Logger,
+    handler_timeout: &mut Option<Duration>,
+    failed_middleware: &mut Option<String>,
 ) -> Result<Response<Body>, HttpError> {
     /*
      * TODO-hardening: is it correct to (and do we correctly) read the entire
      * request body even if we decide it's too large and are going to send a 400
      * response?
-     * TODO-hardening: add a request read timeout as well so that we don't allow
-     * this to take forever.
-     * TODO-correctness: check that URL processing (particularly with slashes as
-     * the only separator) is correct.  (Do we need to URL-escape or un-escape
-     * here?  Redirect container URls that don't end it "/"?)
      * TODO-correctness: Do we need to dump the body on errors?
      */
+    let queue_timeout = server.config.concurrency_queue_timeout;
+    let _server_slot = match &server.request_slots {
+        None => None,
+        Some(slots) => {
+            Some(acquire_request_slot(slots, queue_timeout, "server").await?)
+        }
+    };
+
     let method = request.method();
     let uri = request.uri();
-    let lookup_result = server.router.lookup_route(&method, uri.path())?;
+    let path = uri.path_and_query().map_or(uri.path(), |p| p.as_str());
+    let lookup_result = server.router.lookup_route(&method, path)?;
+    let discard_body = lookup_result.discard_body;
+    *handler_timeout = lookup_result.timeout.or(server.config.handler_timeout);
+    let _endpoint_slot = match lookup_result.concurrency_limit {
+        None => None,
+        Some(slots) => {
+            Some(acquire_request_slot(slots, queue_timeout, "endpoint").await?)
+        }
+    };
+    /*
+     * Move any extensions attached to the request (e.g., by hyper or by code
+     * wrapping the server) into the context, where extractors and the handler
+     * can find them alongside anything they add themselves.
+     */
+    let extensions = std::mem::take(request.extensions_mut());
     let rqctx = RequestContext {
         server: Arc::clone(&server),
         request: Arc::new(Mutex::new(request)),
+        connection,
         path_variables: lookup_result.variables,
+        request_body_max_bytes: lookup_result
+            .request_body_max_bytes
+            .unwrap_or(server.config.request_body_max_bytes),
         request_id: request_id.to_string(),
         log: request_log,
+        extensions: Mutex::new(extensions),
+    };
+    let handler_future = middleware_dispatch(
+        Arc::new(rqctx),
+        lookup_result.middleware,
+        lookup_result.handler,
+        failed_middleware,
+    );
+    let mut response = match *handler_timeout {
+        None => handler_future.await?,
+        Some(timeout) => {
+            match tokio::time::timeout(timeout, handler_future).await {
+                Ok(result) => result?,
+                Err(_) => {
+                    return Err(HttpError::for_unavail(
+                        None,
+                        format!(
+                            "request handler timed out after {} ms",
+                            timeout.as_millis()
+                        ),
+                    ));
+                }
+            }
+        }
     };
-    let mut response = lookup_result.handler.handle_request(rqctx).await?;
     response.headers_mut().insert(
         HEADER_REQUEST_ID,
         http::header::HeaderValue::from_str(&request_id).unwrap(),
     );
+
+    /*
+     * This was a HEAD request handled by the GET handler.  The response should
+     * have the headers that the GET response would have had, including its
+     * Content-Length, but none of its body.
+     */
+    if discard_body {
+        let body = std::mem::replace(response.body_mut(), Body::empty());
+        let headers = response.headers_mut();
+        if !headers.contains_key(http::header::CONTENT_LENGTH) {
+            if let Some(length) = HttpBody::size_hint(&body).exact() {
+                headers.insert(
+                    http::header::CONTENT_LENGTH,
+                    http::header::HeaderValue::from(length),
+                );
+            }
+        }
+    }
     Ok(response)
 }
 
+/**
+ * Wait up to `queue_timeout` for one of the request slots in `slots` to become
+ * available.  If none does, the server is overloaded, so we fail the request
+ * with a 503 error that suggests when the client should try again.  `scope`
+ * says whose limit this is for the benefit of the log.
+ */
+async fn acquire_request_slot<'a>(
+    slots: &'a Semaphore,
+    queue_timeout: Duration,
+    scope: &str,
+) -> Result<SemaphorePermit<'a>, HttpError> {
+    /*
+     * Even with a zero timeout, the semaphore gets one chance to provide a
+     * slot before we give up.  It's never closed, so acquiring can only fail
+     * by timing out.
+     */
+    match tokio::time::timeout(queue_timeout, slots.acquire()).await {
+        Ok(permit) => Ok(permit.expect("request slots closed")),
+        Err(_) => {
+            let retry_after_secs =
+                std::cmp::max(1, queue_timeout.as_millis().div_ceil(1000));
+            Err(HttpError::for_unavail(
+                None,
+                format!("{} concurrent request limit reached", scope),
+            )
+            .with_header(
+                http::header::RETRY_AFTER,
+                http::header::HeaderValue::from(retry_after_secs as u64),
+            ))
+        }
+    }
+}
+
 /*
  * This function should probably be parametrized by some name of the service
  * that is expected to be unique within an organization.  That way, it would be
@@ -300,24 +1392,46 @@ fn generate_request_id() -> String {
  * using a closure to capture the state object, but the resulting code is a bit
  * simpler without it.
  */
-pub struct ServerConnectionHandler {
+pub struct ServerConnectionHandler<C: ServerContext> {
     /** backend state that will be made available to the connection handler */
-    server: Arc<DropshotState>,
+    server: Arc<DropshotState<C>>,
+    /** wraps the request handler for each connection */
+    layer: RequestHandlerLayer<C>,
 }
 
-impl ServerConnectionHandler {
+/*
+ * This can't be derived because the derived implementation would require that
+ * `C` also implement `Clone`.
+ */
+impl<C: ServerContext> Clone for ServerConnectionHandler<C> {
+    fn clone(&self) -> Self {
+        ServerConnectionHandler {
+            server: Arc::clone(&self.server),
+            layer: Arc::clone(&self.layer),
+        }
+    }
+}
+
+impl<C: ServerContext> ServerConnectionHandler<C> {
     /**
      * Create an ServerConnectionHandler with the given state object that
-     * will be made available to the handler.
+     * will be made available to the handler.  Each connection's request
+     * handler is wrapped using `layer`.
      */
-    fn new(server: Arc<DropshotState>) -> Self {
+    fn new(
+        server: Arc<DropshotState<C>>,
+        layer: RequestHandlerLayer<C>,
+    ) -> Self {
         ServerConnectionHandler {
             server: Arc::clone(&server),
+            layer,
         }
     }
 }
 
-impl Service<&AddrStream> for ServerConnectionHandler {
+impl<C: ServerContext> Service<&IdleTimeoutConn<AddrStream>>
+    for ServerConnectionHandler<C>
+{
     /*
      * Recall that a Service in this context is just something that takes a
      * request (which could be anything) and produces a response (which could be
@@ -326,7 +1440,7 @@ impl Service<&AddrStream> for ServerConnectionHandler {
      * another Service: one that accepts HTTP requests and produces HTTP
      * responses.
      */
-    type Response = ServerRequestHandler;
+    type Response = LayeredRequestHandler;
     type Error = GenericError;
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
@@ -338,60 +1452,363 @@ impl Service<&AddrStream> for ServerConnectionHandler {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, conn: &AddrStream) -> Self::Future {
+    fn call(&mut self, conn: &IdleTimeoutConn<AddrStream>) -> Self::Future {
         /*
          * We're given a borrowed reference to the AddrStream, but our interface
          * is async (which is good, so that we can support time-consuming
          * operations as part of receiving requests).  To avoid having to ensure
          * that conn's lifetime exceeds that of this async operation, we simply
-         * copy the only useful information out of the conn: the SocketAddr.  We
-         * may want to create our own connection type to encapsulate the socket
-         * address and any other per-connection state that we want to keep.
+         * copy the useful information out of the conn.  For a plain TCP
+         * connection, that's only the SocketAddr.
          */
         let server = Arc::clone(&self.server);
-        let remote_addr = conn.remote_addr();
-        Box::pin(http_connection_handle(server, remote_addr))
+        let peer = ConnectionPeer::Tcp(conn.inner().remote_addr());
+        Box::pin(http_connection_handle(
+            server,
+            conn.activity(),
+            Arc::clone(&self.layer),
+            Ok(peer),
+            Ok(None),
+            false,
+        ))
+    }
+}
+
+impl<C: ServerContext> Service<&IdleTimeoutConn<TlsConn>>
+    for ServerConnectionHandler<C>
+{
+    /*
+     * This is the same as the implementation for `AddrStream` above, except
+     * that the connection is one on which we've already completed a TLS
+     * handshake.
+     */
+    type Response = LayeredRequestHandler;
+    type Error = GenericError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, conn: &IdleTimeoutConn<TlsConn>) -> Self::Future {
+        let server = Arc::clone(&self.server);
+        let peer = ConnectionPeer::Tcp(conn.inner().remote_addr());
+        let client_certificate = conn.inner().client_certificate();
+        Box::pin(http_connection_handle(
+            server,
+            conn.activity(),
+            Arc::clone(&self.layer),
+            Ok(peer),
+            client_certificate,
+            true,
+        ))
+    }
+}
+
+#[cfg(unix)]
+impl<C: ServerContext> Service<&IdleTimeoutConn<UnixStream>>
+    for ServerConnectionHandler<C>
+{
+    /*
+     * This is the same as the implementation for `AddrStream` above, except
+     * that the connection arrived on a Unix domain socket, so the peer is
+     * identified by its credentials rather than its address.
+     */
+    type Response = LayeredRequestHandler;
+    type Error = GenericError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, conn: &IdleTimeoutConn<UnixStream>) -> Self::Future {
+        let server = Arc::clone(&self.server);
+        let peer =
+            unix_peer_credentials(conn.inner()).map(ConnectionPeer::Unix);
+        Box::pin(http_connection_handle(
+            server,
+            conn.activity(),
+            Arc::clone(&self.layer),
+            peer,
+            Ok(None),
+            false,
+        ))
     }
 }
 
 /**
- * ServerRequestHandler is a Hyper Service implementation that forwards
- * incoming requests to `http_request_handle_wrap()`, including as an argument
- * the backend server state object.  We could use `service_fn` here using a
- * closure to capture the server state object, but the resulting code is a bit
- * simpler without all that.
+ * `ApiService` is a Hyper (Tower) `Service` that handles HTTP requests by
+ * routing them to the handlers in an `ApiDescription`, just as `HttpServer`
+ * does.  It lets an API be mounted in a server that Dropshot doesn't manage,
+ * such as a larger Hyper application that serves some requests itself or
+ * dispatches them among several services.
+ *
+ * Only the parts of `ConfigDropshot` that apply to individual requests take
+ * effect (e.g., `request_body_max_bytes`, `handler_timeout_ms`, and
+ * `max_concurrent_requests`).  Listening sockets, TLS, connection limits, and
+ * HTTP protocol settings are up to the server in which the service is
+ * mounted, as is graceful shutdown.
+ *
+ * The service is cheap to clone, and clones share the same state.  Requests
+ * are reported as having arrived from [`ConnectionPeer::Unknown`] unless the
+ * caller uses [`ApiService::with_peer()`] to say otherwise.
  */
-pub struct ServerRequestHandler {
+pub struct ApiService<C: ServerContext> {
     /** backend state that will be made available to the request handler */
-    server: Arc<DropshotState>,
+    server: Arc<DropshotState<C>>,
+    /** information about the connection on which requests arrive */
+    connection: Arc<ConnectionInfo>,
 }
 
-impl ServerRequestHandler {
+/*
+ * This can't be derived because the derived implementation would require that
+ * `C` also implement `Clone`.
+ */
+impl<C: ServerContext> Clone for ApiService<C> {
+    fn clone(&self) -> Self {
+        ApiService {
+            server: Arc::clone(&self.server),
+            connection: Arc::clone(&self.connection),
+        }
+    }
+}
+
+impl<C: ServerContext> ApiService<C> {
+    /**
+     * Create a service that handles requests using the endpoints registered in
+     * `api`.  `private` is the server-wide state that handlers access using
+     * `RequestContext::context()`.  Fails if `config` is invalid (e.g., if
+     * `max_concurrent_requests` is 0).
+     */
+    pub fn new(
+        config: &ConfigDropshot,
+        api: ApiDescription<C>,
+        private: C,
+        log: &Logger,
+    ) -> Result<ApiService<C>, GenericError> {
+        let server = Arc::new(DropshotState::new(config, api, private, log)?);
+        Ok(ApiService::for_connection(server, ConnectionPeer::Unknown, None))
+    }
+
+    fn for_connection(
+        server: Arc<DropshotState<C>>,
+        peer: ConnectionPeer,
+        client_certificate: Option<ClientCertificate>,
+    ) -> ApiService<C> {
+        ApiService {
+            server,
+            connection: Arc::new(ConnectionInfo {
+                peer,
+                client_certificate,
+            }),
+        }
+    }
+
+    /**
+     * Returns a service that shares this one's state but reports `peer` as the
+     * remote end of the connection for each request (in
+     * `RequestContext::connection`).  Code that accepts connections itself
+     * would typically use this to create a service for each connection.
+     */
+    pub fn with_peer(&self, peer: ConnectionPeer) -> ApiService<C> {
+        ApiService::for_connection(Arc::clone(&self.server), peer, None)
+    }
+
+    /**
+     * Returns the server-wide state provided when the service was created (the
+     * same object that request handlers access with
+     * `RequestContext::context()`).
+     */
+    pub fn app_private(&self) -> &C {
+        &self.server.private
+    }
+
+    /**
+     * Returns the number of requests that the service has received but not yet
+     * finished handling.
+     */
+    pub fn requests_in_flight(&self) -> usize {
+        self.server.requests_in_flight()
+    }
+}
+
+impl<C: ServerContext> Service<Request<Body>> for ApiService<C> {
+    type Response = Response<Body>;
+    type Error = GenericError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        /*
+         * Requests that exceed the server's concurrency limit wait for a slot
+         * (or fail) inside the handler, so we're always ready to accept more.
+         */
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        Box::pin(http_request_handle_wrap(
+            Arc::clone(&self.server),
+            Arc::clone(&self.connection),
+            req,
+        ))
+    }
+}
+
+/**
+ * ServerRequestHandler is a Hyper Service implementation that forwards
+ * incoming requests on one connection to an [`ApiService`], keeping track of
+ * the requests in progress so that the connection isn't considered idle.
+ * `HttpServer` creates one for each connection that it accepts, which is what
+ * the layer passed to [`HttpServer::new_with_layer()`] wraps.
+ */
+pub struct ServerRequestHandler<C: ServerContext> {
+    /** handles requests for the API */
+    api: ApiService<C>,
+    /** tracks requests in progress so the connection isn't considered idle */
+    activity: Arc<ConnectionActivity>,
+    /**
+     * this connection's slot, if the server limits how many connections it
+     * accepts (held until the connection is closed)
+     */
+    _connection_slot: Option<OwnedSemaphorePermit>,
+}
+
+impl<C: ServerContext> ServerRequestHandler<C> {
     /**
      * Create a ServerRequestHandler object with the given state object that
      * will be provided to the handler function.
      */
-    fn new(server: Arc<DropshotState>) -> Self {
+    fn new(
+        server: Arc<DropshotState<C>>,
+        connection: Arc<ConnectionInfo>,
+        activity: Arc<ConnectionActivity>,
+        connection_slot: Option<OwnedSemaphorePermit>,
+    ) -> Self {
         ServerRequestHandler {
-            server: Arc::clone(&server),
+            api: ApiService {
+                server,
+                connection,
+            },
+            activity,
+            _connection_slot: connection_slot,
         }
     }
 }
 
-impl Service<Request<Body>> for ServerRequestHandler {
+impl<C: ServerContext> Service<Request<Body>> for ServerRequestHandler<C> {
     type Response = Response<Body>;
     type Error = GenericError;
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
     fn poll_ready(
         &mut self,
-        _cx: &mut Context<'_>,
+        cx: &mut Context<'_>,
     ) -> Poll<Result<(), Self::Error>> {
-        // TODO is this right?
-        Poll::Ready(Ok(()))
+        self.api.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let activity = self.activity.request_started();
+        let response = self.api.call(req);
+        Box::pin(async move {
+            let _activity = activity;
+            response.await
+        })
+    }
+}
+
+/**
+ * Wraps the `ServerRequestHandler` for a new connection with the layer
+ * provided to `HttpServer::new_with_layer()` (or with nothing, for servers
+ * created without a layer).
+ */
+type RequestHandlerLayer<C> =
+    Arc<dyn Fn(ServerRequestHandler<C>) -> LayeredRequestHandler + Send + Sync>;
+
+/**
+ * LayeredRequestHandler is the Hyper Service that `HttpServer` uses for each
+ * connection: a `ServerRequestHandler` wrapped in whatever layers the consumer
+ * provided.  The type of the wrapped service depends on those layers, so we
+ * erase it here to avoid making `HttpServer` generic over it.
+ */
+pub struct LayeredRequestHandler {
+    service: Box<
+        dyn Service<
+                Request<Body>,
+                Response = Response<Body>,
+                Error = GenericError,
+                Future = ResponseFuture,
+            > + Send,
+    >,
+}
+
+type ResponseFuture = BoxFuture<'static, Result<Response<Body>, GenericError>>;
+
+impl LayeredRequestHandler {
+    fn new<S>(service: S) -> LayeredRequestHandler
+    where
+        S: Service<Request<Body>, Response = Response<Body>> + Send + 'static,
+        S::Error: Into<GenericError>,
+        S::Future: Send + 'static,
+    {
+        LayeredRequestHandler {
+            service: Box::new(BoxedFutureService(service)),
+        }
+    }
+}
+
+impl Service<Request<Body>> for LayeredRequestHandler {
+    type Response = Response<Body>;
+    type Error = GenericError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        self.service.call(req)
+    }
+}
+
+/**
+ * Adapts a Service whose future and error types may be anything suitable into
+ * one that uses the types that `LayeredRequestHandler` expects.
+ */
+struct BoxedFutureService<S>(S);
+
+impl<S> Service<Request<Body>> for BoxedFutureService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<GenericError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = GenericError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx).map_err(Into::into)
     }
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
-        Box::pin(http_request_handle_wrap(Arc::clone(&self.server), req))
+        let response = self.0.call(req);
+        Box::pin(async move { response.await.map_err(Into::into) })
     }
 }
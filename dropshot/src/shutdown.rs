@@ -0,0 +1,173 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Facilities for keeping track of the work a server has in progress so that
+ * shutdown can report on it and, if it takes too long, cut it short
+ */
+
+use slog::Logger;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::task::JoinHandle;
+
+/**
+ * Tracks the requests that the server has received but not yet finished
+ * handling.
+ */
+#[derive(Default)]
+pub(crate) struct InFlightRequests {
+    /** requests in progress, keyed by request id */
+    requests: Mutex<BTreeMap<String, InFlightRequest>>,
+}
+
+/** Describes a request that the server has not yet finished handling. */
+struct InFlightRequest {
+    method: String,
+    path: String,
+    start_time: Instant,
+}
+
+impl InFlightRequests {
+    /** Returns the number of requests in progress. */
+    pub fn count(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    /**
+     * Record that handling of the request `request_id` has begun.  The request
+     * is considered finished when the returned guard is dropped, which also
+     * happens if the future handling the request is cancelled.
+     */
+    pub fn start(
+        self: &Arc<Self>,
+        request_id: &str,
+        method: &http::Method,
+        path: &str,
+    ) -> InFlightRequestGuard {
+        let request = InFlightRequest {
+            method: method.as_str().to_string(),
+            path: path.to_string(),
+            start_time: Instant::now(),
+        };
+        self.requests.lock().unwrap().insert(request_id.to_string(), request);
+        InFlightRequestGuard {
+            requests: Arc::clone(self),
+            request_id: request_id.to_string(),
+        }
+    }
+
+    /**
+     * Log each request in progress (longest-running first) because the
+     * server's drain timeout expired before it finished.
+     */
+    pub fn log_aborted(&self, log: &Logger) {
+        let requests = self.requests.lock().unwrap();
+        let mut aborted = requests.iter().collect::<Vec<_>>();
+        aborted.sort_by_key(|(_, request)| request.start_time);
+        for (request_id, request) in aborted {
+            warn!(log, "aborting request";
+                "req_id" => request_id,
+                "method" => &request.method,
+                "path" => &request.path,
+                "elapsed_ms" => request.start_time.elapsed().as_millis() as u64,
+            );
+        }
+    }
+}
+
+/**
+ * Removes a request from its `InFlightRequests` when dropped.
+ */
+pub(crate) struct InFlightRequestGuard {
+    requests: Arc<InFlightRequests>,
+    request_id: String,
+}
+
+impl Drop for InFlightRequestGuard {
+    fn drop(&mut self) {
+        self.requests.requests.lock().unwrap().remove(&self.request_id);
+    }
+}
+
+/**
+ * ConnectionExecutor is the executor that Hyper uses to spawn the tasks that
+ * serve each connection.  Unlike Hyper's default executor, it keeps track of
+ * those tasks so that they can be aborted if they don't finish on their own
+ * when the server is shutting down.
+ */
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionExecutor {
+    tasks: Arc<Mutex<ConnectionTasks>>,
+}
+
+#[derive(Default)]
+struct ConnectionTasks {
+    next_id: u64,
+    handles: HashMap<u64, JoinHandle<()>>,
+}
+
+impl ConnectionExecutor {
+    /**
+     * Abort all tasks that are still running, returning how many there were.
+     */
+    pub fn abort_all(&self) -> usize {
+        let handles = std::mem::take(&mut self.tasks.lock().unwrap().handles);
+        for handle in handles.values() {
+            handle.abort();
+        }
+        handles.len()
+    }
+}
+
+impl<F> hyper::rt::Executor<F> for ConnectionExecutor
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    fn execute(&self, future: F) {
+        /*
+         * We hold the lock while spawning the task so that the task cannot
+         * remove itself from the map before we've inserted it.
+         */
+        let mut tasks = self.tasks.lock().unwrap();
+        let id = tasks.next_id;
+        tasks.next_id += 1;
+        let task_list = Arc::clone(&self.tasks);
+        let handle = tokio::spawn(async move {
+            future.await;
+            task_list.lock().unwrap().handles.remove(&id);
+        });
+        tasks.handles.insert(id, handle);
+    }
+}
+
+/**
+ * Returns a future that, once `shutdown` reports a drain timeout, waits for
+ * that long and then forcibly closes the connections that remain, logging the
+ * requests that were still in progress.  The returned future never completes;
+ * it's intended to be polled alongside the servers themselves.
+ */
+pub(crate) async fn drain_deadline<F>(
+    shutdown: F,
+    in_flight: Arc<InFlightRequests>,
+    connections: ConnectionExecutor,
+    log: Logger,
+) where
+    F: Future<Output = Option<Duration>>,
+{
+    if let Some(drain_timeout) = shutdown.await {
+        tokio::time::sleep(drain_timeout).await;
+        warn!(log, "drain timeout expired; aborting remaining connections";
+            "drain_timeout_ms" => drain_timeout.as_millis() as u64,
+            "requests_in_flight" => in_flight.count(),
+        );
+        in_flight.log_aborted(&log);
+        let nconnections = connections.abort_all();
+        info!(log, "aborted connections"; "count" => nconnections);
+    }
+
+    futures::future::pending::<()>().await;
+}
@@ -0,0 +1,339 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Support for inheriting listening sockets from a process supervisor
+ *
+ * Under the `LISTEN_FDS` convention (used by systemd's socket activation, among
+ * others), the supervisor binds the listening sockets itself and passes them to
+ * the server as file descriptors 3, 4, and so on.  `LISTEN_FDS` says how many
+ * there are, and `LISTEN_PID` says which process they're meant for.
+ */
+
+use super::server::GenericError;
+
+#[cfg(unix)]
+use std::os::unix::io::FromRawFd;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+
+/** first file descriptor passed under the `LISTEN_FDS` convention */
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+/**
+ * Returns the TCP listening sockets passed to this process under the
+ * `LISTEN_FDS` convention, suitable for passing to
+ * [`crate::HttpServer::new_with_listeners`].  If the environment does not
+ * describe any sockets for this process, this returns an empty list.
+ *
+ * On success, the sockets are marked close-on-exec.  This should be called at
+ * most once, since the sockets are owned by the returned listeners.  Every
+ * socket passed must be a listening TCP socket.  Systems other than Unix-like
+ * ones never pass sockets this way, so this always returns an empty list
+ * there.
+ *
+ * This leaves the `LISTEN_PID`, `LISTEN_FDS`, and `LISTEN_FDNAMES` environment
+ * variables alone, since changing the environment isn't safe once the process
+ * may have other threads (as it does once a Tokio runtime has started).
+ * `LISTEN_PID` keeps child processes from mistaking the sockets for their own,
+ * but a caller that starts children can remove the variables from their
+ * environments (e.g., with `std::process::Command::env_remove()`).
+ */
+#[cfg(unix)]
+pub fn listeners_from_env() -> Result<Vec<std::net::TcpListener>, GenericError>
+{
+    let listen_pid = std::env::var("LISTEN_PID").ok();
+    let listen_fds = std::env::var("LISTEN_FDS").ok();
+    listeners_from_fds(
+        listen_pid.as_deref(),
+        listen_fds.as_deref(),
+        LISTEN_FDS_START,
+    )
+}
+
+#[cfg(not(unix))]
+pub fn listeners_from_env() -> Result<Vec<std::net::TcpListener>, GenericError>
+{
+    Ok(Vec::new())
+}
+
+/**
+ * Implements `listeners_from_env()` given the values of `LISTEN_PID` and
+ * `LISTEN_FDS` and the first file descriptor to use.
+ */
+#[cfg(unix)]
+fn listeners_from_fds(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    first_fd: RawFd,
+) -> Result<Vec<std::net::TcpListener>, GenericError> {
+    /*
+     * As with sd_listen_fds(3), if either variable is missing or the sockets
+     * were meant for some other process, then there are no sockets for us.
+     */
+    let (listen_pid, listen_fds) = match (listen_pid, listen_fds) {
+        (Some(listen_pid), Some(listen_fds)) => (listen_pid, listen_fds),
+        _ => return Ok(Vec::new()),
+    };
+    let pid = listen_pid.parse::<u32>().map_err(|error| {
+        format!("parsing LISTEN_PID {:?}: {}", listen_pid, error)
+    })?;
+    if pid != std::process::id() {
+        return Ok(Vec::new());
+    }
+    let nfds = listen_fds.parse::<u16>().map_err(|error| {
+        format!("parsing LISTEN_FDS {:?}: {}", listen_fds, error)
+    })?;
+
+    /*
+     * Check every descriptor before taking ownership of any of them.  If we
+     * took them one at a time, a bad descriptor would cause us to drop (and so
+     * close) the listeners for the ones before it, which would still belong to
+     * our caller.
+     */
+    let fds = first_fd..first_fd + RawFd::from(nfds);
+    for fd in fds.clone() {
+        check_listener_fd(fd)?;
+    }
+    for fd in fds.clone() {
+        /* Safety: this is a valid socket, as we checked above. */
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } != 0 {
+            return Err(format!(
+                "setting close-on-exec on inherited file descriptor {}: {}",
+                fd,
+                std::io::Error::last_os_error()
+            )
+            .into());
+        }
+    }
+
+    /*
+     * Safety: each of these is a TCP socket, as we checked above, and ownership
+     * of it was passed to us.
+     */
+    Ok(fds
+        .map(|fd| unsafe { std::net::TcpListener::from_raw_fd(fd) })
+        .collect())
+}
+
+/**
+ * Check that `fd`, which we have not yet taken ownership of, is a listening TCP
+ * socket.
+ */
+#[cfg(unix)]
+fn check_listener_fd(fd: RawFd) -> Result<(), GenericError> {
+    let mut sotype: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    /*
+     * Safety: we pass a pointer to (and the size of) a local integer, which is
+     * what the kernel fills in for SO_TYPE.
+     */
+    let rv = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            &mut sotype as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rv != 0 {
+        return Err(format!(
+            "inherited file descriptor {}: {}",
+            fd,
+            std::io::Error::last_os_error()
+        )
+        .into());
+    }
+    if sotype != libc::SOCK_STREAM {
+        return Err(format!(
+            "inherited file descriptor {} is not a stream socket",
+            fd
+        )
+        .into());
+    }
+
+    /*
+     * Safety: `sockaddr_storage` is plain data for which all zeroes is valid,
+     * and we pass a pointer to (and the size of) it, which is large enough for
+     * any socket address the kernel fills in.
+     */
+    let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let mut len =
+        std::mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let rv = unsafe {
+        libc::getsockname(
+            fd,
+            &mut addr as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+            &mut len,
+        )
+    };
+    if rv != 0 {
+        return Err(format!(
+            "inherited file descriptor {} is not a TCP socket: {}",
+            fd,
+            std::io::Error::last_os_error()
+        )
+        .into());
+    }
+    match libc::c_int::from(addr.ss_family) {
+        libc::AF_INET | libc::AF_INET6 => (),
+        _ => {
+            return Err(format!(
+                "inherited file descriptor {} is not a TCP socket",
+                fd
+            )
+            .into());
+        }
+    }
+
+    /*
+     * A connected socket would pass the checks above, but we can't accept
+     * connections on it.
+     * Safety: as for SO_TYPE above.
+     */
+    let mut accepting: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let rv = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            &mut accepting as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rv != 0 {
+        return Err(format!(
+            "inherited file descriptor {}: {}",
+            fd,
+            std::io::Error::last_os_error()
+        )
+        .into());
+    }
+    if accepting == 0 {
+        return Err(format!(
+            "inherited file descriptor {} is not a listening socket",
+            fd
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::listeners_from_fds;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::io::IntoRawFd;
+
+    #[test]
+    fn test_listeners_from_fds_none() {
+        let pid = std::process::id().to_string();
+        assert!(listeners_from_fds(None, None, 3).unwrap().is_empty());
+        assert!(listeners_from_fds(Some(&pid), None, 3).unwrap().is_empty());
+        assert!(listeners_from_fds(None, Some("1"), 3).unwrap().is_empty());
+        assert!(listeners_from_fds(Some("1"), Some("1"), 3)
+            .unwrap()
+            .is_empty());
+        assert!(listeners_from_fds(Some(&pid), Some("0"), 3)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_listeners_from_fds_bad() {
+        let pid = std::process::id().to_string();
+        let error = listeners_from_fds(Some("one"), Some("1"), 3).unwrap_err();
+        assert!(error.to_string().starts_with("parsing LISTEN_PID \"one\""));
+        let error = listeners_from_fds(Some(&pid), Some("-1"), 3).unwrap_err();
+        assert!(error.to_string().starts_with("parsing LISTEN_FDS \"-1\""));
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let fd = socket.into_raw_fd();
+        let error = listeners_from_fds(Some(&pid), Some("1"), fd).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("inherited file descriptor {} is not a stream socket", fd)
+        );
+        /* Safety: we still own this descriptor. */
+        unsafe { libc::close(fd) };
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream =
+            std::net::TcpStream::connect(listener.local_addr().unwrap())
+                .unwrap();
+        let fd = stream.into_raw_fd();
+        let error = listeners_from_fds(Some(&pid), Some("1"), fd).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "inherited file descriptor {} is not a listening socket",
+                fd
+            )
+        );
+        /* Safety: we still own this descriptor. */
+        unsafe { libc::close(fd) };
+    }
+
+    /*
+     * If any descriptor is bad, none of them should be closed, since they all
+     * still belong to the caller.
+     */
+    #[test]
+    fn test_listeners_from_fds_bad_later() {
+        let pid = std::process::id().to_string();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        /*
+         * The descriptors have to be consecutive.  F_DUPFD allocates the
+         * lowest unused descriptor at or above the one we ask for, so we only
+         * ever use descriptors it gave us, even if other tests are opening
+         * and closing their own at the same time.  If the one after the
+         * listener's copy is taken, try again above that.
+         * Safety: these calls only duplicate and close descriptors that we
+         * own.
+         */
+        let mut next = 900;
+        let fd = loop {
+            let fd = unsafe {
+                libc::fcntl(listener.as_raw_fd(), libc::F_DUPFD, next)
+            };
+            assert!(fd >= next);
+            let fd2 =
+                unsafe { libc::fcntl(socket.as_raw_fd(), libc::F_DUPFD, fd) };
+            assert!(fd2 > fd);
+            if fd2 == fd + 1 {
+                break fd;
+            }
+            unsafe { libc::close(fd) };
+            unsafe { libc::close(fd2) };
+            next = fd2 + 1;
+        };
+
+        let error = listeners_from_fds(Some(&pid), Some("2"), fd).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "inherited file descriptor {} is not a stream socket",
+                fd + 1
+            )
+        );
+        /* Safety: we still own both descriptors. */
+        assert_ne!(unsafe { libc::fcntl(fd, libc::F_GETFD) }, -1);
+        unsafe { libc::close(fd) };
+        unsafe { libc::close(fd + 1) };
+    }
+
+    #[test]
+    fn test_listeners_from_fds() {
+        let pid = std::process::id().to_string();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let fd = listener.into_raw_fd();
+
+        let listeners = listeners_from_fds(Some(&pid), Some("1"), fd).unwrap();
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].local_addr().unwrap(), addr);
+    }
+}
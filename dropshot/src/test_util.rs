@@ -19,7 +19,6 @@ use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use serde::Serialize;
 use slog::Logger;
-use std::any::Any;
 use std::fmt::Debug;
 use std::fs;
 use std::iter::Iterator;
@@ -28,7 +27,6 @@ use std::path::Path;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicU32;
 use std::sync::atomic::Ordering;
-use std::sync::Arc;
 use tokio::task::JoinHandle;
 
 use crate::api_description::ApiDescription;
@@ -37,13 +35,14 @@ use crate::error::HttpErrorResponseBody;
 use crate::logging::ConfigLogging;
 use crate::pagination::ResultsPage;
 use crate::server::HttpServer;
+use crate::server::ServerContext;
 
 /**
  * List of allowed HTTP headers in responses.  This is used to make sure we
  * don't leak headers unexpectedly.
  */
-const ALLOWED_HEADER_NAMES: [&str; 4] =
-    ["content-length", "content-type", "date", "x-request-id"];
+const ALLOWED_HEADER_NAMES: [&str; 5] =
+    ["allow", "content-length", "content-type", "date", "x-request-id"];
 
 /**
  * ClientTestContext encapsulates several facilities associated with using an
@@ -407,15 +406,15 @@ impl LogContext {
  * test-case pattern of setting up a logger, server, and client and tearing them
  * all down at the end.
  */
-pub struct TestContext {
+pub struct TestContext<Context: ServerContext> {
     pub client_testctx: ClientTestContext,
-    pub server: HttpServer,
+    pub server: HttpServer<Context>,
     pub log: Logger,
     server_task: JoinHandle<Result<(), hyper::Error>>,
     log_context: Option<LogContext>,
 }
 
-impl TestContext {
+impl<Context: ServerContext> TestContext<Context> {
     /**
      * Instantiate a TestContext by creating a new Dropshot server with `api`,
      * `private`, `config_dropshot`, and `log`, and then creating a
@@ -426,12 +425,12 @@ impl TestContext {
      * in order for it to be used concurrently by many tests.
      */
     pub fn new(
-        api: ApiDescription,
-        private: Arc<dyn Any + Send + Sync + 'static>,
+        api: ApiDescription<Context>,
+        private: Context,
         config_dropshot: &ConfigDropshot,
         log_context: Option<LogContext>,
         log: Logger,
-    ) -> TestContext {
+    ) -> TestContext<Context> {
         assert_eq!(
             0,
             config_dropshot.bind_address.port(),
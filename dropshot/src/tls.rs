@@ -0,0 +1,284 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Facilities for terminating TLS in a Dropshot server
+ */
+
+use super::config::ConfigTls;
+use super::config::ConfigTlsVersion;
+use super::server::GenericError;
+
+use rustls::server::AllowAnyAnonymousOrAuthenticatedClient;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::server::ClientHello;
+use rustls::server::ResolvesServerCert;
+use rustls::sign::CertifiedKey;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::RwLock;
+use x509_parser::extensions::GeneralName;
+
+/**
+ * Supplies the server's certificate chain and private key to rustls for each
+ * TLS handshake.  The certificate and key can be replaced while the server is
+ * running (see [`TlsCertResolver::reload()`]).  Handshakes that have already
+ * started (and connections that have already been established) are unaffected
+ * by a reload.
+ */
+pub(crate) struct TlsCertResolver {
+    /** configuration describing where the certificate and key live */
+    config: ConfigTls,
+    /** certificate chain and key used for new handshakes */
+    certified_key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl TlsCertResolver {
+    /**
+     * Load the certificate chain and private key described by `config`.
+     */
+    pub fn new(config: &ConfigTls) -> Result<TlsCertResolver, GenericError> {
+        let certified_key = load_certified_key(config)?;
+        Ok(TlsCertResolver {
+            config: config.clone(),
+            certified_key: RwLock::new(Arc::new(certified_key)),
+        })
+    }
+
+    /**
+     * Load the certificate chain and private key from the files named in the
+     * configuration again, replacing the ones currently in use.  On failure,
+     * the previously-loaded certificate chain and key remain in use.
+     */
+    pub fn reload(&self) -> Result<(), GenericError> {
+        let certified_key = load_certified_key(&self.config)?;
+        *self.certified_key.write().unwrap() = Arc::new(certified_key);
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for TlsCertResolver {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.certified_key.read().unwrap()))
+    }
+}
+
+/**
+ * Construct the rustls configuration for a server using `config` whose
+ * certificates will be provided by `resolver`.  If `http2` is true, the server
+ * offers HTTP/2 to clients during the handshake.
+ */
+pub(crate) fn tls_server_config(
+    config: &ConfigTls,
+    resolver: Arc<TlsCertResolver>,
+    http2: bool,
+) -> Result<rustls::ServerConfig, GenericError> {
+    let versions: &[&'static rustls::SupportedProtocolVersion] =
+        match config.min_version {
+            ConfigTlsVersion::Tls12 => {
+                &[&rustls::version::TLS13, &rustls::version::TLS12]
+            }
+            ConfigTlsVersion::Tls13 => &[&rustls::version::TLS13],
+        };
+
+    let builder = rustls::ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(versions)
+        .map_err(|error| format!("configuring TLS: {}", error))?;
+
+    let builder = match (&config.client_ca_file, config.require_client_cert) {
+        (None, false) => builder.with_no_client_auth(),
+        (None, true) => {
+            return Err("TLS configuration requires client certificates but \
+                        does not specify \"client_ca_file\""
+                .into());
+        }
+        (Some(client_ca_file), require_client_cert) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for cert in load_certs(client_ca_file)? {
+                roots.add(&cert).map_err(|error| {
+                    format!(
+                        "loading TLS client CA certificate from {:?}: {}",
+                        client_ca_file, error
+                    )
+                })?;
+            }
+            let verifier = if require_client_cert {
+                AllowAnyAuthenticatedClient::new(roots).boxed()
+            } else {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+    };
+
+    let mut server_config = builder.with_cert_resolver(resolver);
+    if http2 {
+        server_config.alpn_protocols =
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    }
+    Ok(server_config)
+}
+
+/**
+ * Describes the certificate that a client presented during the TLS handshake.
+ * By the time this is available to request handlers, the certificate has been
+ * verified against the CA certificates in the server's `client_ca_file`.
+ */
+#[derive(Clone, Debug)]
+pub struct ClientCertificate {
+    /** distinguished name of the certificate's subject (e.g., "CN=foo") */
+    pub subject: String,
+    /**
+     * subject alternative names of supported types, each prefixed with its
+     * type: "DNS:", "URI:", "IP:", or "email:"
+     */
+    pub subject_alt_names: Vec<String>,
+    /** SHA-256 digest of the DER-encoded certificate, as lowercase hex */
+    pub fingerprint_sha256: String,
+    /** the DER-encoded certificate itself */
+    pub der: Vec<u8>,
+}
+
+impl ClientCertificate {
+    /**
+     * Extract the details of a DER-encoded certificate.
+     */
+    pub(crate) fn from_der(der: &[u8]) -> Result<Self, GenericError> {
+        let (_, cert) =
+            x509_parser::parse_x509_certificate(der).map_err(|error| {
+                format!("parsing client certificate: {}", error)
+            })?;
+
+        let subject = cert.subject().to_string();
+        let mut subject_alt_names = Vec::new();
+        let san_extension =
+            cert.subject_alternative_name().map_err(|error| {
+                format!(
+                    "parsing client certificate subject alt names: {}",
+                    error
+                )
+            })?;
+        if let Some(san_extension) = san_extension {
+            for name in &san_extension.value.general_names {
+                match name {
+                    GeneralName::DNSName(dns) => {
+                        subject_alt_names.push(format!("DNS:{}", dns));
+                    }
+                    GeneralName::URI(uri) => {
+                        subject_alt_names.push(format!("URI:{}", uri));
+                    }
+                    GeneralName::RFC822Name(email) => {
+                        subject_alt_names.push(format!("email:{}", email));
+                    }
+                    GeneralName::IPAddress(bytes) => {
+                        if let Some(ip) = ip_from_bytes(bytes) {
+                            subject_alt_names.push(format!("IP:{}", ip));
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        let digest = ring::digest::digest(&ring::digest::SHA256, der);
+        let mut fingerprint_sha256 = String::new();
+        for byte in digest.as_ref() {
+            write!(fingerprint_sha256, "{:02x}", byte).unwrap();
+        }
+
+        Ok(ClientCertificate {
+            subject,
+            subject_alt_names,
+            fingerprint_sha256,
+            der: der.to_vec(),
+        })
+    }
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> Option<IpAddr> {
+    match bytes.len() {
+        4 => {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(bytes);
+            Some(IpAddr::V4(Ipv4Addr::from(octets)))
+        }
+        16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(bytes);
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+/**
+ * Load the certificate chain and private key described by `config` into the
+ * form that rustls uses to sign handshakes.
+ */
+fn load_certified_key(
+    config: &ConfigTls,
+) -> Result<CertifiedKey, GenericError> {
+    let certs = load_certs(&config.cert_file)?;
+    let private_key = load_private_key(&config.key_file)?;
+    let signing_key =
+        rustls::sign::any_supported_type(&private_key).map_err(|error| {
+            format!(
+                "loading TLS private key from {:?}: {}",
+                config.key_file, error
+            )
+        })?;
+    Ok(CertifiedKey::new(certs, signing_key))
+}
+
+/**
+ * Read a chain of PEM-encoded certificates from the file at `path`.
+ */
+fn load_certs(path: &Path) -> Result<Vec<rustls::Certificate>, GenericError> {
+    let file = File::open(path).map_err(|error| {
+        format!("opening TLS certificate file {:?}: {}", path, error)
+    })?;
+    let certs =
+        rustls_pemfile::certs(&mut BufReader::new(file)).map_err(|error| {
+            format!("reading TLS certificates from {:?}: {}", path, error)
+        })?;
+    if certs.is_empty() {
+        return Err(format!("no TLS certificates found in {:?}", path).into());
+    }
+
+    Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+/**
+ * Read the first PEM-encoded private key from the file at `path`.  PKCS#8,
+ * PKCS#1 (RSA), and SEC1 (EC) keys are supported.
+ */
+fn load_private_key(path: &Path) -> Result<rustls::PrivateKey, GenericError> {
+    let file = File::open(path).map_err(|error| {
+        format!("opening TLS private key file {:?}: {}", path, error)
+    })?;
+    let mut reader = BufReader::new(file);
+    loop {
+        let item = rustls_pemfile::read_one(&mut reader).map_err(|error| {
+            format!("reading TLS private key from {:?}: {}", path, error)
+        })?;
+        match item {
+            Some(rustls_pemfile::Item::PKCS8Key(key))
+            | Some(rustls_pemfile::Item::RSAKey(key))
+            | Some(rustls_pemfile::Item::ECKey(key)) => {
+                return Ok(rustls::PrivateKey(key));
+            }
+            Some(_) => continue,
+            None => {
+                return Err(
+                    format!("no TLS private key found in {:?}", path).into()
+                );
+            }
+        }
+    }
+}
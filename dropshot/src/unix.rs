@@ -0,0 +1,155 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Facilities for accepting connections on Unix domain sockets
+ */
+
+use super::config::ConfigUnixSocket;
+use super::server::AcceptBackoff;
+use super::server::GenericError;
+use super::server::UnixPeerCredentials;
+
+use hyper::server::accept::Accept;
+use slog::Logger;
+use std::fs;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+use tokio::net::UnixListener;
+use tokio::net::UnixStream;
+
+/**
+ * Create a Unix domain socket listener as described by `config`, cleaning up a
+ * stale socket first if requested.  This must be called from within a tokio
+ * runtime.
+ */
+pub(crate) fn unix_listener_bind(
+    config: &ConfigUnixSocket,
+) -> Result<UnixListener, GenericError> {
+    let path = &config.path;
+    if config.remove_stale {
+        remove_stale_socket(path)?;
+    }
+
+    let listener = UnixListener::bind(path).map_err(|error| {
+        format!("binding Unix socket {:?}: {}", path, error)
+    })?;
+
+    if let Some(mode) = config.mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).map_err(
+            |error| {
+                format!("setting mode on Unix socket {:?}: {}", path, error)
+            },
+        )?;
+    }
+
+    Ok(listener)
+}
+
+/**
+ * If there's a socket at `path` that nobody is listening on, remove it.  It's
+ * not an error if there's nothing at `path`, but it is an error if there's a
+ * file there that's not a socket or a socket that's in use.
+ */
+fn remove_stale_socket(path: &Path) -> Result<(), GenericError> {
+    match fs::symlink_metadata(path) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => {
+            return Err(format!(
+                "checking for stale Unix socket {:?}: {}",
+                path, error
+            )
+            .into());
+        }
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(format!(
+                "refusing to remove {:?}: file exists and is not a socket",
+                path
+            )
+            .into());
+        }
+        Ok(_) => (),
+    }
+
+    /*
+     * The only way to tell whether a socket is stale is to try to connect to
+     * it.  This is inherently racy, but good enough for cleaning up after a
+     * server that's exited.
+     */
+    match std::os::unix::net::UnixStream::connect(path) {
+        Ok(_) => Err(format!(
+            "refusing to remove {:?}: socket is in use by another process",
+            path
+        )
+        .into()),
+        Err(error) if error.kind() == io::ErrorKind::ConnectionRefused => {
+            fs::remove_file(path).map_err(|error| {
+                format!("removing stale Unix socket {:?}: {}", path, error)
+            })?;
+            Ok(())
+        }
+        Err(error) => {
+            Err(format!("checking for stale Unix socket {:?}: {}", path, error)
+                .into())
+        }
+    }
+}
+
+/**
+ * Returns the credentials of the process on the other end of `stream`.
+ */
+pub(crate) fn unix_peer_credentials(
+    stream: &UnixStream,
+) -> Result<UnixPeerCredentials, GenericError> {
+    let ucred = stream.peer_cred().map_err(|error| {
+        format!("getting Unix socket peer credentials: {}", error)
+    })?;
+    Ok(UnixPeerCredentials {
+        uid: ucred.uid(),
+        gid: ucred.gid(),
+        pid: ucred.pid(),
+    })
+}
+
+/**
+ * UnixAcceptor is a Hyper `Accept` implementation for Unix domain sockets.
+ */
+pub(crate) struct UnixAcceptor {
+    listener: UnixListener,
+    backoff: AcceptBackoff,
+}
+
+impl UnixAcceptor {
+    pub fn new(log: Logger, listener: UnixListener) -> UnixAcceptor {
+        UnixAcceptor {
+            listener,
+            backoff: AcceptBackoff::new(log),
+        }
+    }
+}
+
+impl Accept for UnixAcceptor {
+    type Conn = UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = self.get_mut();
+        while this.backoff.poll_ready(cx).is_ready() {
+            match this.listener.poll_accept(cx) {
+                Poll::Ready(Ok((stream, _))) => {
+                    return Poll::Ready(Some(Ok(stream)))
+                }
+                Poll::Ready(Err(error)) => this.backoff.accept_failed(&error),
+                Poll::Pending => break,
+            }
+        }
+
+        Poll::Pending
+    }
+}
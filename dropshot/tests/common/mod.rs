@@ -3,6 +3,9 @@
  * Common facilities for automated testing.
  */
 
+/* Each test crate that includes this module uses only some of it. */
+#![allow(dead_code)]
+
 use dropshot::test_util::LogContext;
 use dropshot::test_util::TestContext;
 use dropshot::ApiDescription;
@@ -10,9 +13,12 @@ use dropshot::ConfigDropshot;
 use dropshot::ConfigLogging;
 use dropshot::ConfigLoggingIfExists;
 use dropshot::ConfigLoggingLevel;
-use std::sync::Arc;
+use dropshot::ServerContext;
 
-pub fn test_setup(test_name: &str, api: ApiDescription) -> TestContext {
+pub fn test_setup(
+    test_name: &str,
+    api: ApiDescription<usize>,
+) -> TestContext<usize> {
     /*
      * The IP address to which we bind can be any local IP, but we use
      * 127.0.0.1 because we know it's present, it shouldn't expose this server
@@ -23,20 +29,33 @@ pub fn test_setup(test_name: &str, api: ApiDescription) -> TestContext {
      * failures due to port conflicts.
      */
     let config_dropshot: ConfigDropshot = Default::default();
+    test_setup_with_config(test_name, api, 0 as usize, &config_dropshot)
+}
 
+/**
+ * Like `test_setup`, but for tests that need a particular server
+ * configuration or private context.  `config_dropshot` must bind to port 0.
+ */
+pub fn test_setup_with_config<C: ServerContext>(
+    test_name: &str,
+    api: ApiDescription<C>,
+    private: C,
+    config_dropshot: &ConfigDropshot,
+) -> TestContext<C> {
+    let logctx = test_logctx(test_name);
+    let log = logctx.log.new(o!());
+    TestContext::new(api, private, config_dropshot, Some(logctx), log)
+}
+
+/**
+ * Returns a log context for a test that needs to set up its server some other
+ * way than with `test_setup` or `test_setup_with_config`.
+ */
+pub fn test_logctx(test_name: &str) -> LogContext {
     let config_logging = ConfigLogging::File {
         level: ConfigLoggingLevel::Debug,
         path: "UNUSED".to_string(),
         if_exists: ConfigLoggingIfExists::Fail,
     };
-
-    let logctx = LogContext::new(test_name, &config_logging);
-    let log = logctx.log.new(o!());
-    TestContext::new(
-        api,
-        Arc::new(0 as usize),
-        &config_dropshot,
-        Some(logctx),
-        log,
-    )
+    LogContext::new(test_name, &config_logging)
 }
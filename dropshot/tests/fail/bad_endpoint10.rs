@@ -0,0 +1,38 @@
+// Copyright 2021 Oxide Computer Company
+
+use dropshot::endpoint;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+use std::sync::Arc;
+
+struct ContextA;
+struct ContextB;
+
+#[endpoint {
+    method = GET,
+    path = "/a",
+}]
+async fn endpoint_a(
+    _rqctx: Arc<RequestContext<ContextA>>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/b",
+}]
+async fn endpoint_b(
+    _rqctx: Arc<RequestContext<ContextB>>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+// All endpoints registered with an ApiDescription must use the same context.
+fn main() {
+    let mut api = ApiDescription::new();
+    api.register(endpoint_a).unwrap();
+    api.register(endpoint_b).unwrap();
+}
@@ -11,7 +11,7 @@ use std::sync::Arc;
     path = "/test",
 }]
 async fn bad_endpoint(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<()>>,
     param: String,
 ) -> Result<HttpResponseOk<()>, HttpError> {
     Ok(HttpResponseOk(()))
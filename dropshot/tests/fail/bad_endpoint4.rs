@@ -18,7 +18,7 @@ struct QueryParams {
     path = "/test",
 }]
 async fn bad_endpoint(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<()>>,
     _params: Query<QueryParams>,
 ) -> Result<HttpResponseOk<()>, HttpError> {
     Ok(HttpResponseOk(()))
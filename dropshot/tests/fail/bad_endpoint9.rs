@@ -0,0 +1,38 @@
+// Copyright 2021 Oxide Computer Company
+
+use dropshot::endpoint;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+use dropshot::UntypedBody;
+use std::sync::Arc;
+
+// Dropshot supports at most 16 extractors.
+#[endpoint {
+    method = GET,
+    path = "/test",
+}]
+async fn bad_endpoint(
+    _rqctx: Arc<RequestContext<()>>,
+    _b1: UntypedBody,
+    _b2: UntypedBody,
+    _b3: UntypedBody,
+    _b4: UntypedBody,
+    _b5: UntypedBody,
+    _b6: UntypedBody,
+    _b7: UntypedBody,
+    _b8: UntypedBody,
+    _b9: UntypedBody,
+    _b10: UntypedBody,
+    _b11: UntypedBody,
+    _b12: UntypedBody,
+    _b13: UntypedBody,
+    _b14: UntypedBody,
+    _b15: UntypedBody,
+    _b16: UntypedBody,
+    _b17: UntypedBody,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+fn main() {}
@@ -3,11 +3,18 @@
  * Tests for configuration file.
  */
 
+use dropshot::endpoint;
 use dropshot::test_util::read_config;
+use dropshot::test_util::read_json;
 use dropshot::ConfigDropshot;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
 use dropshot::HttpServer;
+use dropshot::RequestContext;
 use slog::Logger;
 use std::fs;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 /*
@@ -53,9 +60,8 @@ fn test_config_bad_bind_address_garbage() {
     );
 }
 
-fn make_server(config: &ConfigDropshot, log: &Logger) -> HttpServer {
-    HttpServer::new(&config, dropshot::ApiDescription::new(), Arc::new(0), log)
-        .unwrap()
+fn make_server(config: &ConfigDropshot, log: &Logger) -> HttpServer<()> {
+    HttpServer::new(&config, dropshot::ApiDescription::new(), (), log).unwrap()
 }
 
 #[tokio::test]
@@ -162,3 +168,167 @@ async fn test_config_bind_address() {
 
     fs::remove_file(log_path).unwrap();
 }
+
+#[test]
+fn test_config_additional_bind_addresses() {
+    let config = read_config::<ConfigDropshot>(
+        "additional_bind_addresses",
+        "bind_address = \"127.0.0.1:12220\"\nadditional_bind_addresses = \
+         [\"[::1]:12220\", \"127.0.0.1:12221\"]",
+    )
+    .unwrap();
+    assert_eq!(config.bind_address, "127.0.0.1:12220".parse().unwrap());
+    assert_eq!(config.additional_bind_addresses, vec![
+        "[::1]:12220".parse().unwrap(),
+        "127.0.0.1:12221".parse().unwrap(),
+    ]);
+}
+
+#[endpoint {
+    method = GET,
+    path = "/count",
+}]
+async fn count_request(
+    rqctx: Arc<RequestContext<AtomicUsize>>,
+) -> Result<HttpResponseOk<usize>, HttpError> {
+    let counter = rqctx.context();
+    Ok(HttpResponseOk(counter.fetch_add(1, Ordering::SeqCst) + 1))
+}
+
+#[tokio::test]
+async fn test_config_multiple_listeners() {
+    let log_path =
+        dropshot::test_util::log_file_for_test("config_multiple_listeners")
+            .as_path()
+            .display()
+            .to_string();
+    eprintln!("log file: {}", log_path);
+
+    let log_config = dropshot::ConfigLogging::File {
+        level: dropshot::ConfigLoggingLevel::Debug,
+        path: log_path.clone(),
+        if_exists: dropshot::ConfigLoggingIfExists::Append,
+    };
+    let log = log_config.to_logger("test_config_multiple_listeners").unwrap();
+
+    /*
+     * Listen on two different ports on the IPv4 loopback address and, if this
+     * system supports it, the IPv6 loopback address as well.
+     */
+    let mut additional_bind_addresses = vec!["127.0.0.1:0".parse().unwrap()];
+    if std::net::TcpListener::bind("[::1]:0").is_ok() {
+        additional_bind_addresses.push("[::1]:0".parse().unwrap());
+    }
+    let nlisteners = additional_bind_addresses.len() + 1;
+    let config = ConfigDropshot {
+        additional_bind_addresses,
+        ..Default::default()
+    };
+
+    let mut api = dropshot::ApiDescription::new();
+    api.register(count_request).unwrap();
+    let mut server =
+        HttpServer::new(&config, api, AtomicUsize::new(0), &log).unwrap();
+    let task = server.run();
+
+    let local_addrs = server.local_addrs().to_vec();
+    assert_eq!(local_addrs.len(), nlisteners);
+    assert_eq!(local_addrs[0], server.local_addr());
+    for addr in &local_addrs {
+        assert_ne!(addr.port(), 0);
+    }
+
+    /*
+     * Requests to each of the addresses should be handled by the same server,
+     * sharing the same state.
+     */
+    let client = hyper::Client::new();
+    for (i, addr) in local_addrs.iter().enumerate() {
+        let uri = format!("http://{}/count", addr).parse().unwrap();
+        let mut response = client.get(uri).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        let count: usize = read_json(&mut response).await;
+        assert_eq!(count, i + 1);
+    }
+
+    /* Closing the server should shut down all of the listeners. */
+    server.close();
+    task.await.unwrap().unwrap();
+    let client = hyper::Client::new();
+    for addr in &local_addrs {
+        let uri = format!("http://{}/count", addr).parse().unwrap();
+        let error = client.get(uri).await.unwrap_err();
+        assert!(error.is_connect());
+    }
+
+    fs::remove_file(log_path).unwrap();
+}
+
+#[tokio::test]
+async fn test_config_new_with_listeners() {
+    let log_path =
+        dropshot::test_util::log_file_for_test("config_new_with_listeners")
+            .as_path()
+            .display()
+            .to_string();
+    eprintln!("log file: {}", log_path);
+
+    let log_config = dropshot::ConfigLogging::File {
+        level: dropshot::ConfigLoggingLevel::Debug,
+        path: log_path.clone(),
+        if_exists: dropshot::ConfigLoggingIfExists::Append,
+    };
+    let log = log_config.to_logger("test_config_new_with_listeners").unwrap();
+
+    /*
+     * At least one listener is required.
+     */
+    let config = ConfigDropshot::default();
+    let error = HttpServer::new_with_listeners(
+        &config,
+        Vec::new(),
+        dropshot::ApiDescription::new(),
+        AtomicUsize::new(0),
+        &log,
+    )
+    .err()
+    .unwrap();
+    assert_eq!(error.to_string(), "at least one listener is required");
+
+    /*
+     * Bind the listener ourselves, as a process supervisor would, and hand it
+     * to the server.  The server should use it (and not the bind address in
+     * the configuration).
+     */
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let listen_addr = listener.local_addr().unwrap();
+    let config = ConfigDropshot {
+        bind_address: "127.0.0.1:1".parse().unwrap(),
+        ..Default::default()
+    };
+    let mut api = dropshot::ApiDescription::new();
+    api.register(count_request).unwrap();
+    let mut server = HttpServer::new_with_listeners(
+        &config,
+        vec![listener],
+        api,
+        AtomicUsize::new(0),
+        &log,
+    )
+    .unwrap();
+    assert_eq!(server.local_addr(), listen_addr);
+    assert_eq!(server.local_addrs(), &[listen_addr]);
+    let task = server.run();
+
+    let client = hyper::Client::new();
+    let uri = format!("http://{}/count", listen_addr).parse().unwrap();
+    let mut response = client.get(uri).await.unwrap();
+    assert_eq!(response.status(), http::StatusCode::OK);
+    let count: usize = read_json(&mut response).await;
+    assert_eq!(count, 1);
+
+    server.close();
+    task.await.unwrap().unwrap();
+
+    fs::remove_file(log_path).unwrap();
+}
@@ -0,0 +1,312 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for limits on the connections that a server accepts and how long
+ * it keeps them open.
+ */
+
+use dropshot::endpoint;
+use dropshot::test_util::read_config;
+use dropshot::test_util::TestContext;
+use dropshot::ApiDescription;
+use dropshot::ConfigDropshot;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::HttpServer;
+use dropshot::RequestContext;
+use http::Method;
+use http::StatusCode;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+/** Completes immediately. */
+#[endpoint {
+    method = GET,
+    path = "/fast",
+}]
+async fn connections_fast(
+    _rqctx: Arc<RequestContext<()>>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+/** Takes longer than the idle timeout used in these tests. */
+#[endpoint {
+    method = GET,
+    path = "/slow",
+}]
+async fn connections_slow(
+    _rqctx: Arc<RequestContext<()>>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    Ok(HttpResponseOk(()))
+}
+
+fn connections_api() -> ApiDescription<()> {
+    let mut api = ApiDescription::new();
+    api.register(connections_fast).unwrap();
+    api.register(connections_slow).unwrap();
+    api
+}
+
+fn connections_testctx(
+    test_name: &str,
+    config: &ConfigDropshot,
+) -> TestContext<()> {
+    common::test_setup_with_config(test_name, connections_api(), (), config)
+}
+
+/**
+ * Reads whatever the server sends on `stream` until it closes the connection.
+ * The server may reset the connection rather than closing it cleanly if it
+ * hasn't read everything we sent.
+ */
+async fn read_until_closed(stream: &mut TcpStream) -> String {
+    let mut response = Vec::new();
+    let result = tokio::time::timeout(
+        Duration::from_secs(30),
+        stream.read_to_end(&mut response),
+    )
+    .await
+    .expect("server did not close the connection");
+    match result {
+        Ok(_) => (),
+        Err(error) if error.kind() == ErrorKind::ConnectionReset => (),
+        Err(error) => panic!("unexpected error reading response: {}", error),
+    }
+    String::from_utf8(response).unwrap()
+}
+
+/**
+ * Sends `request` to the server at `addr` on a new connection, then returns
+ * whatever the server sends back before closing the connection.
+ */
+async fn raw_request(addr: SocketAddr, request: &str) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    read_until_closed(&mut stream).await
+}
+
+/**
+ * Opens a connection to the server at `addr` and makes a request on it,
+ * leaving the connection open.
+ */
+async fn open_connection(addr: SocketAddr) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /fast HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = Vec::new();
+    while !response.windows(4).any(|w| w == b"\r\n\r\n") {
+        let mut buf = [0; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        assert_ne!(n, 0, "server closed the connection");
+        response.extend_from_slice(&buf[..n]);
+    }
+    assert!(response.starts_with(b"HTTP/1.1 200 OK\r\n"));
+    stream
+}
+
+#[tokio::test]
+async fn test_max_connections() {
+    let config = ConfigDropshot {
+        max_connections: Some(1),
+        ..Default::default()
+    };
+    let testctx = connections_testctx("max_connections", &config);
+    let addr = testctx.server.local_addr();
+
+    /*
+     * While one connection is open, the server should close any others without
+     * responding to them.
+     */
+    let connection = open_connection(addr).await;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    assert_eq!(read_until_closed(&mut stream).await, "");
+
+    /* Once that connection is closed, new ones should be accepted again. */
+    drop(connection);
+    let start = Instant::now();
+    loop {
+        let response = raw_request(
+            addr,
+            "GET /fast HTTP/1.1\r\nHost: localhost\r\nConnection: \
+             close\r\n\r\n",
+        )
+        .await;
+        if response.starts_with("HTTP/1.1 200 OK\r\n") {
+            break;
+        }
+        assert_eq!(response, "");
+        assert!(start.elapsed() < Duration::from_secs(10));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_idle_timeout() {
+    let config = ConfigDropshot {
+        idle_timeout_ms: Some(200),
+        ..Default::default()
+    };
+    let testctx = connections_testctx("idle_timeout", &config);
+    let addr = testctx.server.local_addr();
+
+    /* A connection that's never used should be closed. */
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    assert_eq!(read_until_closed(&mut stream).await, "");
+
+    /* So should a connection that has been idle since its last request. */
+    let mut stream = open_connection(addr).await;
+    let mut rest = Vec::new();
+    tokio::time::timeout(
+        Duration::from_secs(30),
+        stream.read_to_end(&mut rest),
+    )
+    .await
+    .expect("server did not close the idle connection")
+    .unwrap();
+
+    /*
+     * A request whose handler takes longer than the idle timeout should not be
+     * affected.
+     */
+    testctx
+        .client_testctx
+        .make_request_no_body(Method::GET, "/slow", StatusCode::OK)
+        .await
+        .unwrap();
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_keep_alive_disabled() {
+    let config = ConfigDropshot {
+        keep_alive: false,
+        ..Default::default()
+    };
+    let testctx = connections_testctx("keep_alive_disabled", &config);
+    let addr = testctx.server.local_addr();
+
+    /*
+     * Without keep-alive, the server should close the connection after its
+     * first response, even though HTTP/1.1 clients expect otherwise.
+     */
+    let response = raw_request(
+        addr,
+        "GET /fast HTTP/1.1\r\nHost: localhost\r\n\r\nGET /fast \
+         HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert_eq!(response.matches("HTTP/1.1 200 OK").count(), 1);
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_request_header_max_bytes() {
+    let config = ConfigDropshot {
+        request_header_max_bytes: Some(8192),
+        ..Default::default()
+    };
+    let testctx = connections_testctx("request_header_max_bytes", &config);
+    let addr = testctx.server.local_addr();
+
+    let request = format!(
+        "GET /fast HTTP/1.1\r\nHost: localhost\r\nX-Big: {}\r\n\r\n",
+        "x".repeat(10000)
+    );
+    let response = raw_request(addr, &request).await;
+    assert!(response.starts_with("HTTP/1.1 431 "));
+
+    /* Requests with smaller headers should be unaffected. */
+    testctx
+        .client_testctx
+        .make_request_no_body(Method::GET, "/fast", StatusCode::OK)
+        .await
+        .unwrap();
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_request_header_max_bytes_too_small() {
+    let logctx = common::test_logctx("request_header_max_bytes_too_small");
+    let config = ConfigDropshot {
+        request_header_max_bytes: Some(4096),
+        ..Default::default()
+    };
+    let error = HttpServer::new(&config, connections_api(), (), &logctx.log)
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "request_header_max_bytes must be at least 8192"
+    );
+    logctx.cleanup_successful();
+}
+
+#[tokio::test]
+async fn test_max_connections_invalid() {
+    let logctx = common::test_logctx("max_connections_invalid");
+    for max in [0, usize::MAX].iter() {
+        let config = ConfigDropshot {
+            max_connections: Some(*max),
+            ..Default::default()
+        };
+        let error =
+            HttpServer::new(&config, connections_api(), (), &logctx.log)
+                .err()
+                .unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("max_connections must be between 1 and "));
+    }
+    logctx.cleanup_successful();
+}
+
+#[test]
+fn test_connections_config() {
+    let config = read_config::<ConfigDropshot>(
+        "connections_config",
+        r#"
+            bind_address = "127.0.0.1:0"
+            max_connections = 1000
+            idle_timeout_ms = 60000
+            keep_alive = false
+            request_header_max_bytes = 16384
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.max_connections, Some(1000));
+    assert_eq!(config.idle_timeout_ms, Some(60000));
+    assert!(!config.keep_alive);
+    assert_eq!(config.request_header_max_bytes, Some(16384));
+
+    let config = read_config::<ConfigDropshot>(
+        "connections_config_default",
+        r#"
+            bind_address = "127.0.0.1:0"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.max_connections, None);
+    assert_eq!(config.idle_timeout_ms, None);
+    assert!(config.keep_alive);
+    assert_eq!(config.request_header_max_bytes, None);
+}
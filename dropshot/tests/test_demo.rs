@@ -15,21 +15,33 @@
  * JSON body length)
  */
 
+use async_trait::async_trait;
 use dropshot::endpoint;
 use dropshot::test_util::read_json;
 use dropshot::test_util::read_string;
 use dropshot::ApiDescription;
+use dropshot::ApiEndpointParameter;
+use dropshot::Cookies;
+use dropshot::Extractor;
+use dropshot::Header;
 use dropshot::HttpError;
+use dropshot::HttpErrorResponseBody;
 use dropshot::HttpResponseOk;
+use dropshot::HttpResponseSetCookies;
 use dropshot::Path;
 use dropshot::Query;
 use dropshot::RequestContext;
+use dropshot::SameSite;
+use dropshot::ServerContext;
+use dropshot::SetCookie;
 use dropshot::TypedBody;
 use dropshot::UntypedBody;
 use dropshot::CONTENT_TYPE_JSON;
 use http::StatusCode;
 use hyper::Body;
+use hyper::Client;
 use hyper::Method;
+use hyper::Request;
 use hyper::Response;
 use schemars::JsonSchema;
 use serde::Deserialize;
@@ -42,7 +54,7 @@ extern crate slog;
 
 mod common;
 
-fn demo_api() -> ApiDescription {
+fn demo_api() -> ApiDescription<usize> {
     let mut api = ApiDescription::new();
     api.register(demo_handler_args_1).unwrap();
     api.register(demo_handler_args_2query).unwrap();
@@ -51,7 +63,12 @@ fn demo_api() -> ApiDescription {
     api.register(demo_handler_path_param_string).unwrap();
     api.register(demo_handler_path_param_uuid).unwrap();
     api.register(demo_handler_path_param_u32).unwrap();
+    api.register(demo_handler_path_param_wildcard).unwrap();
     api.register(demo_handler_untyped_body).unwrap();
+    api.register(demo_handler_headers).unwrap();
+    api.register(demo_handler_cookies).unwrap();
+    api.register(demo_handler_many_args).unwrap();
+    api.register(demo_handler_extensions).unwrap();
 
     /*
      * We don't need to exhaustively test these cases, as they're tested by unit
@@ -77,8 +94,7 @@ async fn test_demo1() {
     let testctx = common::test_setup("demo1", api);
 
     let private = testctx.server.app_private();
-    let p = private.downcast::<usize>().expect("wrong type for private data");
-    assert_eq!(*p, 0);
+    assert_eq!(*private, 0);
 
     let mut response = testctx
         .client_testctx
@@ -388,25 +404,25 @@ async fn test_demo_path_param_string() {
     }
 
     /*
-     * Success cases (use the path parameter).
-     * TODO-coverage verify whether the values below are encoded correctly in
-     * all the right places.  It's a little surprising that they come back
-     * encoded.
+     * Success cases (use the path parameter).  The values come back
+     * percent-decoded.
      */
     let okay_paths = vec![
         ("/testing/demo_path_string/okay", "okay"),
         ("/testing/demo_path_string/okay/", "okay"),
         ("/testing/demo_path_string//okay", "okay"),
         ("/testing/demo_path_string//okay//", "okay"),
-        ("/testing/demo_path_string//%7Bevil%7D", "%7Bevil%7D"),
+        ("/testing/demo_path_string//%7Bevil%7D", "{evil}"),
         (
             "/testing/demo_path_string//%7Bsurprisingly_okay",
-            "%7Bsurprisingly_okay",
+            "{surprisingly_okay",
         ),
         (
             "/testing/demo_path_string//surprisingly_okay%7D",
-            "surprisingly_okay%7D",
+            "surprisingly_okay}",
         ),
+        ("/testing/demo_path_string/caf%C3%A9%20ol%C3%A9", "café olé"),
+        ("/testing/demo_path_string/a/../b/./", "b"),
     ];
 
     for (okay_path, matched_part) in okay_paths {
@@ -517,6 +533,41 @@ async fn test_demo_path_param_u32() {
     testctx.teardown().await;
 }
 
+/*
+ * The "demo_path_param_wildcard" handler takes a path parameter that matches
+ * the rest of the path.
+ */
+#[tokio::test]
+async fn test_demo_path_param_wildcard() {
+    let api = demo_api();
+    let testctx = common::test_setup("demo_path_param_wildcard", api);
+
+    let okay_paths = vec![
+        ("/testing/demo_path_wildcard/a", "a"),
+        ("/testing/demo_path_wildcard/a/b/c.txt", "a/b/c.txt"),
+        ("/testing/demo_path_wildcard//a//b/", "a/b"),
+        ("/testing/demo_path_wildcard", ""),
+        ("/testing/demo_path_wildcard/", ""),
+    ];
+
+    for (okay_path, matched_part) in okay_paths {
+        let mut response = testctx
+            .client_testctx
+            .make_request_with_body(
+                Method::GET,
+                okay_path,
+                Body::empty(),
+                StatusCode::OK,
+            )
+            .await
+            .unwrap();
+        let json: DemoPathWildcard = read_json(&mut response).await;
+        assert_eq!(json.rest, matched_part);
+    }
+
+    testctx.teardown().await;
+}
+
 /*
  * Test `UntypedBody`.
  */
@@ -605,6 +656,187 @@ async fn test_untyped_body() {
     testctx.teardown().await;
 }
 
+/*
+ * The "demo_headers" handler takes a required u32 header and an optional
+ * string header.  `ClientTestContext` doesn't let us set request headers, so
+ * these requests are made directly.
+ */
+#[tokio::test]
+async fn test_demo_headers() {
+    let api = demo_api();
+    let testctx = common::test_setup("demo_headers", api);
+    let uri = testctx.client_testctx.url("/testing/demo_headers");
+    let client = Client::new();
+    let make_request = |headers: &[(&str, &str)]| {
+        let mut request = Request::get(uri.clone());
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        client.request(request.body(Body::empty()).unwrap())
+    };
+
+    /* Error case: missing required header. */
+    let mut response = make_request(&[]).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error: HttpErrorResponseBody = read_json(&mut response).await;
+    assert_eq!(
+        error.message,
+        "unable to parse request headers: missing field `x-demo-count`"
+    );
+
+    /* Error case: malformed header. */
+    let mut response = make_request(&[("X-Demo-Count", "abcd")]).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error: HttpErrorResponseBody = read_json(&mut response).await;
+    assert_eq!(
+        error.message,
+        "unable to parse request headers: invalid value for \"x-demo-count\": \
+         unable to parse 'abcd' as u32"
+    );
+
+    /* Success case: only the required header. */
+    let mut response = make_request(&[("X-Demo-Count", "37")]).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: DemoHeaders = read_json(&mut response).await;
+    assert_eq!(json.x_demo_count, 37);
+    assert_eq!(json.x_demo_name, None);
+
+    /* Success case: both headers, with the optional one given twice. */
+    let mut response = make_request(&[
+        ("X-Demo-Count", "38"),
+        ("X-Demo-Name", "foo"),
+        ("X-Demo-Name", "bar"),
+    ])
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: DemoHeaders = read_json(&mut response).await;
+    assert_eq!(json.x_demo_count, 38);
+    assert_eq!(json.x_demo_name, Some(String::from("foo, bar")));
+
+    testctx.teardown().await;
+}
+
+/*
+ * The "demo_cookies" handler takes a required string cookie and an optional u32
+ * cookie, and sets a cookie of its own.  As with headers, these requests are
+ * made directly.
+ */
+#[tokio::test]
+async fn test_demo_cookies() {
+    let api = demo_api();
+    let testctx = common::test_setup("demo_cookies", api);
+    let uri = testctx.client_testctx.url("/testing/demo_cookies");
+    let client = Client::new();
+    let make_request = |cookies: &[&str]| {
+        let mut request = Request::get(uri.clone());
+        for cookie in cookies {
+            request = request.header(http::header::COOKIE, *cookie);
+        }
+        client.request(request.body(Body::empty()).unwrap())
+    };
+
+    /* Error case: missing required cookie. */
+    let mut response = make_request(&["visits=3"]).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error: HttpErrorResponseBody = read_json(&mut response).await;
+    assert_eq!(
+        error.message,
+        "unable to parse cookies: missing field `session`"
+    );
+
+    /* Error case: malformed cookie. */
+    let mut response =
+        make_request(&["session=abc; visits=many"]).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let error: HttpErrorResponseBody = read_json(&mut response).await;
+    assert_eq!(
+        error.message,
+        "unable to parse cookies: invalid value for \"visits\": unable to \
+         parse 'many' as u32"
+    );
+
+    /* Success case: only the required cookie, among others. */
+    let mut response =
+        make_request(&["other=1; session=abc", "more=2"]).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let set_cookies = response
+        .headers()
+        .get_all(http::header::SET_COOKIE)
+        .iter()
+        .map(|value| value.to_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(set_cookies, vec![
+        "visits=1; Path=/testing; Max-Age=86400; SameSite=Strict; Secure; \
+         HttpOnly",
+        "legacy=; Max-Age=0",
+    ]);
+    let json: DemoCookies = read_json(&mut response).await;
+    assert_eq!(json.session, "abc");
+    assert_eq!(json.visits, None);
+
+    /* Success case: both cookies. */
+    let mut response = make_request(&["session=def; visits=41"]).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(http::header::SET_COOKIE).unwrap(),
+        "visits=42; Path=/testing; Max-Age=86400; SameSite=Strict; Secure; \
+         HttpOnly"
+    );
+    let json: DemoCookies = read_json(&mut response).await;
+    assert_eq!(json.session, "def");
+    assert_eq!(json.visits, Some(41));
+
+    testctx.teardown().await;
+}
+
+/*
+ * The "demo_many_args" handler takes eight extractors, pulling values out of
+ * every part of the request.
+ */
+#[tokio::test]
+async fn test_demo_many_args() {
+    let api = demo_api();
+    let testctx = common::test_setup("demo_many_args", api);
+    let uri = testctx
+        .client_testctx
+        .url("/testing/demo_many_args/path1?query1=query1&query2=query2");
+    let request = Request::put(uri)
+        .header("x-header1", "header1")
+        .header("x-header2", "header2")
+        .header(http::header::COOKIE, "cookie1=cookie1; cookie2=cookie2")
+        .body("\"body\"".into())
+        .unwrap();
+    let mut response = Client::new().request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: Vec<String> = read_json(&mut response).await;
+    assert_eq!(json, vec![
+        "path1", "query1", "query2", "header1", "header2", "cookie1",
+        "cookie2", "body"
+    ]);
+
+    testctx.teardown().await;
+}
+
+/*
+ * The "demo_extensions" handler reads a value that its extractor stored in the
+ * request's extensions.
+ */
+#[tokio::test]
+async fn test_demo_extensions() {
+    let api = demo_api();
+    let testctx = common::test_setup("demo_extensions", api);
+    let uri = testctx.client_testctx.url("/testing/demo_extensions");
+    let request =
+        Request::get(uri).header("x-demo-user", "alice").body(Body::empty());
+    let mut response = Client::new().request(request.unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json: String = read_json(&mut response).await;
+    assert_eq!(json, "alice");
+
+    testctx.teardown().await;
+}
+
 /*
  * Demo handler functions
  */
@@ -614,7 +846,7 @@ async fn test_untyped_body() {
     path = "/testing/demo1",
 }]
 async fn demo_handler_args_1(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<usize>>,
 ) -> Result<Response<Body>, HttpError> {
     http_echo(&"demo_handler_args_1")
 }
@@ -629,7 +861,7 @@ pub struct DemoQueryArgs {
     path = "/testing/demo2query",
 }]
 async fn demo_handler_args_2query(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<usize>>,
     query: Query<DemoQueryArgs>,
 ) -> Result<Response<Body>, HttpError> {
     http_echo(&query.into_inner())
@@ -645,7 +877,7 @@ pub struct DemoJsonBody {
     path = "/testing/demo2json",
 }]
 async fn demo_handler_args_2json(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<usize>>,
     json: TypedBody<DemoJsonBody>,
 ) -> Result<Response<Body>, HttpError> {
     http_echo(&json.into_inner())
@@ -661,7 +893,7 @@ pub struct DemoJsonAndQuery {
     path = "/testing/demo3",
 }]
 async fn demo_handler_args_3(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<usize>>,
     query: Query<DemoQueryArgs>,
     json: TypedBody<DemoJsonBody>,
 ) -> Result<Response<Body>, HttpError> {
@@ -681,7 +913,7 @@ pub struct DemoPathString {
     path = "/testing/demo_path_string/{test1}",
 }]
 async fn demo_handler_path_param_string(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<usize>>,
     path_params: Path<DemoPathString>,
 ) -> Result<Response<Body>, HttpError> {
     http_echo(&path_params.into_inner())
@@ -696,7 +928,7 @@ pub struct DemoPathUuid {
     path = "/testing/demo_path_uuid/{test1}",
 }]
 async fn demo_handler_path_param_uuid(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<usize>>,
     path_params: Path<DemoPathUuid>,
 ) -> Result<Response<Body>, HttpError> {
     http_echo(&path_params.into_inner())
@@ -711,12 +943,27 @@ pub struct DemoPathU32 {
     path = "/testing/demo_path_u32/{test1}",
 }]
 async fn demo_handler_path_param_u32(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<usize>>,
     path_params: Path<DemoPathU32>,
 ) -> Result<Response<Body>, HttpError> {
     http_echo(&path_params.into_inner())
 }
 
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub struct DemoPathWildcard {
+    pub rest: String,
+}
+#[endpoint {
+    method = GET,
+    path = "/testing/demo_path_wildcard/{rest:.*}",
+}]
+async fn demo_handler_path_param_wildcard(
+    _rqctx: Arc<RequestContext<usize>>,
+    path_params: Path<DemoPathWildcard>,
+) -> Result<Response<Body>, HttpError> {
+    http_echo(&path_params.into_inner())
+}
+
 #[derive(Deserialize, Serialize, JsonSchema)]
 pub struct DemoUntyped {
     pub nbytes: usize,
@@ -731,7 +978,7 @@ pub struct DemoUntypedQuery {
     path = "/testing/untyped_body"
 }]
 async fn demo_handler_untyped_body(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<usize>>,
     body: UntypedBody,
     query: Query<DemoUntypedQuery>,
 ) -> Result<HttpResponseOk<DemoUntyped>, HttpError> {
@@ -748,6 +995,148 @@ async fn demo_handler_untyped_body(
     }))
 }
 
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub struct DemoHeaders {
+    pub x_demo_count: u32,
+    pub x_demo_name: Option<String>,
+}
+#[endpoint {
+    method = GET,
+    path = "/testing/demo_headers",
+}]
+async fn demo_handler_headers(
+    _rqctx: Arc<RequestContext<usize>>,
+    headers: Header<DemoHeaders>,
+) -> Result<HttpResponseOk<DemoHeaders>, HttpError> {
+    Ok(HttpResponseOk(headers.into_inner()))
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub struct DemoCookies {
+    pub session: String,
+    pub visits: Option<u32>,
+}
+#[endpoint {
+    method = GET,
+    path = "/testing/demo_cookies",
+}]
+async fn demo_handler_cookies(
+    _rqctx: Arc<RequestContext<usize>>,
+    cookies: Cookies<DemoCookies>,
+) -> Result<HttpResponseSetCookies<HttpResponseOk<DemoCookies>>, HttpError> {
+    let cookies = cookies.into_inner();
+    let visits = cookies.visits.unwrap_or(0) + 1;
+    Ok(HttpResponseSetCookies::new(HttpResponseOk(cookies))
+        .cookie(
+            SetCookie::new("visits", visits.to_string())
+                .path("/testing")
+                .max_age(std::time::Duration::from_secs(86400))
+                .same_site(SameSite::Strict)
+                .secure(true)
+                .http_only(true),
+        )
+        .cookie(SetCookie::removal("legacy")))
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct DemoManyPath {
+    pub path1: String,
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct DemoManyQuery1 {
+    pub query1: String,
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct DemoManyQuery2 {
+    pub query2: String,
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct DemoManyHeader1 {
+    #[serde(rename = "x-header1")]
+    pub header1: String,
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct DemoManyHeader2 {
+    #[serde(rename = "x-header2")]
+    pub header2: String,
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct DemoManyCookie1 {
+    pub cookie1: String,
+}
+#[derive(Deserialize, JsonSchema)]
+pub struct DemoManyCookie2 {
+    pub cookie2: String,
+}
+#[endpoint {
+    method = PUT,
+    path = "/testing/demo_many_args/{path1}",
+}]
+#[allow(clippy::too_many_arguments)]
+async fn demo_handler_many_args(
+    _rqctx: Arc<RequestContext<usize>>,
+    path: Path<DemoManyPath>,
+    query1: Query<DemoManyQuery1>,
+    query2: Query<DemoManyQuery2>,
+    header1: Header<DemoManyHeader1>,
+    header2: Header<DemoManyHeader2>,
+    cookie1: Cookies<DemoManyCookie1>,
+    cookie2: Cookies<DemoManyCookie2>,
+    body: TypedBody<String>,
+) -> Result<HttpResponseOk<Vec<String>>, HttpError> {
+    Ok(HttpResponseOk(vec![
+        path.into_inner().path1,
+        query1.into_inner().query1,
+        query2.into_inner().query2,
+        header1.into_inner().header1,
+        header2.into_inner().header2,
+        cookie1.into_inner().cookie1,
+        cookie2.into_inner().cookie2,
+        body.into_inner(),
+    ]))
+}
+
+/**
+ * Stands in for an authentication extractor: it stores the user named by the
+ * "x-demo-user" header in the request's extensions for the handler to find.
+ */
+pub struct DemoAuthenticated;
+pub struct DemoUser(String);
+#[async_trait]
+impl Extractor for DemoAuthenticated {
+    async fn from_request<Context: ServerContext>(
+        rqctx: Arc<RequestContext<Context>>,
+    ) -> Result<DemoAuthenticated, HttpError> {
+        let request = rqctx.request.lock().await;
+        let user = request
+            .headers()
+            .get("x-demo-user")
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| {
+                HttpError::for_bad_request(None, String::from("no user"))
+            })?;
+        rqctx.extensions.lock().await.insert(DemoUser(user.to_string()));
+        Ok(DemoAuthenticated)
+    }
+
+    fn metadata() -> Vec<ApiEndpointParameter> {
+        vec![]
+    }
+}
+#[endpoint {
+    method = GET,
+    path = "/testing/demo_extensions",
+}]
+async fn demo_handler_extensions(
+    rqctx: Arc<RequestContext<usize>>,
+    _auth: DemoAuthenticated,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    let extensions = rqctx.extensions.lock().await;
+    let user = extensions.get::<DemoUser>().unwrap();
+    Ok(HttpResponseOk(user.0.clone()))
+}
+
 #[derive(Deserialize, Serialize, JsonSchema)]
 pub struct DemoPathImpossible {
     pub test1: String,
@@ -757,7 +1146,7 @@ pub struct DemoPathImpossible {
     path = "/testing/demo_path_impossible/{different_param_name}",
 }]
 async fn demo_handler_path_param_impossible(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<usize>>,
     path_params: Path<DemoPathImpossible>,
 ) -> Result<Response<Body>, HttpError> {
     http_echo(&path_params.into_inner())
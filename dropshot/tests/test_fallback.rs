@@ -0,0 +1,131 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for the fallback handler for requests that match no endpoint.
+ */
+
+use async_trait::async_trait;
+use dropshot::endpoint;
+use dropshot::test_util::read_json;
+use dropshot::test_util::read_string;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::Middleware;
+use dropshot::RequestContext;
+use http::Method;
+use http::StatusCode;
+use hyper::Body;
+use hyper::Response;
+use std::sync::Arc;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+#[endpoint {
+    method = GET,
+    path = "/api/status",
+}]
+async fn status_get(
+    _rqctx: Arc<RequestContext<usize>>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(String::from("ok")))
+}
+
+/** Serves the "app" for any path under "/app" and fails everything else. */
+async fn app_fallback(
+    rqctx: Arc<RequestContext<usize>>,
+) -> Result<Response<Body>, HttpError> {
+    let via = match rqctx.extensions.lock().await.get::<ApiMiddlewareRan>() {
+        Some(_) => "via middleware",
+        None => "directly",
+    };
+    let request = rqctx.request.lock().await;
+    let path = request.uri().path();
+    if !path.starts_with("/app") {
+        return Err(HttpError::for_not_found(
+            None,
+            format!("nothing at \"{}\"", path),
+        ));
+    }
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, "text/plain")
+        .body(format!("{} {} {}", request.method(), path, via).into())?)
+}
+
+/** Records in the request's extensions that it ran. */
+struct ApiMiddleware;
+
+struct ApiMiddlewareRan;
+
+#[async_trait]
+impl Middleware<usize> for ApiMiddleware {
+    async fn request(
+        &self,
+        rqctx: &Arc<RequestContext<usize>>,
+    ) -> Result<(), HttpError> {
+        rqctx.extensions.lock().await.insert(ApiMiddlewareRan);
+        Ok(())
+    }
+}
+
+fn fallback_api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(status_get).unwrap();
+    api.fallback(app_fallback);
+    api.middleware(ApiMiddleware);
+    api
+}
+
+#[tokio::test]
+async fn test_fallback() {
+    let testctx = common::test_setup("fallback", fallback_api());
+    let client = &testctx.client_testctx;
+
+    /*
+     * Requests for any method and path that match no endpoint go to the
+     * fallback handler, and the API's middleware runs around it.
+     */
+    let uri = client.url("/app/projects/p1");
+    let mut response = hyper::Client::new()
+        .request(hyper::Request::post(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        read_string(&mut response).await,
+        "POST /app/projects/p1 via middleware"
+    );
+
+    let error = client
+        .make_request_error(Method::GET, "/other", StatusCode::NOT_FOUND)
+        .await;
+    assert_eq!(error.message, "Not Found");
+
+    /* Requests that match an endpoint are handled as usual. */
+    let mut response = client
+        .make_request_no_body(Method::GET, "/api/status", StatusCode::OK)
+        .await
+        .unwrap();
+    let status: String = read_json(&mut response).await;
+    assert_eq!(status, "ok");
+    client
+        .make_request_error(
+            Method::DELETE,
+            "/api/status",
+            StatusCode::METHOD_NOT_ALLOWED,
+        )
+        .await;
+
+    testctx.teardown().await;
+}
+
+#[test]
+fn test_fallback_openapi() {
+    let openapi = fallback_api().openapi("test", "1").json().unwrap();
+    let paths =
+        openapi["paths"].as_object().unwrap().keys().collect::<Vec<_>>();
+    assert_eq!(paths, vec!["/api/status"]);
+}
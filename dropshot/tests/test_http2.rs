@@ -0,0 +1,230 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for serving HTTP/2 without TLS ("prior knowledge").  HTTP/2
+ * negotiated during the TLS handshake is tested with the other TLS cases.
+ */
+
+use dropshot::endpoint;
+use dropshot::test_util::read_config;
+use dropshot::test_util::read_json;
+use dropshot::test_util::TestContext;
+use dropshot::ApiDescription;
+use dropshot::ConfigDropshot;
+use dropshot::ConfigHttp2;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::HttpServer;
+use dropshot::RequestContext;
+use http::Method;
+use http::StatusCode;
+use http::Version;
+use hyper::client::HttpConnector;
+use hyper::Client;
+use std::sync::Arc;
+use tokio::sync::Barrier;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+/** number of requests issued at once by the multiplexing test */
+const NREQUESTS: usize = 8;
+
+/**
+ * Completes once `NREQUESTS` requests are waiting in this handler, returning
+ * the remote address of the connection on which the request arrived.
+ */
+#[endpoint {
+    method = GET,
+    path = "/together",
+}]
+async fn http2_together(
+    rqctx: Arc<RequestContext<Barrier>>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    let barrier = rqctx.context();
+    barrier.wait().await;
+    Ok(HttpResponseOk(rqctx.connection.peer.to_string()))
+}
+
+/** Returns the HTTP version of the request. */
+#[endpoint {
+    method = GET,
+    path = "/version",
+}]
+async fn http2_version(
+    rqctx: Arc<RequestContext<Barrier>>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    let request = rqctx.request.lock().await;
+    Ok(HttpResponseOk(format!("{:?}", request.version())))
+}
+
+fn http2_api() -> ApiDescription<Barrier> {
+    let mut api = ApiDescription::new();
+    api.register(http2_together).unwrap();
+    api.register(http2_version).unwrap();
+    api
+}
+
+fn http2_testctx(
+    test_name: &str,
+    config: &ConfigDropshot,
+) -> TestContext<Barrier> {
+    let private = Barrier::new(NREQUESTS);
+    common::test_setup_with_config(test_name, http2_api(), private, config)
+}
+
+/** Returns a client that speaks only HTTP/2, without negotiating it first. */
+fn http2_client() -> Client<HttpConnector> {
+    Client::builder().http2_only(true).build_http()
+}
+
+#[tokio::test]
+async fn test_http2_prior_knowledge() {
+    let config = ConfigDropshot {
+        http2: Some(ConfigHttp2::default()),
+        ..Default::default()
+    };
+    let testctx = http2_testctx("http2_prior_knowledge", &config);
+
+    let client = http2_client();
+    let uri = testctx.client_testctx.url("/version");
+    let mut response = client.get(uri).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), Version::HTTP_2);
+    let version: String = read_json(&mut response).await;
+    assert_eq!(version, "HTTP/2.0");
+
+    /* HTTP/1 clients should still be served. */
+    let mut response = testctx
+        .client_testctx
+        .make_request_no_body(Method::GET, "/version", StatusCode::OK)
+        .await
+        .unwrap();
+    let version: String = read_json(&mut response).await;
+    assert_eq!(version, "HTTP/1.1");
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_http2_disabled() {
+    let testctx = http2_testctx("http2_disabled", &ConfigDropshot::default());
+
+    /* Without an HTTP/2 configuration, the server speaks only HTTP/1. */
+    let client = http2_client();
+    let uri = testctx.client_testctx.url("/version");
+    assert!(client.get(uri).await.is_err());
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_http2_multiplexing() {
+    let config = ConfigDropshot {
+        http2: Some(ConfigHttp2 {
+            max_concurrent_streams: Some(NREQUESTS as u32),
+            initial_stream_window_size: Some(256 * 1024),
+            initial_connection_window_size: Some(4 * 1024 * 1024),
+            keep_alive_interval_ms: Some(1000),
+            keep_alive_timeout_ms: Some(5000),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let testctx = http2_testctx("http2_multiplexing", &config);
+
+    /*
+     * The handler only completes once all of the requests have arrived, so
+     * this only succeeds if the server handles them concurrently.  They should
+     * all arrive on the same connection.
+     */
+    let client = http2_client();
+    let requests = (0..NREQUESTS).map(|_| {
+        let client = client.clone();
+        let uri = testctx.client_testctx.url("/together");
+        async move {
+            let mut response = client.get(uri).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            read_json::<String>(&mut response).await
+        }
+    });
+    let mut peers = futures::future::join_all(requests).await;
+    peers.dedup();
+    assert_eq!(peers.len(), 1);
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_http2_bad_window_size() {
+    let logctx = common::test_logctx("http2_bad_window_size");
+    let config = ConfigDropshot {
+        http2: Some(ConfigHttp2 {
+            initial_connection_window_size: Some(1 << 31),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let error = HttpServer::new(
+        &config,
+        http2_api(),
+        Barrier::new(NREQUESTS),
+        &logctx.log,
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "http2.initial_connection_window_size must be at most 2147483647"
+    );
+    logctx.cleanup_successful();
+}
+
+#[test]
+fn test_http2_config() {
+    let config = read_config::<ConfigDropshot>(
+        "http2_config",
+        r#"
+            bind_address = "127.0.0.1:0"
+            [http2]
+            max_concurrent_streams = 100
+            initial_stream_window_size = 65535
+            initial_connection_window_size = 1048576
+            adaptive_window = true
+            keep_alive_interval_ms = 30000
+            keep_alive_timeout_ms = 10000
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.http2,
+        Some(ConfigHttp2 {
+            max_concurrent_streams: Some(100),
+            initial_stream_window_size: Some(65535),
+            initial_connection_window_size: Some(1048576),
+            adaptive_window: true,
+            keep_alive_interval_ms: Some(30000),
+            keep_alive_timeout_ms: Some(10000),
+        })
+    );
+
+    let config = read_config::<ConfigDropshot>(
+        "http2_config_empty",
+        r#"
+            bind_address = "127.0.0.1:0"
+            [http2]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.http2, Some(ConfigHttp2::default()));
+
+    let config = read_config::<ConfigDropshot>(
+        "http2_config_default",
+        r#"
+            bind_address = "127.0.0.1:0"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.http2, None);
+}
@@ -0,0 +1,301 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for limits on how much work a server takes on at once.
+ */
+
+use dropshot::endpoint;
+use dropshot::test_util::read_config;
+use dropshot::test_util::TestContext;
+use dropshot::ApiDescription;
+use dropshot::ApiEndpoint;
+use dropshot::ConfigDropshot;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::HttpServer;
+use dropshot::RequestContext;
+use http::StatusCode;
+use hyper::Body;
+use hyper::Client;
+use hyper::Response;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+/**
+ * Lets tests control when the handlers for "/wait" and "/limited" finish.
+ */
+struct LimitsContext {
+    /** number of requests that have started running a handler */
+    started: AtomicUsize,
+    /** each handler takes a permit from here before completing */
+    gate: Semaphore,
+}
+
+impl LimitsContext {
+    async fn wait(&self) {
+        self.started.fetch_add(1, Ordering::SeqCst);
+        self.gate.acquire().await.unwrap().forget();
+    }
+}
+
+/** Completes when the test allows it to. */
+#[endpoint {
+    method = GET,
+    path = "/wait",
+}]
+async fn limits_wait(
+    rqctx: Arc<RequestContext<LimitsContext>>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    rqctx.context().wait().await;
+    Ok(HttpResponseOk(()))
+}
+
+/** Like `/wait`, but only one request may be handled at a time. */
+#[endpoint {
+    method = GET,
+    path = "/limited",
+    concurrency_limit = 1,
+}]
+async fn limits_limited(
+    rqctx: Arc<RequestContext<LimitsContext>>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    rqctx.context().wait().await;
+    Ok(HttpResponseOk(()))
+}
+
+/** Completes immediately. */
+#[endpoint {
+    method = GET,
+    path = "/fast",
+}]
+async fn limits_fast(
+    _rqctx: Arc<RequestContext<LimitsContext>>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    Ok(HttpResponseOk(()))
+}
+
+fn limits_testctx(
+    test_name: &str,
+    config: &ConfigDropshot,
+) -> TestContext<LimitsContext> {
+    let mut api = ApiDescription::new();
+    api.register(limits_wait).unwrap();
+    api.register(limits_limited).unwrap();
+    api.register(limits_fast).unwrap();
+
+    let private = LimitsContext {
+        started: AtomicUsize::new(0),
+        gate: Semaphore::new(0),
+    };
+    common::test_setup_with_config(test_name, api, private, config)
+}
+
+fn limits_context(testctx: &TestContext<LimitsContext>) -> &LimitsContext {
+    testctx.server.app_private()
+}
+
+/** Issues a GET request for `path` in the background. */
+fn start_request(
+    testctx: &TestContext<LimitsContext>,
+    path: &str,
+) -> JoinHandle<Response<Body>> {
+    let uri = testctx.client_testctx.url(path);
+    tokio::spawn(async move { Client::new().get(uri).await.unwrap() })
+}
+
+/**
+ * Issues GET requests for `path` in the background, waiting until each one
+ * has started running its handler.
+ */
+async fn start_waiting_requests(
+    testctx: &TestContext<LimitsContext>,
+    path: &str,
+    count: usize,
+) -> Vec<JoinHandle<Response<Body>>> {
+    let context = limits_context(testctx);
+    let mut requests = Vec::new();
+    for _ in 0..count {
+        let nstarted = context.started.load(Ordering::SeqCst);
+        requests.push(start_request(testctx, path));
+        while context.started.load(Ordering::SeqCst) == nstarted {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+    requests
+}
+
+/**
+ * Checks that `response` reports that the server was overloaded and suggests
+ * retrying after `retry_after` seconds.
+ */
+fn assert_overloaded(response: &Response<Body>, retry_after: &str) {
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        response.headers().get(http::header::RETRY_AFTER).unwrap(),
+        retry_after
+    );
+}
+
+/** Lets `requests` finish, checking that each one succeeds. */
+async fn finish_requests(
+    testctx: &TestContext<LimitsContext>,
+    requests: Vec<JoinHandle<Response<Body>>>,
+) {
+    limits_context(testctx).gate.add_permits(requests.len());
+    for request in requests {
+        assert_eq!(request.await.unwrap().status(), StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn test_concurrency_limit_server() {
+    let config = ConfigDropshot {
+        max_concurrent_requests: Some(2),
+        ..Default::default()
+    };
+    let testctx = limits_testctx("concurrency_limit_server", &config);
+
+    /*
+     * With two requests in progress, the server should reject any others until
+     * one of them finishes.
+     */
+    let requests = start_waiting_requests(&testctx, "/wait", 2).await;
+    let response = start_request(&testctx, "/fast").await.unwrap();
+    assert_overloaded(&response, "1");
+    finish_requests(&testctx, requests).await;
+
+    let response = start_request(&testctx, "/fast").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_concurrency_limit_endpoint() {
+    let testctx = limits_testctx(
+        "concurrency_limit_endpoint",
+        &ConfigDropshot::default(),
+    );
+
+    /*
+     * With a request to "/limited" in progress, other requests to the same
+     * endpoint should be rejected, but requests to other endpoints should not.
+     */
+    let requests = start_waiting_requests(&testctx, "/limited", 1).await;
+    let response = start_request(&testctx, "/limited").await.unwrap();
+    assert_overloaded(&response, "1");
+    let response = start_request(&testctx, "/fast").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    finish_requests(&testctx, requests).await;
+
+    let requests = start_waiting_requests(&testctx, "/limited", 1).await;
+    finish_requests(&testctx, requests).await;
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_concurrency_limit_queue() {
+    let config = ConfigDropshot {
+        max_concurrent_requests: Some(1),
+        concurrency_queue_timeout_ms: 1500,
+        ..Default::default()
+    };
+    let testctx = limits_testctx("concurrency_limit_queue", &config);
+    let context = limits_context(&testctx);
+
+    /*
+     * A request that arrives while the server is busy should wait for its turn
+     * rather than failing immediately.
+     */
+    let mut requests = start_waiting_requests(&testctx, "/wait", 1).await;
+    requests.push(start_request(&testctx, "/wait"));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(context.started.load(Ordering::SeqCst), 1);
+    finish_requests(&testctx, requests).await;
+    assert_eq!(context.started.load(Ordering::SeqCst), 2);
+
+    /* If it waits too long, though, it should fail. */
+    let requests = start_waiting_requests(&testctx, "/wait", 1).await;
+    let response = start_request(&testctx, "/fast").await.unwrap();
+    assert_overloaded(&response, "2");
+    finish_requests(&testctx, requests).await;
+
+    testctx.teardown().await;
+}
+
+#[test]
+fn test_concurrency_limit_config() {
+    let config = read_config::<ConfigDropshot>(
+        "concurrency_limit_config",
+        r#"
+            bind_address = "127.0.0.1:0"
+            max_concurrent_requests = 100
+            concurrency_queue_timeout_ms = 250
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.max_concurrent_requests, Some(100));
+    assert_eq!(config.concurrency_queue_timeout_ms, 250);
+
+    let config = read_config::<ConfigDropshot>(
+        "concurrency_limit_config_default",
+        r#"
+            bind_address = "127.0.0.1:0"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.max_concurrent_requests, None);
+    assert_eq!(config.concurrency_queue_timeout_ms, 0);
+}
+
+#[tokio::test]
+async fn test_concurrency_limit_invalid() {
+    let logctx = common::test_logctx("concurrency_limit_invalid");
+    let log = logctx.log.new(o!());
+    let expected_error = format!(
+        "max_concurrent_requests must be between 1 and {}",
+        Semaphore::MAX_PERMITS
+    );
+    for max in [0, Semaphore::MAX_PERMITS + 1].iter() {
+        let config = ConfigDropshot {
+            max_concurrent_requests: Some(*max),
+            ..Default::default()
+        };
+        let private = LimitsContext {
+            started: AtomicUsize::new(0),
+            gate: Semaphore::new(0),
+        };
+        let error =
+            HttpServer::new(&config, ApiDescription::new(), private, &log)
+                .err()
+                .unwrap();
+        assert_eq!(error.to_string(), expected_error);
+    }
+
+    let mut api = ApiDescription::<LimitsContext>::new();
+    for limit in [0, Semaphore::MAX_PERMITS + 1].iter() {
+        let error = api
+            .register(ApiEndpoint::from(limits_fast).concurrency_limit(*limit))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            format!(
+                "endpoint \"limits_fast\": concurrency_limit must be between \
+                 1 and {}",
+                Semaphore::MAX_PERMITS
+            )
+        );
+    }
+    api.register(ApiEndpoint::from(limits_fast).concurrency_limit(1)).unwrap();
+
+    logctx.cleanup_successful();
+}
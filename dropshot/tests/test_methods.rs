@@ -0,0 +1,198 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for the HEAD and OPTIONS requests that Dropshot handles
+ * automatically and for the "Allow" header on "405 Method Not Allowed" errors.
+ */
+
+use dropshot::endpoint;
+use dropshot::test_util::read_json;
+use dropshot::test_util::read_string;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::HttpResponseUpdatedNoContent;
+use dropshot::RequestContext;
+use http::Method;
+use http::StatusCode;
+use hyper::Body;
+use hyper::Response;
+use std::sync::Arc;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+#[endpoint {
+    method = GET,
+    path = "/widget",
+}]
+async fn widget_get(
+    _rqctx: Arc<RequestContext<usize>>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(String::from("a widget")))
+}
+
+#[endpoint {
+    method = PUT,
+    path = "/widget",
+}]
+async fn widget_put(
+    _rqctx: Arc<RequestContext<usize>>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    Ok(HttpResponseUpdatedNoContent())
+}
+
+#[endpoint {
+    method = GET,
+    path = "/gadget",
+}]
+async fn gadget_get(
+    _rqctx: Arc<RequestContext<usize>>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(String::from("a gadget")))
+}
+
+#[endpoint {
+    method = HEAD,
+    path = "/gadget",
+}]
+async fn gadget_head(
+    _rqctx: Arc<RequestContext<usize>>,
+) -> Result<Response<Body>, HttpError> {
+    Ok(Response::builder().status(StatusCode::NO_CONTENT).body(Body::empty())?)
+}
+
+#[endpoint {
+    method = OPTIONS,
+    path = "/gadget",
+}]
+async fn gadget_options(
+    _rqctx: Arc<RequestContext<usize>>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(String::from("gadget options")))
+}
+
+fn methods_api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(widget_get).unwrap();
+    api.register(widget_put).unwrap();
+    api.register(gadget_get).unwrap();
+    api.register(gadget_head).unwrap();
+    api.register(gadget_options).unwrap();
+    api
+}
+
+fn allow_header(response: &Response<Body>) -> &str {
+    response.headers().get(http::header::ALLOW).unwrap().to_str().unwrap()
+}
+
+#[tokio::test]
+async fn test_automatic_methods() {
+    let testctx = common::test_setup("automatic_methods", methods_api());
+    let client = &testctx.client_testctx;
+
+    /*
+     * A HEAD request gets the headers of the GET response, but not its body.
+     */
+    let mut response = client
+        .make_request_no_body(Method::GET, "/widget", StatusCode::OK)
+        .await
+        .unwrap();
+    let content_length =
+        response.headers().get(http::header::CONTENT_LENGTH).unwrap().clone();
+    let widget: String = read_json(&mut response).await;
+    assert_eq!(widget, "a widget");
+    let mut response = client
+        .make_request_no_body(Method::HEAD, "/widget", StatusCode::OK)
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers().get(http::header::CONTENT_LENGTH).unwrap(),
+        content_length
+    );
+    assert_eq!(read_string(&mut response).await, "");
+
+    /*
+     * An OPTIONS request gets the list of methods.
+     */
+    let response = client
+        .make_request_no_body(
+            Method::OPTIONS,
+            "/widget",
+            StatusCode::NO_CONTENT,
+        )
+        .await
+        .unwrap();
+    assert_eq!(allow_header(&response), "GET, HEAD, OPTIONS, PUT");
+
+    /*
+     * HEAD and OPTIONS endpoints take precedence over the automatic handling.
+     */
+    client
+        .make_request_no_body(Method::HEAD, "/gadget", StatusCode::NO_CONTENT)
+        .await
+        .unwrap();
+    let mut response = client
+        .make_request_no_body(Method::OPTIONS, "/gadget", StatusCode::OK)
+        .await
+        .unwrap();
+    let options: String = read_json(&mut response).await;
+    assert_eq!(options, "gadget options");
+
+    /*
+     * Requests for other methods fail with the list of methods that are
+     * allowed.
+     */
+    let response = client
+        .make_request_with_body(
+            Method::DELETE,
+            "/widget",
+            Body::empty(),
+            StatusCode::METHOD_NOT_ALLOWED,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(response.message, "Method Not Allowed");
+    let uri = client.url("/gadget");
+    let response = hyper::Client::new()
+        .request(hyper::Request::delete(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(allow_header(&response), "GET, HEAD, OPTIONS");
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_automatic_methods_disabled() {
+    let mut api = methods_api();
+    api.automatic_methods(false);
+    let testctx = common::test_setup("automatic_methods_disabled", api);
+
+    for method in &[Method::HEAD, Method::OPTIONS] {
+        let uri = testctx.client_testctx.url("/widget");
+        let response = hyper::Client::new()
+            .request(
+                hyper::Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(allow_header(&response), "GET, PUT");
+    }
+
+    /* Endpoints for HEAD and OPTIONS still work. */
+    testctx
+        .client_testctx
+        .make_request_no_body(Method::HEAD, "/gadget", StatusCode::NO_CONTENT)
+        .await
+        .unwrap();
+
+    testctx.teardown().await;
+}
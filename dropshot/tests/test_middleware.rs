@@ -0,0 +1,197 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for middleware, including the order in which it runs and what
+ * happens when it fails a request.
+ */
+
+use async_trait::async_trait;
+use dropshot::endpoint;
+use dropshot::test_util::read_json;
+use dropshot::ApiDescription;
+use dropshot::ApiEndpoint;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::Middleware;
+use dropshot::RequestContext;
+use http::header::HeaderName;
+use http::header::HeaderValue;
+use http::StatusCode;
+use hyper::Body;
+use hyper::Client;
+use hyper::Request;
+use hyper::Response;
+use std::sync::Arc;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+/** names of the middleware whose `request()` has run, in order */
+struct Trace(Vec<String>);
+
+/**
+ * Records that it ran in the request's extensions and adds an "x-trace"
+ * header to the response (or error).
+ */
+struct TraceMiddleware(&'static str);
+
+#[async_trait]
+impl Middleware<usize> for TraceMiddleware {
+    async fn request(
+        &self,
+        rqctx: &Arc<RequestContext<usize>>,
+    ) -> Result<(), HttpError> {
+        let mut extensions = rqctx.extensions.lock().await;
+        match extensions.get_mut::<Trace>() {
+            Some(trace) => trace.0.push(self.0.to_string()),
+            None => {
+                extensions.insert(Trace(vec![self.0.to_string()]));
+            }
+        }
+        Ok(())
+    }
+
+    async fn response(
+        &self,
+        _rqctx: &Arc<RequestContext<usize>>,
+        result: Result<Response<Body>, HttpError>,
+    ) -> Result<Response<Body>, HttpError> {
+        let name = HeaderName::from_static("x-trace");
+        let value = HeaderValue::from_static(self.0);
+        match result {
+            Ok(mut response) => {
+                response.headers_mut().append(name, value);
+                Ok(response)
+            }
+            Err(error) => Err(error.with_header(name, value)),
+        }
+    }
+}
+
+/** Fails requests that don't have an "x-allow" header. */
+struct GuardMiddleware;
+
+#[async_trait]
+impl Middleware<usize> for GuardMiddleware {
+    fn name(&self) -> &str {
+        "guard"
+    }
+
+    async fn request(
+        &self,
+        rqctx: &Arc<RequestContext<usize>>,
+    ) -> Result<(), HttpError> {
+        let request = rqctx.request.lock().await;
+        if request.headers().contains_key("x-allow") {
+            Ok(())
+        } else {
+            Err(HttpError::for_client_error(
+                None,
+                StatusCode::UNAUTHORIZED,
+                String::from("not allowed"),
+            ))
+        }
+    }
+}
+
+#[endpoint {
+    method = GET,
+    path = "/plain",
+}]
+async fn mw_plain(
+    rqctx: Arc<RequestContext<usize>>,
+) -> Result<HttpResponseOk<Vec<String>>, HttpError> {
+    let extensions = rqctx.extensions.lock().await;
+    Ok(HttpResponseOk(extensions.get::<Trace>().unwrap().0.clone()))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/guarded",
+    tags = [ "guarded" ],
+}]
+async fn mw_guarded(
+    rqctx: Arc<RequestContext<usize>>,
+) -> Result<HttpResponseOk<Vec<String>>, HttpError> {
+    let extensions = rqctx.extensions.lock().await;
+    Ok(HttpResponseOk(extensions.get::<Trace>().unwrap().0.clone()))
+}
+
+fn middleware_api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(mw_plain).unwrap();
+    api.register(
+        ApiEndpoint::from(mw_guarded).middleware(TraceMiddleware("endpoint")),
+    )
+    .unwrap();
+
+    /*
+     * API-wide and tag middleware applies regardless of whether it's added
+     * before or after the endpoints are registered, and always runs before the
+     * endpoint's own middleware.
+     */
+    api.tag_middleware("guarded", GuardMiddleware);
+    api.tag_middleware("guarded", TraceMiddleware("tag"));
+    api.tag_middleware("unused", GuardMiddleware);
+    api.middleware(TraceMiddleware("api"));
+    api
+}
+
+async fn get(uri: hyper::Uri, allow: bool) -> Response<Body> {
+    let mut request = Request::get(uri);
+    if allow {
+        request = request.header("x-allow", "true");
+    }
+    Client::new().request(request.body(Body::empty()).unwrap()).await.unwrap()
+}
+
+fn trace_header(response: &Response<Body>) -> Vec<&str> {
+    response
+        .headers()
+        .get_all("x-trace")
+        .iter()
+        .map(|value| value.to_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_middleware_order() {
+    let testctx = common::test_setup("middleware_order", middleware_api());
+
+    let uri = testctx.client_testctx.url("/plain");
+    let mut response = get(uri, false).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(trace_header(&response), vec!["api"]);
+    let trace: Vec<String> = read_json(&mut response).await;
+    assert_eq!(trace, vec!["api"]);
+
+    /*
+     * Requests run through the middleware in order, and responses run back
+     * through it in reverse.
+     */
+    let uri = testctx.client_testctx.url("/guarded");
+    let mut response = get(uri, true).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(trace_header(&response), vec!["endpoint", "tag", "api"]);
+    let trace: Vec<String> = read_json(&mut response).await;
+    assert_eq!(trace, vec!["api", "tag", "endpoint"]);
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_middleware_error() {
+    let testctx = common::test_setup("middleware_error", middleware_api());
+
+    /*
+     * When middleware fails the request, neither the handler nor any later
+     * middleware runs, but earlier middleware still sees the error.
+     */
+    let uri = testctx.client_testctx.url("/guarded");
+    let response = get(uri, false).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(trace_header(&response), vec!["api"]);
+
+    testctx.teardown().await;
+}
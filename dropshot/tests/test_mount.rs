@@ -0,0 +1,182 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for assembling an API by mounting one `ApiDescription` within
+ * another.
+ */
+
+use dropshot::endpoint;
+use dropshot::test_util::read_json;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::Path;
+use dropshot::RequestContext;
+use http::Method;
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use std::sync::Arc;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+#[derive(Deserialize, JsonSchema)]
+struct InstancePath {
+    instance: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+struct InstanceInfo {
+    project: String,
+    instance: String,
+}
+
+/** Fetch an instance. */
+#[endpoint {
+    method = GET,
+    path = "/{instance}",
+    tags = [ "instances" ],
+}]
+async fn instance_get(
+    rqctx: Arc<RequestContext<usize>>,
+    path: Path<InstancePath>,
+) -> Result<HttpResponseOk<InstanceInfo>, HttpError> {
+    Ok(HttpResponseOk(InstanceInfo {
+        project: rqctx.path_variables["project"].clone(),
+        instance: path.into_inner().instance,
+    }))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/status",
+}]
+async fn status_get(
+    _rqctx: Arc<RequestContext<usize>>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(String::from("ok")))
+}
+
+fn instances_api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(instance_get).unwrap();
+    api
+}
+
+fn mount_api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(status_get).unwrap();
+    api.mount("/v1/projects/{project}/instances", instances_api()).unwrap();
+    api
+}
+
+#[tokio::test]
+async fn test_mount() {
+    let testctx = common::test_setup("mount", mount_api());
+    let client = &testctx.client_testctx;
+
+    let mut response = client
+        .make_request_no_body(
+            Method::GET,
+            "/v1/projects/p1/instances/i1",
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    let info: InstanceInfo = read_json(&mut response).await;
+    assert_eq!(info, InstanceInfo {
+        project: String::from("p1"),
+        instance: String::from("i1"),
+    });
+
+    /* The mounted endpoint is no longer available at its original path. */
+    client.make_request_error(Method::GET, "/i1", StatusCode::NOT_FOUND).await;
+    client
+        .make_request_no_body(Method::GET, "/status", StatusCode::OK)
+        .await
+        .unwrap();
+
+    testctx.teardown().await;
+}
+
+#[test]
+fn test_mount_conflict() {
+    /*
+     * Mounting the same endpoints twice conflicts, and none of them is added
+     * the second time.
+     */
+    let mut api = mount_api();
+    let error = api
+        .mount("/v1/projects/{project}/instances", instances_api())
+        .unwrap_err();
+    assert_eq!(
+        error,
+        "URI path \"/v1/projects/{project}/instances/{instance}\": attempted \
+         to create duplicate route for method \"GET\" (endpoint \
+         \"instance_get\" conflicts with endpoint \"instance_get\" for GET \
+         \"/v1/projects/{project}/instances/{instance}\")"
+    );
+
+    /* Variables in the prefix must be consistent with the existing routes. */
+    let error = api.mount("/v1/projects/{id}", instances_api()).unwrap_err();
+    assert_eq!(
+        error,
+        "URI path \"/v1/projects/{id}/{instance}\": attempted to use variable \
+         name \"id\", but a different name (\"project\") has already been \
+         used for this (endpoint \"instance_get\" conflicts with endpoint \
+         \"instance_get\" for GET \
+         \"/v1/projects/{project}/instances/{instance}\")"
+    );
+    let error = ApiDescription::new()
+        .mount("/{instance}", instances_api())
+        .unwrap_err();
+    assert_eq!(
+        error,
+        "URI path \"/{instance}/{instance}\": variable name \"instance\" is \
+         used more than once"
+    );
+
+    /* A prefix of "/" merges the APIs without changing any paths. */
+    let mut api = ApiDescription::new();
+    api.mount("/", instances_api()).unwrap();
+    let paths = api.openapi("test", "1").json().unwrap()["paths"]
+        .as_object()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(paths, vec!["/{instance}"]);
+}
+
+#[test]
+fn test_mount_openapi() {
+    let api = mount_api();
+    let openapi = api.openapi("test", "1").json().unwrap();
+    let paths = openapi["paths"].as_object().unwrap();
+    let mut path_names = paths.keys().collect::<Vec<_>>();
+    path_names.sort();
+    assert_eq!(path_names, vec![
+        "/status",
+        "/v1/projects/{project}/instances/{instance}"
+    ]);
+
+    /*
+     * The mounted endpoint keeps its tags, and the variable from the prefix
+     * is described as a path parameter.
+     */
+    let operation =
+        &paths["/v1/projects/{project}/instances/{instance}"]["get"];
+    assert_eq!(operation["operationId"], "instance_get");
+    assert_eq!(operation["tags"], serde_json::json!(["instances"]));
+    let mut parameters = operation["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| (p["in"].as_str().unwrap(), p["name"].as_str().unwrap()))
+        .collect::<Vec<_>>();
+    parameters.sort();
+    assert_eq!(parameters, vec![("path", "instance"), ("path", "project")]);
+}
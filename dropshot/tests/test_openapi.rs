@@ -1,10 +1,11 @@
 // Copyright 2020 Oxide Computer Company
 
 use dropshot::{
-    endpoint, ApiDescription, HttpError, HttpResponseAccepted,
+    endpoint, ApiDescription, Cookies, Header, HttpError, HttpResponseAccepted,
     HttpResponseCreated, HttpResponseDeleted, HttpResponseOk,
-    HttpResponseUpdatedNoContent, PaginationParams, Path, Query,
-    RequestContext, ResultsPage, TypedBody, UntypedBody,
+    HttpResponseSetCookies, HttpResponseUpdatedNoContent, PaginationParams,
+    Path, Query, RequestContext, ResultsPage, StreamingBody, TypedBody,
+    UntypedBody,
 };
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -18,7 +19,7 @@ use std::{io::Cursor, str::from_utf8, sync::Arc};
 /// line comment.
 /// It uses Rust-style.
 async fn handler1(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<()>>,
 ) -> Result<HttpResponseOk<()>, HttpError> {
     Ok(HttpResponseOk(()))
 }
@@ -41,7 +42,7 @@ struct QueryArgs {
  * It uses C-style.
  */
 async fn handler2(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<()>>,
     _query: Query<QueryArgs>,
 ) -> Result<HttpResponseUpdatedNoContent, HttpError> {
     Ok(HttpResponseUpdatedNoContent())
@@ -58,7 +59,7 @@ struct PathArgs {
     path = "/test/man/{x}",
 }]
 async fn handler3(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<()>>,
     _path: Path<PathArgs>,
 ) -> Result<HttpResponseDeleted, HttpError> {
     Ok(HttpResponseDeleted())
@@ -77,7 +78,7 @@ struct Response {}
     path = "/test/camera",
 }]
 async fn handler4(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<()>>,
     _body: TypedBody<BodyParam>,
 ) -> Result<HttpResponseCreated<Response>, HttpError> {
     Ok(HttpResponseCreated(Response {}))
@@ -89,7 +90,7 @@ async fn handler4(
     tags = [ "person", "woman", "man", "camera", "tv"]
 }]
 async fn handler5(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<()>>,
     _path: Path<PathArgs>,
     _query: Query<QueryArgs>,
     _body: TypedBody<BodyParam>,
@@ -119,7 +120,7 @@ struct ExamplePageSelector {
     path = "/impairment",
 }]
 async fn handler6(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<()>>,
     _query: Query<PaginationParams<ExampleScanParams, ExamplePageSelector>>,
 ) -> Result<HttpResponseOk<ResultsPage<ResponseItem>>, HttpError> {
     unimplemented!();
@@ -130,13 +131,96 @@ async fn handler6(
     path = "/datagoeshere",
 }]
 async fn handler7(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<()>>,
     _dump: UntypedBody,
 ) -> Result<HttpResponseOk<()>, HttpError> {
     unimplemented!();
 }
 
-fn make_api() -> Result<ApiDescription, String> {
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+#[allow(dead_code)]
+struct HeaderArgs {
+    x_request_count: u32,
+    x_trace_token: Option<String>,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/headers",
+}]
+async fn handler8(
+    _rqctx: Arc<RequestContext<()>>,
+    _headers: Header<HeaderArgs>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    unimplemented!();
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[allow(dead_code)]
+struct CookieArgs {
+    session: String,
+    theme: Option<String>,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/cookies",
+}]
+async fn handler9(
+    _rqctx: Arc<RequestContext<()>>,
+    _cookies: Cookies<CookieArgs>,
+) -> Result<HttpResponseSetCookies<HttpResponseOk<()>>, HttpError> {
+    unimplemented!();
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[allow(dead_code)]
+struct ArtifactPath {
+    path: String,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/artifacts/{path:.*}",
+}]
+async fn handler10(
+    _rqctx: Arc<RequestContext<()>>,
+    _path: Path<ArtifactPath>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    unimplemented!();
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[allow(dead_code)]
+struct ArtifactIdPath {
+    id: uuid::Uuid,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/artifacts/{id:uuid}",
+}]
+async fn handler11(
+    _rqctx: Arc<RequestContext<()>>,
+    _path: Path<ArtifactIdPath>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    unimplemented!();
+}
+
+#[endpoint {
+    method = PUT,
+    path = "/images/upload",
+    request_body_max_bytes = 1073741824,
+}]
+async fn handler12(
+    _rqctx: Arc<RequestContext<()>>,
+    _body: StreamingBody,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    unimplemented!();
+}
+
+fn make_api() -> Result<ApiDescription<()>, String> {
     let mut api = ApiDescription::new();
     api.register(handler1)?;
     api.register(handler2)?;
@@ -145,6 +229,11 @@ fn make_api() -> Result<ApiDescription, String> {
     api.register(handler5)?;
     api.register(handler6)?;
     api.register(handler7)?;
+    api.register(handler8)?;
+    api.register(handler9)?;
+    api.register(handler10)?;
+    api.register(handler11)?;
+    api.register(handler12)?;
     Ok(api)
 }
 
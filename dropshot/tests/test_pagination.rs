@@ -165,7 +165,7 @@ fn page_selector_for(n: &u16, _p: &EmptyScanParams) -> IntegersPageSelector {
  * Define an API with a couple of different endpoints that allow us to exercise
  * various functionality.
  */
-fn paginate_api() -> ApiDescription {
+fn paginate_api() -> ApiDescription<usize> {
     let mut api = ApiDescription::new();
     api.register(api_integers).unwrap();
     api.register(api_empty).unwrap();
@@ -198,7 +198,7 @@ fn range_u16(start: u16, limit: u16) -> Vec<u16> {
     path = "/intapi",
 }]
 async fn api_integers(
-    rqctx: Arc<RequestContext>,
+    rqctx: Arc<RequestContext<usize>>,
     query: Query<PaginationParams<EmptyScanParams, IntegersPageSelector>>,
 ) -> Result<HttpResponseOk<ResultsPage<u16>>, HttpError> {
     let pag_params = query.into_inner();
@@ -399,7 +399,7 @@ async fn test_paginate_basic() {
     path = "/empty",
 }]
 async fn api_empty(
-    _rqctx: Arc<RequestContext>,
+    _rqctx: Arc<RequestContext<usize>>,
     _query: Query<PaginationParams<EmptyScanParams, IntegersPageSelector>>,
 ) -> Result<HttpResponseOk<ResultsPage<u16>>, HttpError> {
     Ok(HttpResponseOk(ResultsPage::new(
@@ -461,7 +461,7 @@ async fn test_paginate_empty() {
     path = "/ints_extra",
 }]
 async fn api_with_extra_params(
-    rqctx: Arc<RequestContext>,
+    rqctx: Arc<RequestContext<usize>>,
     query_pag: Query<PaginationParams<EmptyScanParams, IntegersPageSelector>>,
     query_extra: Query<ExtraQueryParams>,
 ) -> Result<HttpResponseOk<ExtraResultsPage>, HttpError> {
@@ -558,7 +558,7 @@ struct ReqScanParams {
     path = "/required",
 }]
 async fn api_with_required_params(
-    rqctx: Arc<RequestContext>,
+    rqctx: Arc<RequestContext<usize>>,
     query: Query<PaginationParams<ReqScanParams, IntegersPageSelector>>,
 ) -> Result<HttpResponseOk<ResultsPage<u16>>, HttpError> {
     let pag_params = query.into_inner();
@@ -674,7 +674,7 @@ struct DictionaryPageSelector {
     path = "/dictionary",
 }]
 async fn api_dictionary(
-    rqctx: Arc<RequestContext>,
+    rqctx: Arc<RequestContext<usize>>,
     query: Query<
         PaginationParams<DictionaryScanParams, DictionaryPageSelector>,
     >,
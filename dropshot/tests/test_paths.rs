@@ -0,0 +1,170 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for the normalization of request paths: percent-decoding,
+ * encoded "/" characters, and redirects for trailing "/" characters.
+ */
+
+use dropshot::endpoint;
+use dropshot::test_util::read_json;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::Path;
+use dropshot::RequestContext;
+use http::Method;
+use http::StatusCode;
+use hyper::Body;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::Arc;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+#[derive(Deserialize, JsonSchema)]
+struct NamePath {
+    name: String,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/projects/{name}",
+}]
+async fn project_get(
+    _rqctx: Arc<RequestContext<usize>>,
+    path: Path<NamePath>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(path.into_inner().name))
+}
+
+#[endpoint {
+    method = GET,
+    path = "/objects/{name}",
+    allow_encoded_slashes = true,
+}]
+async fn object_get(
+    _rqctx: Arc<RequestContext<usize>>,
+    path: Path<NamePath>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(path.into_inner().name))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct FilePath {
+    path: String,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/files/{path:.*}",
+    allow_encoded_slashes = true,
+}]
+async fn file_get(
+    _rqctx: Arc<RequestContext<usize>>,
+    path: Path<FilePath>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk(path.into_inner().path))
+}
+
+fn paths_api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(project_get).unwrap();
+    api.register(object_get).unwrap();
+    api.register(file_get).unwrap();
+    api
+}
+
+#[tokio::test]
+async fn test_encoded_slashes() {
+    let testctx = common::test_setup("encoded_slashes", paths_api());
+    let client = &testctx.client_testctx;
+
+    let error = client
+        .make_request_with_body(
+            Method::GET,
+            "/projects/a%2Fb",
+            Body::empty(),
+            StatusCode::BAD_REQUEST,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(error.message, "URI path segment contains an encoded \"/\"");
+
+    let mut response = client
+        .make_request_no_body(Method::GET, "/objects/a%2Fb", StatusCode::OK)
+        .await
+        .unwrap();
+    let name: String = read_json(&mut response).await;
+    assert_eq!(name, "a/b");
+
+    /*
+     * Even where encoded slashes are allowed, they can't be used to sneak
+     * "." or ".." components into a variable.
+     */
+    let mut response = client
+        .make_request_no_body(Method::GET, "/files/a%2Fb/c", StatusCode::OK)
+        .await
+        .unwrap();
+    let path: String = read_json(&mut response).await;
+    assert_eq!(path, "a/b/c");
+    for bad_path in &[
+        "/files/..%2F..%2Fetc%2Fpasswd",
+        "/files/a%2F.%2Fb",
+        "/files/%2e%2e/etc/passwd",
+    ] {
+        let error = client
+            .make_request_with_body(
+                Method::GET,
+                bad_path,
+                Body::empty(),
+                StatusCode::BAD_REQUEST,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.message,
+            "URI path segment contains an encoded \".\" or \"..\""
+        );
+    }
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_trailing_slash_redirect() {
+    let mut api = paths_api();
+    api.redirect_trailing_slashes(true);
+    let testctx = common::test_setup("trailing_slash_redirect", api);
+
+    let uri = testctx.client_testctx.url("/projects/p%201/?x=1");
+    let response = hyper::Client::new().get(uri).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        response.headers().get(http::header::LOCATION).unwrap(),
+        "/projects/p%201?x=1"
+    );
+
+    /*
+     * A path starting with "//" must not redirect to a location starting with
+     * "//", which clients would take to be another host.
+     */
+    let uri = testctx.client_testctx.url("//projects/evil.example/");
+    let response = hyper::Client::new().get(uri).await.unwrap();
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(
+        response.headers().get(http::header::LOCATION).unwrap(),
+        "/projects/evil.example"
+    );
+
+    let mut response = testctx
+        .client_testctx
+        .make_request_no_body(Method::GET, "/projects/p%201", StatusCode::OK)
+        .await
+        .unwrap();
+    let name: String = read_json(&mut response).await;
+    assert_eq!(name, "p 1");
+
+    testctx.teardown().await;
+}
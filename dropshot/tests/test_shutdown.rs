@@ -0,0 +1,122 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for shutting down a server, with and without a drain timeout.
+ */
+
+use dropshot::endpoint;
+use dropshot::ApiDescription;
+use dropshot::ConfigDropshot;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::HttpServer;
+use dropshot::RequestContext;
+use http::StatusCode;
+use hyper::Body;
+use hyper::Client;
+use hyper::Response;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+/** Never completes, as though the handler were stuck. */
+#[endpoint {
+    method = GET,
+    path = "/stuck",
+}]
+async fn shutdown_stuck(
+    _rqctx: Arc<RequestContext<()>>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    futures::future::pending::<()>().await;
+    unreachable!();
+}
+
+/** Completes after a short delay. */
+#[endpoint {
+    method = GET,
+    path = "/slow",
+}]
+async fn shutdown_slow(
+    _rqctx: Arc<RequestContext<()>>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    Ok(HttpResponseOk(()))
+}
+
+fn shutdown_server(log: &slog::Logger) -> HttpServer<()> {
+    let mut api = ApiDescription::new();
+    api.register(shutdown_stuck).unwrap();
+    api.register(shutdown_slow).unwrap();
+    HttpServer::new(&ConfigDropshot::default(), api, (), log).unwrap()
+}
+
+/**
+ * Issue a GET request for `path` in the background, then wait until the server
+ * reports that the request is in flight.
+ */
+async fn start_request(
+    server: &HttpServer<()>,
+    path: &str,
+) -> tokio::task::JoinHandle<hyper::Result<Response<Body>>> {
+    let uri = format!("http://{}{}", server.local_addr(), path);
+    let request =
+        tokio::spawn(
+            async move { Client::new().get(uri.parse().unwrap()).await },
+        );
+    while server.requests_in_flight() == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    request
+}
+
+#[tokio::test]
+async fn test_shutdown_drain_timeout() {
+    let logctx = common::test_logctx("shutdown_drain_timeout");
+    let log = logctx.log.new(o!());
+    let mut server = shutdown_server(&log);
+    let server_task = server.run();
+    assert_eq!(server.requests_in_flight(), 0);
+
+    /*
+     * A request that never completes should be cut off once the drain timeout
+     * expires, allowing the server to finish shutting down.
+     */
+    let request = start_request(&server, "/stuck").await;
+    assert_eq!(server.requests_in_flight(), 1);
+    server.close_with_drain_timeout(Duration::from_millis(100));
+    tokio::time::timeout(Duration::from_secs(10), server_task)
+        .await
+        .expect("server did not shut down after its drain timeout")
+        .unwrap()
+        .unwrap();
+    assert!(request.await.unwrap().is_err());
+
+    logctx.cleanup_successful();
+}
+
+#[tokio::test]
+async fn test_shutdown_drain_completes() {
+    let logctx = common::test_logctx("shutdown_drain_completes");
+    let log = logctx.log.new(o!());
+    let mut server = shutdown_server(&log);
+    let server_task = server.run();
+
+    /*
+     * A request that completes before the drain timeout should be allowed to
+     * finish normally.
+     */
+    let request = start_request(&server, "/slow").await;
+    server.close_with_drain_timeout(Duration::from_secs(60));
+    let response = request.await.unwrap().unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    tokio::time::timeout(Duration::from_secs(10), server_task)
+        .await
+        .expect("server did not shut down after its last request")
+        .unwrap()
+        .unwrap();
+
+    logctx.cleanup_successful();
+}
@@ -0,0 +1,139 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for the `StreamingBody` extractor.
+ */
+
+use bytes::Bytes;
+use dropshot::endpoint;
+use dropshot::test_util::read_json;
+use dropshot::ApiDescription;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+use dropshot::StreamingBody;
+use futures::StreamExt;
+use http::Method;
+use http::StatusCode;
+use hyper::Body;
+use hyper::Client;
+use hyper::Request;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+#[derive(Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+struct UploadResult {
+    contents: String,
+}
+
+/**
+ * Writes the body to a file as it arrives, then reports what the file
+ * contains.
+ */
+#[endpoint {
+    method = PUT,
+    path = "/upload",
+    request_body_max_bytes = 4096,
+}]
+async fn upload(
+    rqctx: Arc<RequestContext<usize>>,
+    mut body: StreamingBody,
+) -> Result<HttpResponseOk<UploadResult>, HttpError> {
+    let path = std::env::temp_dir()
+        .join(format!("dropshot-test-upload-{}", rqctx.request_id));
+    let mut file = tokio::fs::File::create(&path).await.unwrap();
+    let result = async {
+        while let Some(chunk) = body.next().await {
+            file.write_all(&chunk?).await.unwrap();
+        }
+        Ok(())
+    }
+    .await;
+    drop(file);
+    let contents = tokio::fs::read_to_string(&path).await.unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+    result.map(|_| {
+        HttpResponseOk(UploadResult {
+            contents,
+        })
+    })
+}
+
+fn streaming_api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(upload).unwrap();
+    api
+}
+
+/** Returns a body that's sent in `nchunks` chunks of `size` bytes each. */
+fn chunked_body(nchunks: usize, size: usize) -> Body {
+    let chunks = (0..nchunks).map(move |i| {
+        let c = b'a' + (i % 26) as u8;
+        Ok::<_, std::io::Error>(Bytes::from(vec![c; size]))
+    });
+    Body::wrap_stream(futures::stream::iter(chunks))
+}
+
+#[tokio::test]
+async fn test_streaming_body() {
+    let testctx = common::test_setup("streaming_body", streaming_api());
+    let client = &testctx.client_testctx;
+
+    /*
+     * The endpoint's limit applies instead of the server's (1024 bytes), both
+     * for bodies of known size and for those sent in chunks.
+     */
+    let mut response = client
+        .make_request_with_body(
+            Method::PUT,
+            "/upload",
+            Body::from(vec![b'x'; 2000]),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+    let result: UploadResult = read_json(&mut response).await;
+    assert_eq!(result.contents, "x".repeat(2000));
+
+    let uri = client.url("/upload");
+    let request = Request::put(uri).body(chunked_body(4, 1000)).unwrap();
+    let mut response = Client::new().request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let result: UploadResult = read_json(&mut response).await;
+    assert_eq!(
+        result.contents,
+        ["a", "b", "c", "d"].iter().map(|c| c.repeat(1000)).collect::<String>()
+    );
+
+    /*
+     * A body that's too large fails, either immediately (if the request says
+     * how large it is) or once the limit is exceeded.
+     */
+    let error = client
+        .make_request_with_body(
+            Method::PUT,
+            "/upload",
+            Body::from(vec![b'x'; 5000]),
+            StatusCode::BAD_REQUEST,
+        )
+        .await
+        .unwrap_err();
+    assert_eq!(
+        error.message,
+        "request body exceeded maximum size of 4096 bytes"
+    );
+
+    let uri = client.url("/upload");
+    let request = Request::put(uri).body(chunked_body(5, 1000)).unwrap();
+    let response = Client::new().request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    testctx.teardown().await;
+}
@@ -0,0 +1,324 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for time limits on handling requests, including limits on how
+ * long clients may take to send them.
+ */
+
+use dropshot::endpoint;
+use dropshot::test_util::read_config;
+use dropshot::test_util::TestContext;
+use dropshot::ApiDescription;
+use dropshot::ConfigDropshot;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::RequestContext;
+use dropshot::UntypedBody;
+use http::Method;
+use http::StatusCode;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+/** Takes longer than the server-wide timeout used in these tests. */
+#[endpoint {
+    method = GET,
+    path = "/slow",
+}]
+async fn timeout_slow(
+    _rqctx: Arc<RequestContext<()>>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    tokio::time::sleep(Duration::from_secs(300)).await;
+    Ok(HttpResponseOk(()))
+}
+
+/** Like `/slow`, but with a short timeout of its own. */
+#[endpoint {
+    method = GET,
+    path = "/slow-short-timeout",
+    timeout_ms = 100,
+}]
+async fn timeout_slow_short(
+    _rqctx: Arc<RequestContext<()>>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    tokio::time::sleep(Duration::from_secs(300)).await;
+    Ok(HttpResponseOk(()))
+}
+
+/**
+ * Takes a little longer than the server-wide timeout used in these tests, but
+ * has a longer timeout of its own.
+ */
+#[endpoint {
+    method = GET,
+    path = "/slowish-long-timeout",
+    timeout_ms = 60000,
+}]
+async fn timeout_slowish_long(
+    _rqctx: Arc<RequestContext<()>>,
+) -> Result<HttpResponseOk<()>, HttpError> {
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    Ok(HttpResponseOk(()))
+}
+
+/** Returns the size of the request body. */
+#[endpoint {
+    method = PUT,
+    path = "/upload",
+}]
+async fn timeout_upload(
+    _rqctx: Arc<RequestContext<()>>,
+    body: UntypedBody,
+) -> Result<HttpResponseOk<usize>, HttpError> {
+    Ok(HttpResponseOk(body.as_bytes().len()))
+}
+
+fn timeout_testctx(
+    test_name: &str,
+    config: &ConfigDropshot,
+) -> TestContext<()> {
+    let mut api = ApiDescription::new();
+    api.register(timeout_slow).unwrap();
+    api.register(timeout_slow_short).unwrap();
+    api.register(timeout_slowish_long).unwrap();
+    api.register(timeout_upload).unwrap();
+
+    common::test_setup_with_config(test_name, api, (), config)
+}
+
+#[tokio::test]
+async fn test_handler_timeout_server() {
+    let config = ConfigDropshot {
+        handler_timeout_ms: Some(100),
+        ..Default::default()
+    };
+    let testctx = timeout_testctx("handler_timeout_server", &config);
+    let client = &testctx.client_testctx;
+
+    /* The server-wide timeout applies to endpoints without their own. */
+    let error = client
+        .make_request_error(
+            Method::GET,
+            "/slow",
+            StatusCode::SERVICE_UNAVAILABLE,
+        )
+        .await;
+    assert_eq!(error.message, "Service Unavailable");
+    assert!(!error.request_id.is_empty());
+
+    /* An endpoint's own timeout takes precedence. */
+    client
+        .make_request_no_body(
+            Method::GET,
+            "/slowish-long-timeout",
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_handler_timeout_endpoint() {
+    let testctx =
+        timeout_testctx("handler_timeout_endpoint", &ConfigDropshot::default());
+    let client = &testctx.client_testctx;
+
+    /* Without a server-wide timeout, only the endpoint's timeout applies. */
+    client
+        .make_request_error(
+            Method::GET,
+            "/slow-short-timeout",
+            StatusCode::SERVICE_UNAVAILABLE,
+        )
+        .await;
+    client
+        .make_request_no_body(
+            Method::GET,
+            "/slowish-long-timeout",
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+
+    testctx.teardown().await;
+}
+
+/**
+ * Sends `head` (the request line and headers) to the server at `addr`,
+ * followed by the bytes of `body` one at a time, `delay` apart, then returns
+ * whatever the server sends back before closing the connection.  Stops sending
+ * early if the server closes the connection.
+ */
+async fn slow_request(
+    addr: SocketAddr,
+    head: &str,
+    body: &[u8],
+    delay: Duration,
+) -> String {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (mut reader, mut writer) = stream.into_split();
+    writer.write_all(head.as_bytes()).await.unwrap();
+    let body = body.to_vec();
+    let sender = tokio::spawn(async move {
+        for byte in body {
+            tokio::time::sleep(delay).await;
+            if writer.write_all(&[byte]).await.is_err() {
+                break;
+            }
+        }
+        writer
+    });
+
+    let mut response = Vec::new();
+    tokio::time::timeout(
+        Duration::from_secs(30),
+        reader.read_to_end(&mut response),
+    )
+    .await
+    .expect("server did not close the connection")
+    .unwrap();
+    sender.abort();
+    String::from_utf8(response).unwrap()
+}
+
+#[tokio::test]
+async fn test_request_header_timeout() {
+    let config = ConfigDropshot {
+        request_header_timeout_ms: Some(200),
+        ..Default::default()
+    };
+    let testctx = timeout_testctx("request_header_timeout", &config);
+    let addr = testctx.server.local_addr();
+
+    /*
+     * A client that never finishes sending its headers should be disconnected.
+     */
+    let response = slow_request(
+        addr,
+        "PUT /upload HTTP/1.1\r\nHost: localhost\r\n",
+        b"Content-Length: 0\r\n\r\n",
+        Duration::from_secs(1),
+    )
+    .await;
+    assert_eq!(response, "");
+
+    /* Clients that are quick enough should be unaffected. */
+    testctx
+        .client_testctx
+        .make_request_with_body(
+            Method::PUT,
+            "/upload",
+            "hello".into(),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_request_body_timeout() {
+    let config = ConfigDropshot {
+        request_body_timeout_ms: Some(300),
+        ..Default::default()
+    };
+    let testctx = timeout_testctx("request_body_timeout", &config);
+    let addr = testctx.server.local_addr();
+
+    /*
+     * A client that takes too long to send the body should get a 408 error,
+     * after which the server should close the connection.
+     */
+    let response = slow_request(
+        addr,
+        "PUT /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\n",
+        b"0123456789",
+        Duration::from_millis(100),
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    assert!(response.contains("\r\nconnection: close\r\n"));
+    assert!(response.contains("x-request-id"));
+
+    /* Clients that are quick enough should be unaffected. */
+    testctx
+        .client_testctx
+        .make_request_with_body(
+            Method::PUT,
+            "/upload",
+            "hello".into(),
+            StatusCode::OK,
+        )
+        .await
+        .unwrap();
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_request_body_min_rate() {
+    let config = ConfigDropshot {
+        request_body_min_bytes_per_sec: Some(100),
+        ..Default::default()
+    };
+    let testctx = timeout_testctx("request_body_min_rate", &config);
+    let addr = testctx.server.local_addr();
+
+    /*
+     * A client trickling the body in at 10 bytes per second should be cut off
+     * after the grace period, long before it would finish.
+     */
+    let response = slow_request(
+        addr,
+        "PUT /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: \
+         100\r\n\r\n",
+        &[b'x'; 100],
+        Duration::from_millis(100),
+    )
+    .await;
+    assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    assert!(response.contains("\r\nconnection: close\r\n"));
+
+    testctx.teardown().await;
+}
+
+#[test]
+fn test_handler_timeout_config() {
+    let config = read_config::<ConfigDropshot>(
+        "handler_timeout_config",
+        r#"
+            bind_address = "127.0.0.1:0"
+            handler_timeout_ms = 2500
+            request_header_timeout_ms = 10000
+            request_body_timeout_ms = 30000
+            request_body_min_bytes_per_sec = 512
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.handler_timeout_ms, Some(2500));
+    assert_eq!(config.request_header_timeout_ms, Some(10000));
+    assert_eq!(config.request_body_timeout_ms, Some(30000));
+    assert_eq!(config.request_body_min_bytes_per_sec, Some(512));
+
+    let config = read_config::<ConfigDropshot>(
+        "handler_timeout_config_default",
+        r#"
+            bind_address = "127.0.0.1:0"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.handler_timeout_ms, None);
+    assert_eq!(config.request_header_timeout_ms, None);
+    assert_eq!(config.request_body_timeout_ms, None);
+    assert_eq!(config.request_body_min_bytes_per_sec, None);
+}
@@ -0,0 +1,650 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for servers that terminate TLS.
+ */
+
+use dropshot::endpoint;
+use dropshot::test_util::log_file_for_test;
+use dropshot::test_util::read_config;
+use dropshot::test_util::read_json;
+use dropshot::test_util::TestContext;
+use dropshot::ApiDescription;
+use dropshot::ClientCertificate;
+use dropshot::ConfigDropshot;
+use dropshot::ConfigHttp2;
+use dropshot::ConfigTls;
+use dropshot::ConfigTlsVersion;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::HttpServer;
+use dropshot::RequestContext;
+use http::StatusCode;
+use http::Version;
+use hyper::client::HttpConnector;
+use hyper::Client;
+use hyper_rustls::HttpsConnector;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+/**
+ * Certificate chain and private key files for a self-signed certificate for
+ * "localhost".  The files are removed when this is dropped.
+ */
+struct TestCertificate {
+    cert_der: Vec<u8>,
+    cert_file: PathBuf,
+    key_file: PathBuf,
+}
+
+impl TestCertificate {
+    fn new(test_name: &str) -> TestCertificate {
+        let base = log_file_for_test(test_name);
+        let cert_file = base.with_extension("cert.pem");
+        let key_file = base.with_extension("key.pem");
+        let cert = TestCertificate {
+            cert_der: Vec::new(),
+            cert_file,
+            key_file,
+        };
+        cert.regenerate()
+    }
+
+    /**
+     * Generate a new self-signed certificate and key and write them over the
+     * existing files.
+     */
+    fn regenerate(mut self) -> TestCertificate {
+        let cert =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+                .unwrap();
+        fs::write(&self.cert_file, cert.serialize_pem().unwrap()).unwrap();
+        fs::write(&self.key_file, cert.serialize_private_key_pem()).unwrap();
+        self.cert_der = cert.serialize_der().unwrap();
+        self
+    }
+
+    fn config(&self, min_version: ConfigTlsVersion) -> ConfigTls {
+        ConfigTls {
+            cert_file: self.cert_file.clone(),
+            key_file: self.key_file.clone(),
+            min_version,
+            client_ca_file: None,
+            require_client_cert: false,
+            handshake_timeout_ms: 10000,
+        }
+    }
+}
+
+impl Drop for TestCertificate {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.cert_file);
+        let _ = fs::remove_file(&self.key_file);
+    }
+}
+
+/**
+ * A certificate authority for issuing client certificates, along with a file
+ * containing its PEM-encoded certificate.  The file is removed when this is
+ * dropped.
+ */
+struct TestCa {
+    ca: rcgen::Certificate,
+    ca_file: PathBuf,
+}
+
+impl TestCa {
+    fn new(test_name: &str) -> TestCa {
+        let mut params = rcgen::CertificateParams::new(Vec::new());
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "dropshot test CA");
+        let ca = rcgen::Certificate::from_params(params).unwrap();
+        let ca_file = log_file_for_test(test_name).with_extension("ca.pem");
+        fs::write(&ca_file, ca.serialize_pem().unwrap()).unwrap();
+        TestCa {
+            ca,
+            ca_file,
+        }
+    }
+
+    /**
+     * Issue a client certificate with the given common name, returning the
+     * DER-encoded certificate and private key.
+     */
+    fn issue(&self, common_name: &str) -> (Vec<u8>, Vec<u8>) {
+        let mut params = rcgen::CertificateParams::new(vec!["client.example.\
+                                                             com"
+        .to_string()]);
+        params.subject_alt_names.push(rcgen::SanType::URI(
+            "spiffe://example.com/client".to_string(),
+        ));
+        params.distinguished_name.push(rcgen::DnType::CommonName, common_name);
+        params.extended_key_usages =
+            vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+        let cert = rcgen::Certificate::from_params(params).unwrap();
+        let der = cert.serialize_der_with_signer(&self.ca).unwrap();
+        (der, cert.serialize_private_key_der())
+    }
+}
+
+impl Drop for TestCa {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.ca_file);
+    }
+}
+
+/**
+ * Returns an HTTPS client that trusts only the certificate `cert_der` and
+ * speaks only the given TLS versions.
+ */
+fn make_client(
+    cert_der: &[u8],
+    versions: &[&'static rustls::SupportedProtocolVersion],
+) -> Client<HttpsConnector<HttpConnector>> {
+    make_client_with_identity(cert_der, versions, None)
+}
+
+/**
+ * Like `make_client()`, but the client presents the given DER-encoded
+ * certificate and private key, if any, when the server asks for one.
+ */
+fn make_client_with_identity(
+    cert_der: &[u8],
+    versions: &[&'static rustls::SupportedProtocolVersion],
+    identity: Option<&(Vec<u8>, Vec<u8>)>,
+) -> Client<HttpsConnector<HttpConnector>> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(&rustls::Certificate(cert_der.to_vec())).unwrap();
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(versions)
+        .unwrap()
+        .with_root_certificates(roots);
+    let tls_config = match identity {
+        None => builder.with_no_client_auth(),
+        Some((client_cert_der, client_key_der)) => builder
+            .with_client_auth_cert(
+                vec![rustls::Certificate(client_cert_der.clone())],
+                rustls::PrivateKey(client_key_der.clone()),
+            )
+            .unwrap(),
+    };
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_only()
+        .enable_http1()
+        .build();
+    Client::builder().build(connector)
+}
+
+/**
+ * Returns an HTTPS client that trusts only the certificate `cert_der` and
+ * offers HTTP/2 as well as HTTP/1.1 during the TLS handshake.
+ */
+fn make_http2_client(cert_der: &[u8]) -> Client<HttpsConnector<HttpConnector>> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(&rustls::Certificate(cert_der.to_vec())).unwrap();
+    let tls_config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_only()
+        .enable_all_versions()
+        .build();
+    Client::builder().build(connector)
+}
+
+#[endpoint {
+    method = GET,
+    path = "/",
+}]
+async fn tls_root(
+    _rqctx: Arc<RequestContext<()>>,
+) -> Result<HttpResponseOk<String>, HttpError> {
+    Ok(HttpResponseOk("hello over TLS".to_string()))
+}
+
+#[derive(Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+struct ClientIdentity {
+    subject: String,
+    subject_alt_names: Vec<String>,
+    fingerprint_sha256: String,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/whoami",
+}]
+async fn tls_whoami(
+    rqctx: Arc<RequestContext<()>>,
+) -> Result<HttpResponseOk<Option<ClientIdentity>>, HttpError> {
+    let identity = rqctx.connection.client_certificate.as_ref().map(
+        |cert: &ClientCertificate| ClientIdentity {
+            subject: cert.subject.clone(),
+            subject_alt_names: cert.subject_alt_names.clone(),
+            fingerprint_sha256: cert.fingerprint_sha256.clone(),
+        },
+    );
+    Ok(HttpResponseOk(identity))
+}
+
+struct TlsTestContext {
+    testctx: TestContext<()>,
+}
+
+impl TlsTestContext {
+    fn new(test_name: &str, tls: ConfigTls) -> TlsTestContext {
+        let config = ConfigDropshot {
+            tls: Some(tls),
+            ..Default::default()
+        };
+        TlsTestContext::with_config(test_name, &config)
+    }
+
+    fn with_config(test_name: &str, config: &ConfigDropshot) -> TlsTestContext {
+        let mut api = ApiDescription::new();
+        api.register(tls_root).unwrap();
+        api.register(tls_whoami).unwrap();
+        TlsTestContext {
+            testctx: common::test_setup_with_config(test_name, api, (), config),
+        }
+    }
+
+    fn server(&self) -> &HttpServer<()> {
+        &self.testctx.server
+    }
+
+    fn url(&self, scheme: &str) -> hyper::Uri {
+        let addr: SocketAddr = self.server().local_addr();
+        format!("{}://localhost:{}/", scheme, addr.port()).parse().unwrap()
+    }
+
+    async fn whoami(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+    ) -> Result<Option<ClientIdentity>, hyper::Error> {
+        let url = format!("{}whoami", self.url("https"));
+        let mut response = client.get(url.parse().unwrap()).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        Ok(read_json(&mut response).await)
+    }
+
+    async fn teardown(self) {
+        self.testctx.teardown().await;
+    }
+}
+
+async fn request_ok(client: &Client<HttpsConnector<HttpConnector>>, url: &str) {
+    let mut response = client.get(url.parse().unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: String = read_json(&mut response).await;
+    assert_eq!(body, "hello over TLS");
+}
+
+#[tokio::test]
+async fn test_tls_basic() {
+    let cert = TestCertificate::new("tls_basic");
+    let testctx =
+        TlsTestContext::new("tls_basic", cert.config(ConfigTlsVersion::Tls12));
+    let url = testctx.url("https").to_string();
+
+    /* Both TLS 1.2 and TLS 1.3 clients should work by default. */
+    let client = make_client(&cert.cert_der, &[&rustls::version::TLS13]);
+    request_ok(&client, &url).await;
+    let client = make_client(&cert.cert_der, &[&rustls::version::TLS12]);
+    request_ok(&client, &url).await;
+
+    /*
+     * The server should not offer HTTP/2 unless it's configured to, even to
+     * clients that support it.
+     */
+    let client = make_http2_client(&cert.cert_der);
+    let response = client.get(url.parse().unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), Version::HTTP_11);
+
+    /* A plaintext HTTP request should not get a response. */
+    let plain_client = Client::new();
+    let result = plain_client.get(testctx.url("http")).await;
+    assert!(result.is_err());
+
+    /* A client that does not trust our certificate should fail. */
+    let other_cert =
+        rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+            .unwrap();
+    let client = make_client(&other_cert.serialize_der().unwrap(), &[
+        &rustls::version::TLS13,
+    ]);
+    let error = client.get(url.parse().unwrap()).await.unwrap_err();
+    assert!(error.is_connect());
+
+    /* The server should still be healthy after these failures. */
+    let client = make_client(&cert.cert_der, &[&rustls::version::TLS13]);
+    request_ok(&client, &url).await;
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_tls_http2() {
+    let cert = TestCertificate::new("tls_http2");
+    let config = ConfigDropshot {
+        tls: Some(cert.config(ConfigTlsVersion::Tls12)),
+        http2: Some(ConfigHttp2::default()),
+        ..Default::default()
+    };
+    let testctx = TlsTestContext::with_config("tls_http2", &config);
+    let url = testctx.url("https").to_string();
+
+    /* Clients that offer HTTP/2 during the handshake should get it. */
+    let client = make_http2_client(&cert.cert_der);
+    let mut response = client.get(url.parse().unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), Version::HTTP_2);
+    let body: String = read_json(&mut response).await;
+    assert_eq!(body, "hello over TLS");
+
+    /* Clients that only speak HTTP/1.1 should be unaffected. */
+    let client = make_client(&cert.cert_der, &[&rustls::version::TLS13]);
+    request_ok(&client, &url).await;
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_tls_min_version() {
+    let cert = TestCertificate::new("tls_min_version");
+    let testctx = TlsTestContext::new(
+        "tls_min_version",
+        cert.config(ConfigTlsVersion::Tls13),
+    );
+    let url = testctx.url("https").to_string();
+
+    let client = make_client(&cert.cert_der, &[&rustls::version::TLS12]);
+    let error = client.get(url.parse().unwrap()).await.unwrap_err();
+    assert!(error.is_connect());
+
+    let client = make_client(&cert.cert_der, &[&rustls::version::TLS13]);
+    request_ok(&client, &url).await;
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_tls_refresh() {
+    let cert = TestCertificate::new("tls_refresh");
+    let testctx = TlsTestContext::new(
+        "tls_refresh",
+        cert.config(ConfigTlsVersion::Tls12),
+    );
+    let url = testctx.url("https").to_string();
+    let old_cert_der = cert.cert_der.clone();
+
+    let client = make_client(&old_cert_der, &[&rustls::version::TLS13]);
+    request_ok(&client, &url).await;
+
+    /*
+     * Replace the certificate on disk.  Until we refresh, new connections
+     * should continue to use the old certificate.
+     */
+    let cert = cert.regenerate();
+    let client = make_client(&old_cert_der, &[&rustls::version::TLS13]);
+    request_ok(&client, &url).await;
+
+    /* After a refresh, new connections should use the new certificate. */
+    testctx.server().refresh_tls().unwrap();
+    let client = make_client(&old_cert_der, &[&rustls::version::TLS13]);
+    let error = client.get(url.parse().unwrap()).await.unwrap_err();
+    assert!(error.is_connect());
+    let client = make_client(&cert.cert_der, &[&rustls::version::TLS13]);
+    request_ok(&client, &url).await;
+
+    /*
+     * If the new files are bad, the refresh should fail and the server should
+     * keep using the certificate that it had.
+     */
+    fs::write(&cert.cert_file, "not a certificate").unwrap();
+    let error = testctx.server().refresh_tls().unwrap_err();
+    assert!(error.to_string().starts_with("no TLS certificates found in"));
+    let client = make_client(&cert.cert_der, &[&rustls::version::TLS13]);
+    request_ok(&client, &url).await;
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_tls_handshake_timeout() {
+    let cert = TestCertificate::new("tls_handshake_timeout");
+    let config = ConfigDropshot {
+        tls: Some(ConfigTls {
+            handshake_timeout_ms: 500,
+            ..cert.config(ConfigTlsVersion::Tls12)
+        }),
+        max_connections: Some(1),
+        ..Default::default()
+    };
+    let testctx = TlsTestContext::with_config("tls_handshake_timeout", &config);
+    let url = testctx.url("https").to_string();
+
+    /*
+     * A client that connects and never starts the handshake takes up a
+     * connection slot, so other clients are refused until its handshake times
+     * out and the server closes the connection.
+     */
+    let mut silent =
+        tokio::net::TcpStream::connect(testctx.server().local_addr())
+            .await
+            .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let client = make_client(&cert.cert_der, &[&rustls::version::TLS13]);
+    client.get(url.parse().unwrap()).await.unwrap_err();
+
+    let mut buf = [0u8; 16];
+    let nread =
+        tokio::time::timeout(Duration::from_secs(5), silent.read(&mut buf))
+            .await
+            .expect("server did not close the silent connection")
+            .unwrap();
+    assert_eq!(nread, 0);
+
+    let client = make_client(&cert.cert_der, &[&rustls::version::TLS13]);
+    request_ok(&client, &url).await;
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_tls_bad_config() {
+    let logctx = common::test_logctx("tls_bad_config");
+    let log = logctx.log.new(o!());
+    let config = ConfigDropshot {
+        tls: Some(ConfigTls {
+            cert_file: PathBuf::from("/nonexistent/cert.pem"),
+            key_file: PathBuf::from("/nonexistent/key.pem"),
+            min_version: ConfigTlsVersion::Tls12,
+            client_ca_file: None,
+            require_client_cert: false,
+            handshake_timeout_ms: 10000,
+        }),
+        ..Default::default()
+    };
+    let error = HttpServer::new(&config, ApiDescription::new(), (), &log)
+        .err()
+        .unwrap();
+    assert!(error
+        .to_string()
+        .starts_with("opening TLS certificate file \"/nonexistent/cert.pem\""));
+
+    /* Requiring client certificates without a client CA is an error. */
+    let cert = TestCertificate::new("tls_bad_config");
+    let config = ConfigDropshot {
+        tls: Some(ConfigTls {
+            require_client_cert: true,
+            ..cert.config(ConfigTlsVersion::Tls12)
+        }),
+        ..Default::default()
+    };
+    let error = HttpServer::new(&config, ApiDescription::new(), (), &log)
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "TLS configuration requires client certificates but does not specify \
+         \"client_ca_file\""
+    );
+
+    /* Refreshing TLS on a server without TLS is an error. */
+    let server =
+        HttpServer::new(&Default::default(), ApiDescription::new(), (), &log)
+            .unwrap();
+    let error = server.refresh_tls().unwrap_err();
+    assert_eq!(error.to_string(), "server is not configured to use TLS");
+
+    logctx.cleanup_successful();
+}
+
+#[tokio::test]
+async fn test_tls_client_cert_required() {
+    let cert = TestCertificate::new("tls_client_cert_required");
+    let ca = TestCa::new("tls_client_cert_required");
+    let other_ca = TestCa::new("tls_client_cert_required_other");
+    let testctx = TlsTestContext::new("tls_client_cert_required", ConfigTls {
+        client_ca_file: Some(ca.ca_file.clone()),
+        require_client_cert: true,
+        ..cert.config(ConfigTlsVersion::Tls12)
+    });
+    let versions = &[&rustls::version::TLS13, &rustls::version::TLS12];
+
+    /* A client with no certificate should be rejected. */
+    let client = make_client(&cert.cert_der, versions);
+    testctx.whoami(&client).await.unwrap_err();
+
+    /* So should a client whose certificate was issued by some other CA. */
+    let identity = other_ca.issue("impostor");
+    let client =
+        make_client_with_identity(&cert.cert_der, versions, Some(&identity));
+    testctx.whoami(&client).await.unwrap_err();
+
+    /*
+     * A client with a valid certificate should succeed, and the handler
+     * should see the details of that certificate.
+     */
+    let identity = ca.issue("test client");
+    let client =
+        make_client_with_identity(&cert.cert_der, versions, Some(&identity));
+    let found = testctx.whoami(&client).await.unwrap().unwrap();
+    let digest = ring::digest::digest(&ring::digest::SHA256, &identity.0);
+    let fingerprint = digest
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    assert_eq!(found, ClientIdentity {
+        subject: "CN=test client".to_string(),
+        subject_alt_names: vec![
+            "DNS:client.example.com".to_string(),
+            "URI:spiffe://example.com/client".to_string(),
+        ],
+        fingerprint_sha256: fingerprint,
+    });
+
+    /* The same should work over TLS 1.2. */
+    let client = make_client_with_identity(
+        &cert.cert_der,
+        &[&rustls::version::TLS12],
+        Some(&identity),
+    );
+    let found = testctx.whoami(&client).await.unwrap().unwrap();
+    assert_eq!(found.subject, "CN=test client");
+
+    testctx.teardown().await;
+}
+
+#[tokio::test]
+async fn test_tls_client_cert_optional() {
+    let cert = TestCertificate::new("tls_client_cert_optional");
+    let ca = TestCa::new("tls_client_cert_optional");
+    let other_ca = TestCa::new("tls_client_cert_optional_other");
+    let testctx = TlsTestContext::new("tls_client_cert_optional", ConfigTls {
+        client_ca_file: Some(ca.ca_file.clone()),
+        ..cert.config(ConfigTlsVersion::Tls12)
+    });
+    let versions = &[&rustls::version::TLS13];
+
+    /* A client with no certificate is allowed, but has no identity. */
+    let client = make_client(&cert.cert_der, versions);
+    assert_eq!(testctx.whoami(&client).await.unwrap(), None);
+
+    /* A client that presents a certificate must present a valid one. */
+    let identity = other_ca.issue("impostor");
+    let client =
+        make_client_with_identity(&cert.cert_der, versions, Some(&identity));
+    testctx.whoami(&client).await.unwrap_err();
+
+    let identity = ca.issue("test client");
+    let client =
+        make_client_with_identity(&cert.cert_der, versions, Some(&identity));
+    let found = testctx.whoami(&client).await.unwrap().unwrap();
+    assert_eq!(found.subject, "CN=test client");
+
+    testctx.teardown().await;
+}
+
+#[test]
+fn test_tls_config() {
+    let config = read_config::<ConfigDropshot>(
+        "tls_config",
+        r#"
+            bind_address = "127.0.0.1:0"
+            request_body_max_bytes = 1024
+            [tls]
+            cert_file = "/etc/server/cert.pem"
+            key_file = "/etc/server/key.pem"
+        "#,
+    )
+    .unwrap();
+    let tls = config.tls.unwrap();
+    assert_eq!(tls.cert_file, PathBuf::from("/etc/server/cert.pem"));
+    assert_eq!(tls.key_file, PathBuf::from("/etc/server/key.pem"));
+    assert_eq!(tls.min_version, ConfigTlsVersion::Tls12);
+    assert_eq!(tls.client_ca_file, None);
+    assert!(!tls.require_client_cert);
+    assert_eq!(tls.handshake_timeout_ms, 10000);
+
+    let config = read_config::<ConfigDropshot>(
+        "tls_config_client_auth",
+        r#"
+            bind_address = "127.0.0.1:0"
+            request_body_max_bytes = 1024
+            [tls]
+            cert_file = "cert.pem"
+            key_file = "key.pem"
+            min_version = "1.3"
+            client_ca_file = "ca.pem"
+            require_client_cert = true
+            handshake_timeout_ms = 2500
+        "#,
+    )
+    .unwrap();
+    let tls = config.tls.unwrap();
+    assert_eq!(tls.min_version, ConfigTlsVersion::Tls13);
+    assert_eq!(tls.client_ca_file, Some(PathBuf::from("ca.pem")));
+    assert!(tls.require_client_cert);
+    assert_eq!(tls.handshake_timeout_ms, 2500);
+}
@@ -0,0 +1,248 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for using Dropshot with Tower: wrapping the server's request
+ * handler in layers and mounting an `ApiService` in some other Hyper server.
+ */
+
+use dropshot::endpoint;
+use dropshot::test_util::read_json;
+use dropshot::ApiDescription;
+use dropshot::ApiService;
+use dropshot::ConfigDropshot;
+use dropshot::ConnectionPeer;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::HttpServer;
+use dropshot::RequestContext;
+use futures::future::BoxFuture;
+use http::header::HeaderValue;
+use http::StatusCode;
+use hyper::server::conn::AddrStream;
+use hyper::service::make_service_fn;
+use hyper::service::service_fn;
+use hyper::service::Service;
+use hyper::Body;
+use hyper::Client;
+use hyper::Request;
+use hyper::Response;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use tower_layer::Layer;
+use tower_layer::Stack;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+/** names of the layers that a request passed through, outermost first */
+#[derive(Clone)]
+struct Layers(Vec<&'static str>);
+
+#[derive(Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+struct RequestInfo {
+    peer: String,
+    layers: Vec<String>,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/info",
+}]
+async fn tower_info(
+    rqctx: Arc<RequestContext<usize>>,
+) -> Result<HttpResponseOk<RequestInfo>, HttpError> {
+    let extensions = rqctx.extensions.lock().await;
+    let layers = extensions.get::<Layers>().map_or(vec![], |layers| {
+        layers.0.iter().map(|name| name.to_string()).collect()
+    });
+    Ok(HttpResponseOk(RequestInfo {
+        peer: rqctx.connection.peer.to_string(),
+        layers,
+    }))
+}
+
+fn tower_api() -> ApiDescription<usize> {
+    let mut api = ApiDescription::new();
+    api.register(tower_info).unwrap();
+    api
+}
+
+/**
+ * Layer that records its name in each request's `Layers` extension and in an
+ * "x-layer" header on each response.
+ */
+#[derive(Clone)]
+struct NameLayer(&'static str);
+
+impl<S> Layer<S> for NameLayer {
+    type Service = NameService<S>;
+
+    fn layer(&self, inner: S) -> NameService<S> {
+        NameService {
+            name: self.0,
+            inner,
+        }
+    }
+}
+
+struct NameService<S> {
+    name: &'static str,
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for NameService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response<Body>, S::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        let name = self.name;
+        let extensions = request.extensions_mut();
+        match extensions.get_mut::<Layers>() {
+            Some(layers) => layers.0.push(name),
+            None => {
+                extensions.insert(Layers(vec![name]));
+            }
+        }
+        let response = self.inner.call(request);
+        Box::pin(async move {
+            let mut response = response.await?;
+            response
+                .headers_mut()
+                .append("x-layer", HeaderValue::from_static(name));
+            Ok(response)
+        })
+    }
+}
+
+fn layer_header(response: &Response<Body>) -> Vec<&str> {
+    response
+        .headers()
+        .get_all("x-layer")
+        .iter()
+        .map(|value| value.to_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_tower_layers() {
+    let logctx = common::test_logctx("tower_layers");
+    let log = logctx.log.new(o!());
+    let config = ConfigDropshot::default();
+    let layer = Stack::new(NameLayer("inner"), NameLayer("outer"));
+    let mut server =
+        HttpServer::new_with_layer(&config, tower_api(), 0, &log, layer)
+            .unwrap();
+    let server_task = server.run();
+    let client = Client::new();
+
+    /*
+     * The outermost layer sees the request first and the response last, and
+     * anything the layers add to the request is available to the handler.
+     */
+    let uri = format!("http://{}/info", server.local_addr());
+    let mut response = client.get(uri.parse().unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(layer_header(&response), vec!["inner", "outer"]);
+    let info: RequestInfo = read_json(&mut response).await;
+    assert_eq!(info.layers, vec!["outer", "inner"]);
+    assert!(info.peer.starts_with("127.0.0.1:"));
+
+    /* Layers also see requests that Dropshot fails. */
+    let uri = format!("http://{}/nonexistent", server.local_addr());
+    let response = client.get(uri.parse().unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(layer_header(&response), vec!["inner", "outer"]);
+
+    server.close();
+    server_task.await.unwrap().unwrap();
+    logctx.cleanup_successful();
+}
+
+#[tokio::test]
+async fn test_tower_api_service() {
+    let logctx = common::test_logctx("tower_api_service");
+    let log = logctx.log.new(o!());
+    let config = ConfigDropshot::default();
+    let api_service = ApiService::new(&config, tower_api(), 0, &log).unwrap();
+    assert_eq!(*api_service.app_private(), 0);
+
+    /*
+     * Without a peer, requests are reported as coming from an unknown peer.
+     */
+    let request = Request::get("/info").body(Body::empty()).unwrap();
+    let mut response = api_service.clone().call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let info: RequestInfo = read_json(&mut response).await;
+    assert_eq!(info, RequestInfo {
+        peer: String::from("unknown"),
+        layers: vec![],
+    });
+
+    /*
+     * Mount the API in a Hyper server that handles some requests itself and
+     * tells the API where each connection came from.
+     */
+    let make_service = make_service_fn(move |conn: &AddrStream| {
+        let peer = ConnectionPeer::Tcp(conn.remote_addr());
+        let api_service = api_service.with_peer(peer);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let mut api_service = api_service.clone();
+                async move {
+                    if request.uri().path() == "/health" {
+                        Ok(Response::new(Body::from("ok")))
+                    } else {
+                        api_service.call(request).await
+                    }
+                }
+            }))
+        }
+    });
+    let server = hyper::Server::bind(&"127.0.0.1:0".parse().unwrap())
+        .serve(make_service);
+    let local_addr = server.local_addr();
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let server_task = tokio::spawn(server.with_graceful_shutdown(async {
+        rx.await.unwrap();
+    }));
+    let client = Client::new();
+
+    let uri = format!("http://{}/health", local_addr);
+    let response = client.get(uri.parse().unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(body.as_ref(), b"ok");
+
+    let uri = format!("http://{}/info", local_addr);
+    let mut response = client.get(uri.parse().unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key(dropshot::HEADER_REQUEST_ID));
+    let info: RequestInfo = read_json(&mut response).await;
+    assert!(info.peer.starts_with("127.0.0.1:"));
+
+    let uri = format!("http://{}/nonexistent", local_addr);
+    let response = client.get(uri.parse().unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    tx.send(()).unwrap();
+    server_task.await.unwrap().unwrap();
+    logctx.cleanup_successful();
+}
@@ -0,0 +1,239 @@
+// Copyright 2021 Oxide Computer Company
+/*!
+ * Test cases for servers listening on Unix domain sockets.
+ */
+
+#![cfg(unix)]
+
+use dropshot::endpoint;
+use dropshot::test_util::log_file_for_test;
+use dropshot::test_util::read_config;
+use dropshot::test_util::read_json;
+use dropshot::ApiDescription;
+use dropshot::ConfigDropshot;
+use dropshot::ConfigUnixSocket;
+use dropshot::ConnectionPeer;
+use dropshot::HttpError;
+use dropshot::HttpResponseOk;
+use dropshot::HttpServer;
+use dropshot::RequestContext;
+use http::StatusCode;
+use hyper::Body;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[macro_use]
+extern crate slog;
+
+mod common;
+
+#[derive(Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+struct Peer {
+    tcp: Option<String>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    pid: Option<i32>,
+}
+
+#[endpoint {
+    method = GET,
+    path = "/peer",
+}]
+async fn unix_peer(
+    rqctx: Arc<RequestContext<()>>,
+) -> Result<HttpResponseOk<Peer>, HttpError> {
+    let peer = match &rqctx.connection.peer {
+        ConnectionPeer::Tcp(addr) => Peer {
+            tcp: Some(addr.to_string()),
+            uid: None,
+            gid: None,
+            pid: None,
+        },
+        ConnectionPeer::Unix(creds) => Peer {
+            tcp: None,
+            uid: Some(creds.uid),
+            gid: Some(creds.gid),
+            pid: creds.pid,
+        },
+        ConnectionPeer::Unknown => Peer {
+            tcp: None,
+            uid: None,
+            gid: None,
+            pid: None,
+        },
+    };
+    Ok(HttpResponseOk(peer))
+}
+
+fn unix_api() -> ApiDescription<()> {
+    let mut api = ApiDescription::new();
+    api.register(unix_peer).unwrap();
+    api
+}
+
+fn socket_path_for_test(test_name: &str) -> PathBuf {
+    log_file_for_test(test_name).with_extension("sock")
+}
+
+/**
+ * Make a GET request for `uri` over a new connection to the Unix domain socket
+ * at `path`.
+ */
+async fn unix_get(path: &Path, uri: &str) -> Response<Body> {
+    let stream = tokio::net::UnixStream::connect(path).await.unwrap();
+    let (mut sender, connection) =
+        hyper::client::conn::handshake(stream).await.unwrap();
+    tokio::spawn(connection);
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(uri)
+        .header(http::header::HOST, "localhost")
+        .body(Body::empty())
+        .unwrap();
+    sender.send_request(request).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_unix_socket() {
+    let path = socket_path_for_test("unix_socket");
+    let config = ConfigDropshot {
+        unix_socket: Some(ConfigUnixSocket {
+            path: path.clone(),
+            mode: Some(0o600),
+            remove_stale: false,
+        }),
+        ..Default::default()
+    };
+    let testctx =
+        common::test_setup_with_config("unix_socket", unix_api(), (), &config);
+
+    let metadata = fs::metadata(&path).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+
+    /*
+     * A request over the Unix socket should report our own credentials.
+     */
+    let mut response = unix_get(&path, "/peer").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let peer: Peer = read_json(&mut response).await;
+    assert_eq!(peer, Peer {
+        tcp: None,
+        uid: Some(unsafe { libc::geteuid() }),
+        gid: Some(unsafe { libc::getegid() }),
+        pid: peer.pid,
+    });
+    if let Some(pid) = peer.pid {
+        assert_eq!(pid as u32, std::process::id());
+    }
+
+    /*
+     * The same server should still be serving requests over TCP, which report
+     * the remote address instead.
+     */
+    let mut response = testctx
+        .client_testctx
+        .make_request_no_body(Method::GET, "/peer", StatusCode::OK)
+        .await
+        .unwrap();
+    let peer: Peer = read_json(&mut response).await;
+    assert!(peer.tcp.unwrap().starts_with("127.0.0.1:"));
+    assert_eq!(peer.uid, None);
+
+    /* The socket should be removed when the server shuts down. */
+    testctx.teardown().await;
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn test_unix_socket_stale() {
+    let path = socket_path_for_test("unix_socket_stale");
+    let logctx = common::test_logctx("unix_socket_stale");
+    let log = logctx.log.new(o!());
+    let mut config = ConfigDropshot {
+        unix_socket: Some(ConfigUnixSocket {
+            path: path.clone(),
+            mode: None,
+            remove_stale: false,
+        }),
+        ..Default::default()
+    };
+
+    /*
+     * Leave behind a socket that nobody's listening on, as a server that
+     * crashed would.  Without `remove_stale`, we should fail to start.
+     */
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+    let error = HttpServer::new(&config, unix_api(), (), &log).err().unwrap();
+    assert!(error.to_string().starts_with("binding Unix socket"));
+
+    /* With `remove_stale`, we should clean it up and start normally. */
+    config.unix_socket.as_mut().unwrap().remove_stale = true;
+    let mut server = HttpServer::new(&config, unix_api(), (), &log).unwrap();
+    let server_task = server.run();
+    let response = unix_get(&path, "/peer").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    /* A socket that's in use should not be removed. */
+    let error = HttpServer::new(&config, unix_api(), (), &log).err().unwrap();
+    assert!(error.to_string().ends_with("socket is in use by another process"));
+    let response = unix_get(&path, "/peer").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    server.close();
+    server_task.await.unwrap().unwrap();
+    assert!(!path.exists());
+
+    /* A file that's not a socket should not be removed. */
+    fs::write(&path, "not a socket").unwrap();
+    let error = HttpServer::new(&config, unix_api(), (), &log).err().unwrap();
+    assert!(error.to_string().ends_with("file exists and is not a socket"));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "not a socket");
+    fs::remove_file(&path).unwrap();
+
+    logctx.cleanup_successful();
+}
+
+#[test]
+fn test_unix_socket_config() {
+    let config = read_config::<ConfigDropshot>(
+        "unix_socket_config",
+        r#"
+            bind_address = "127.0.0.1:0"
+            [unix_socket]
+            path = "/var/run/server.sock"
+            mode = 0o660
+            remove_stale = true
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.unix_socket,
+        Some(ConfigUnixSocket {
+            path: PathBuf::from("/var/run/server.sock"),
+            mode: Some(0o660),
+            remove_stale: true,
+        })
+    );
+
+    let config = read_config::<ConfigDropshot>(
+        "unix_socket_config_defaults",
+        r#"
+            [unix_socket]
+            path = "/var/run/server.sock"
+        "#,
+    )
+    .unwrap();
+    let unix_socket = config.unix_socket.unwrap();
+    assert_eq!(unix_socket.mode, None);
+    assert!(!unix_socket.remove_stale);
+}
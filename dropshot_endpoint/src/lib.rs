@@ -26,6 +26,8 @@ use syn::ItemFn;
 enum MethodType {
     DELETE,
     GET,
+    HEAD,
+    OPTIONS,
     PATCH,
     POST,
     PUT,
@@ -36,6 +38,8 @@ impl MethodType {
         match self {
             MethodType::DELETE => "DELETE",
             MethodType::GET => "GET",
+            MethodType::HEAD => "HEAD",
+            MethodType::OPTIONS => "OPTIONS",
             MethodType::PATCH => "PATCH",
             MethodType::POST => "POST",
             MethodType::PUT => "PUT",
@@ -48,11 +52,25 @@ struct Metadata {
     method: MethodType,
     path: String,
     tags: Option<Vec<String>>,
+    timeout_ms: Option<u64>,
+    concurrency_limit: Option<usize>,
+    request_body_max_bytes: Option<usize>,
+    allow_encoded_slashes: Option<bool>,
     _dropshot_crate: Option<String>,
 }
 
 const DROPSHOT: &str = "dropshot";
 
+/// The maximum number of extractor arguments that an endpoint handler function
+/// may take (after its RequestContext).  This must match the number of
+/// implementations of `HttpHandlerFunc` provided by dropshot.
+const MAX_EXTRACTORS: usize = 16;
+
+/// The largest `concurrency_limit` that an endpoint may have.  Limits are
+/// enforced with a semaphore, and this is the most permits that one can have
+/// (tokio's `Semaphore::MAX_PERMITS`, which we can't refer to from here).
+const MAX_CONCURRENCY_LIMIT: usize = usize::MAX >> 3;
+
 /// This attribute transforms a handler function into a Dropshot endpoint
 /// suitable to be used as a parameter to
 /// [`ApiDescription::register()`](../dropshot/struct.ApiDescription.html#method.register).
@@ -62,11 +80,15 @@ const DROPSHOT: &str = "dropshot";
 /// ```ignore
 /// #[endpoint {
 ///     // Required fields
-///     method = { DELETE | GET | PATCH | POST | PUT },
+///     method = { DELETE | GET | HEAD | OPTIONS | PATCH | POST | PUT },
 ///     path = "/path/name/with/{named}/{variables}",
 ///
 ///     // Optional fields
 ///     tags = [ "all", "your", "OpenAPI", "tags" ],
+///     timeout_ms = 30000,
+///     concurrency_limit = 16,
+///     request_body_max_bytes = 1048576,
+///     allow_encoded_slashes = true,
 /// }]
 /// ```
 ///
@@ -91,6 +113,18 @@ fn do_endpoint(
 ) -> Result<TokenStream, Error> {
     let metadata = from_tokenstream::<Metadata>(&attr)?;
 
+    if let Some(limit) = metadata.concurrency_limit {
+        if limit == 0 || limit > MAX_CONCURRENCY_LIMIT {
+            return Err(Error::new_spanned(
+                &attr,
+                format!(
+                    "concurrency_limit must be between 1 and {}",
+                    MAX_CONCURRENCY_LIMIT
+                ),
+            ));
+        }
+    }
+
     let method = metadata.method.as_str();
     let path = metadata.path;
 
@@ -103,6 +137,21 @@ fn do_endpoint(
         ));
     }
 
+    // Dropshot only implements HttpHandlerFunc for so many extractors.  Beyond
+    // that, the errors would be about unsatisfied trait bounds, which doesn't
+    // say much about what went wrong.
+    if let Some(arg) = ast.sig.inputs.iter().nth(MAX_EXTRACTORS + 1) {
+        return Err(Error::new_spanned(
+            arg,
+            format!(
+                "endpoint handler functions may take at most {} extractor \
+                 arguments after the RequestContext (found {})",
+                MAX_EXTRACTORS,
+                ast.sig.inputs.len() - 1,
+            ),
+        ));
+    }
+
     let name = &ast.sig.ident;
     let name_str = name.to_string();
     let method_ident = format_ident!("{}", method);
@@ -135,17 +184,40 @@ fn do_endpoint(
         })
         .unwrap_or_default();
 
+    let timeout = metadata.timeout_ms.map(|timeout_ms| {
+        quote! {
+            .timeout(std::time::Duration::from_millis(#timeout_ms))
+        }
+    });
+
+    let concurrency_limit = metadata.concurrency_limit.map(|limit| {
+        quote! {
+            .concurrency_limit(#limit)
+        }
+    });
+
+    let request_body_max_bytes = metadata.request_body_max_bytes.map(|limit| {
+        quote! {
+            .request_body_max_bytes(#limit)
+        }
+    });
+
+    let allow_encoded_slashes = match metadata.allow_encoded_slashes {
+        Some(true) => Some(quote! {
+            .allow_encoded_slashes()
+        }),
+        _ => None,
+    };
+
     let dropshot = get_crate(metadata._dropshot_crate);
 
     // When the user attaches this proc macro to a function with the wrong type
     // signature, the resulting errors can be deeply inscrutable. To attempt to
     // make failures easier to understand, we inject code that asserts the types
-    // of the various parameters. For the first parameter of type
-    // Arc<RequestContext>, we turn that type into a trait and then construct
-    // a dummy function that requires a type match. It will fail for anything
-    // of the wrong type. Subsequent parameters are simpler: we simply need to
-    // call a dummy function that requires a type that satisfies the trait
-    // Extractor.
+    // of the various parameters. We do this by calling a dummy function that
+    // requires a type that satisfies a particular trait: for the first
+    // parameter, that's RequestContextArgument (implemented only by
+    // Arc<RequestContext<T>>); for subsequent parameters, it's Extractor.
     let mut checks = ast
         .sig
         .inputs
@@ -153,7 +225,7 @@ fn do_endpoint(
         .enumerate()
         .map(|(i, arg)| {
             let req = if i == 0 {
-                quote! { std::sync::Arc<#dropshot::RequestContext> }
+                quote! { #dropshot::RequestContextArgument }
             } else {
                 quote! { #dropshot::Extractor }
             };
@@ -170,15 +242,9 @@ fn do_endpoint(
                     if i == 0 {
                         quote_spanned! { span=>
                             const _: fn() = ||{
-                                trait TypeEq {
-                                    type This: ?Sized;
-                                }
-                                impl<T: ?Sized> TypeEq for T {
-                                    type This = Self;
-                                }
                                 fn need_arc_requestcontext<T>()
                                 where
-                                    T: ?Sized + TypeEq<This = #req>,
+                                    T: ?Sized + #req,
                                 {
                                 }
                                 need_arc_requestcontext::<#ty>();
@@ -207,13 +273,25 @@ fn do_endpoint(
             Error::new_spanned(
                 (&ast.sig).into_token_stream(),
                 "incompatible function signature; expected async fn \
-                 (Arc<RequestContext>(, Extractor)*) -> Result<HttpResponse, \
-                 HttpError>",
+                 (Arc<RequestContext<T>>(, Extractor)*) -> \
+                 Result<HttpResponse, HttpError>",
             )
             .to_compile_error(),
         );
     }
 
+    // The type of the server context is determined by the first argument, which
+    // ties the endpoint to ApiDescriptions (and servers) having that context
+    // type.  If the first argument is missing or malformed, we've already
+    // emitted an error above, so any type will do here.
+    let context = match ast.sig.inputs.first() {
+        Some(syn::FnArg::Typed(pat)) => {
+            let ty = pat.ty.as_ref();
+            quote! { <#ty as #dropshot::RequestContextArgument>::Context }
+        }
+        _ => quote! { () },
+    };
+
     // The final TokenStream returned will have a few components that reference
     // `#name`, the name of the method to which this macro was applied...
     let stream = quote! {
@@ -230,7 +308,7 @@ fn do_endpoint(
 
         // ... an impl of `From<#name>` for ApiEndpoint that allows the constant
         // `#name` to be passed into `ApiDescription::register()`
-        impl From<#name> for #dropshot::ApiEndpoint {
+        impl From<#name> for #dropshot::ApiEndpoint<#context> {
             fn from(_: #name) -> Self {
                 #item
 
@@ -242,6 +320,10 @@ fn do_endpoint(
                 )
                 #description
                 #(#tags)*
+                #timeout
+                #concurrency_limit
+                #request_body_max_bytes
+                #allow_encoded_slashes
             }
         }
     };
@@ -318,27 +400,18 @@ mod tests {
             }
             .into(),
             quote! {
-                pub async fn handler_xyz(_rqctx: Arc<RequestContext>) {}
+                pub async fn handler_xyz(_rqctx: Arc<RequestContext<()>>) {}
             }
             .into(),
         );
-        let full = quote! {
-            std::sync::Arc< dropshot::RequestContext>
-        };
-        let short = quote! {
-            Arc<RequestContext>
+        let short: syn::Type = syn::parse_quote! {
+            Arc<RequestContext<()>>
         };
         let expected = quote! {
             const _: fn() = || {
-                trait TypeEq {
-                    type This: ?Sized;
-                }
-                impl<T: ?Sized> TypeEq for T {
-                    type This = Self;
-                }
                 fn need_arc_requestcontext<T>()
                 where
-                    T: ?Sized + TypeEq<This = #full>,
+                    T: ?Sized + dropshot::RequestContextArgument,
                 {
                 }
                 need_arc_requestcontext::<#short>();
@@ -352,9 +425,13 @@ mod tests {
             #[doc = "API Endpoint: handler_xyz"]
             pub const handler_xyz: handler_xyz = handler_xyz {};
 
-            impl From<handler_xyz> for dropshot::ApiEndpoint {
+            impl From<handler_xyz>
+                for dropshot::ApiEndpoint<
+                    <#short as dropshot::RequestContextArgument>::Context
+                >
+            {
                 fn from(_: handler_xyz) -> Self {
-                    pub async fn handler_xyz(_rqctx: Arc<RequestContext>) {}
+                    pub async fn handler_xyz(_rqctx: Arc<RequestContext<()>>) {}
                     dropshot::ApiEndpoint::new(
                         "handler_xyz".to_string(),
                         handler_xyz,
@@ -377,30 +454,21 @@ mod tests {
             }
             .into(),
             quote! {
-                async fn handler_xyz(_rqctx: Arc<RequestContext>, q: Query<Q>) {}
+                async fn handler_xyz(_rqctx: Arc<RequestContext<()>>, q: Query<Q>) {}
             }
             .into(),
         );
-        let full = quote! {
-            std::sync::Arc< dropshot::RequestContext>
-        };
-        let short = quote! {
-            Arc<RequestContext>
+        let short: syn::Type = syn::parse_quote! {
+            Arc<RequestContext<()>>
         };
         let query = quote! {
             Query<Q>
         };
         let expected = quote! {
             const _: fn() = || {
-                trait TypeEq {
-                    type This: ?Sized;
-                }
-                impl<T: ?Sized> TypeEq for T {
-                    type This = Self;
-                }
                 fn need_arc_requestcontext<T>()
                 where
-                    T: ?Sized + TypeEq<This = #full>,
+                    T: ?Sized + dropshot::RequestContextArgument,
                 {
                 }
                 need_arc_requestcontext::<#short>();
@@ -423,9 +491,13 @@ mod tests {
             #[doc = "API Endpoint: handler_xyz"]
             const handler_xyz: handler_xyz = handler_xyz {};
 
-            impl From<handler_xyz> for dropshot::ApiEndpoint {
+            impl From<handler_xyz>
+                for dropshot::ApiEndpoint<
+                    <#short as dropshot::RequestContextArgument>::Context
+                >
+            {
                 fn from(_: handler_xyz) -> Self {
-                    async fn handler_xyz(_rqctx: Arc<RequestContext>, q: Query<Q>) {}
+                    async fn handler_xyz(_rqctx: Arc<RequestContext<()>>, q: Query<Q>) {}
                     dropshot::ApiEndpoint::new(
                         "handler_xyz".to_string(),
                         handler_xyz,
@@ -448,30 +520,21 @@ mod tests {
             }
             .into(),
             quote! {
-                pub(crate) async fn handler_xyz(_rqctx: Arc<RequestContext>, q: Query<Q>) {}
+                pub(crate) async fn handler_xyz(_rqctx: Arc<RequestContext<()>>, q: Query<Q>) {}
             }
             .into(),
         );
-        let full = quote! {
-            std::sync::Arc< dropshot::RequestContext>
-        };
-        let short = quote! {
-            Arc<RequestContext>
+        let short: syn::Type = syn::parse_quote! {
+            Arc<RequestContext<()>>
         };
         let query = quote! {
             Query<Q>
         };
         let expected = quote! {
             const _: fn() = || {
-                trait TypeEq {
-                    type This: ?Sized;
-                }
-                impl<T: ?Sized> TypeEq for T {
-                    type This = Self;
-                }
                 fn need_arc_requestcontext<T>()
                 where
-                    T: ?Sized + TypeEq<This = #full>,
+                    T: ?Sized + dropshot::RequestContextArgument,
                 {
                 }
                 need_arc_requestcontext::<#short>();
@@ -494,9 +557,13 @@ mod tests {
             #[doc = "API Endpoint: handler_xyz"]
             pub(crate) const handler_xyz: handler_xyz = handler_xyz {};
 
-            impl From<handler_xyz> for dropshot::ApiEndpoint {
+            impl From<handler_xyz>
+                for dropshot::ApiEndpoint<
+                    <#short as dropshot::RequestContextArgument>::Context
+                >
+            {
                 fn from(_: handler_xyz) -> Self {
-                    pub(crate) async fn handler_xyz(_rqctx: Arc<RequestContext>, q: Query<Q>) {}
+                    pub(crate) async fn handler_xyz(_rqctx: Arc<RequestContext<()>>, q: Query<Q>) {}
                     dropshot::ApiEndpoint::new(
                         "handler_xyz".to_string(),
                         handler_xyz,
@@ -520,27 +587,131 @@ mod tests {
             }
             .into(),
             quote! {
-                async fn handler_xyz(_rqctx: Arc<RequestContext>) {}
+                async fn handler_xyz(_rqctx: Arc<RequestContext<()>>) {}
             }
             .into(),
         );
-        let full = quote! {
-            std::sync::Arc< dropshot::RequestContext>
+        let short: syn::Type = syn::parse_quote! {
+            Arc<RequestContext<()>>
         };
-        let short = quote! {
-            Arc<RequestContext>
+        let expected = quote! {
+            const _: fn() = || {
+                fn need_arc_requestcontext<T>()
+                where
+                    T: ?Sized + dropshot::RequestContextArgument,
+                {
+                }
+                need_arc_requestcontext::<#short>();
+            };
+
+            #[allow(non_camel_case_types, missing_docs)]
+            #[doc = "API Endpoint: handler_xyz"]
+            struct handler_xyz {}
+            #[allow(non_upper_case_globals, missing_docs)]
+            #[doc = "API Endpoint: handler_xyz"]
+            const handler_xyz: handler_xyz = handler_xyz {};
+            impl From<handler_xyz>
+                for dropshot::ApiEndpoint<
+                    <#short as dropshot::RequestContextArgument>::Context
+                >
+            {
+                fn from(_: handler_xyz) -> Self {
+                    async fn handler_xyz(_rqctx: Arc<RequestContext<()>>) {}
+                    dropshot::ApiEndpoint::new(
+                        "handler_xyz".to_string(),
+                        handler_xyz,
+                        dropshot::Method::GET,
+                        "/a/b/c",
+                    )
+                    .tag("stuff")
+                    .tag("things")
+                }
+            }
+        };
+
+        assert_eq!(expected.to_string(), ret.unwrap().to_string());
+    }
+
+    #[test]
+    fn test_endpoint_with_limits() {
+        let ret = do_endpoint(
+            quote! {
+                method = GET,
+                path = "/a/b/c",
+                timeout_ms = 1500,
+                concurrency_limit = 4,
+                request_body_max_bytes = 65536,
+            }
+            .into(),
+            quote! {
+                async fn handler_xyz(_rqctx: Arc<RequestContext<()>>) {}
+            }
+            .into(),
+        );
+        let short: syn::Type = syn::parse_quote! {
+            Arc<RequestContext<()>>
         };
         let expected = quote! {
             const _: fn() = || {
-                trait TypeEq {
-                    type This: ?Sized;
+                fn need_arc_requestcontext<T>()
+                where
+                    T: ?Sized + dropshot::RequestContextArgument,
+                {
                 }
-                impl<T: ?Sized> TypeEq for T {
-                    type This = Self;
+                need_arc_requestcontext::<#short>();
+            };
+
+            #[allow(non_camel_case_types, missing_docs)]
+            #[doc = "API Endpoint: handler_xyz"]
+            struct handler_xyz {}
+            #[allow(non_upper_case_globals, missing_docs)]
+            #[doc = "API Endpoint: handler_xyz"]
+            const handler_xyz: handler_xyz = handler_xyz {};
+            impl From<handler_xyz>
+                for dropshot::ApiEndpoint<
+                    <#short as dropshot::RequestContextArgument>::Context
+                >
+            {
+                fn from(_: handler_xyz) -> Self {
+                    async fn handler_xyz(_rqctx: Arc<RequestContext<()>>) {}
+                    dropshot::ApiEndpoint::new(
+                        "handler_xyz".to_string(),
+                        handler_xyz,
+                        dropshot::Method::GET,
+                        "/a/b/c",
+                    )
+                    .timeout(std::time::Duration::from_millis(1500u64))
+                    .concurrency_limit(4usize)
+                    .request_body_max_bytes(65536usize)
                 }
+            }
+        };
+
+        assert_eq!(expected.to_string(), ret.unwrap().to_string());
+    }
+
+    #[test]
+    fn test_endpoint_allow_encoded_slashes() {
+        let ret = do_endpoint(
+            quote! {
+                method = GET,
+                path = "/a/b/c",
+                allow_encoded_slashes = true,
+            }
+            .into(),
+            quote! {
+                async fn handler_xyz(_rqctx: Arc<RequestContext<()>>) {}
+            }
+            .into(),
+        );
+        let short: syn::Type = syn::parse_quote! {
+            Arc<RequestContext<()>>
+        };
+        let expected = quote! {
+            const _: fn() = || {
                 fn need_arc_requestcontext<T>()
                 where
-                    T: ?Sized + TypeEq<This = #full>,
+                    T: ?Sized + dropshot::RequestContextArgument,
                 {
                 }
                 need_arc_requestcontext::<#short>();
@@ -552,17 +723,20 @@ mod tests {
             #[allow(non_upper_case_globals, missing_docs)]
             #[doc = "API Endpoint: handler_xyz"]
             const handler_xyz: handler_xyz = handler_xyz {};
-            impl From<handler_xyz> for dropshot::ApiEndpoint {
+            impl From<handler_xyz>
+                for dropshot::ApiEndpoint<
+                    <#short as dropshot::RequestContextArgument>::Context
+                >
+            {
                 fn from(_: handler_xyz) -> Self {
-                    async fn handler_xyz(_rqctx: Arc<RequestContext>) {}
+                    async fn handler_xyz(_rqctx: Arc<RequestContext<()>>) {}
                     dropshot::ApiEndpoint::new(
                         "handler_xyz".to_string(),
                         handler_xyz,
                         dropshot::Method::GET,
                         "/a/b/c",
                     )
-                    .tag("stuff")
-                    .tag("things")
+                    .allow_encoded_slashes()
                 }
             }
         };
@@ -580,27 +754,18 @@ mod tests {
             .into(),
             quote! {
                 /** handle "xyz" requests */
-                async fn handler_xyz(_rqctx: Arc<RequestContext>) {}
+                async fn handler_xyz(_rqctx: Arc<RequestContext<()>>) {}
             }
             .into(),
         );
-        let full = quote! {
-            std::sync::Arc< dropshot::RequestContext>
-        };
-        let short = quote! {
-            Arc<RequestContext>
+        let short: syn::Type = syn::parse_quote! {
+            Arc<RequestContext<()>>
         };
         let expected = quote! {
             const _: fn() = || {
-                trait TypeEq {
-                    type This: ?Sized;
-                }
-                impl<T: ?Sized> TypeEq for T {
-                    type This = Self;
-                }
                 fn need_arc_requestcontext<T>()
                 where
-                    T: ?Sized + TypeEq<This = #full>,
+                    T: ?Sized + dropshot::RequestContextArgument,
                 {
                 }
                 need_arc_requestcontext::<#short>();
@@ -612,10 +777,14 @@ mod tests {
             #[allow(non_upper_case_globals, missing_docs)]
             #[doc = "API Endpoint: handle \"xyz\" requests"]
             const handler_xyz: handler_xyz = handler_xyz {};
-            impl From<handler_xyz> for dropshot::ApiEndpoint {
+            impl From<handler_xyz>
+                for dropshot::ApiEndpoint<
+                    <#short as dropshot::RequestContextArgument>::Context
+                >
+            {
                 fn from(_: handler_xyz) -> Self {
                     #[doc = r#" handle "xyz" requests "#]
-                    async fn handler_xyz(_rqctx: Arc<RequestContext>) {}
+                    async fn handler_xyz(_rqctx: Arc<RequestContext<()>>) {}
                     dropshot::ApiEndpoint::new(
                         "handler_xyz".to_string(),
                         handler_xyz,
@@ -693,7 +862,7 @@ mod tests {
             }
             .into(),
             quote! {
-                fn handler_xyz(_rqctx: Arc<RequestContext>) {}
+                fn handler_xyz(_rqctx: Arc<RequestContext<()>>) {}
             }
             .into(),
         );
@@ -701,4 +870,29 @@ mod tests {
         let msg = format!("{}", ret.err().unwrap());
         assert_eq!("endpoint handler functions must be async", msg);
     }
+
+    #[test]
+    fn test_endpoint_bad_concurrency_limit() {
+        let ret = do_endpoint(
+            quote! {
+                method = GET,
+                path = "/a/b/c",
+                concurrency_limit = 0,
+            }
+            .into(),
+            quote! {
+                async fn handler_xyz(_rqctx: Arc<RequestContext<()>>) {}
+            }
+            .into(),
+        );
+
+        let msg = format!("{}", ret.err().unwrap());
+        assert_eq!(
+            format!(
+                "concurrency_limit must be between 1 and {}",
+                usize::MAX >> 3
+            ),
+            msg
+        );
+    }
 }